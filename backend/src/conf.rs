@@ -33,6 +33,7 @@ pub struct EnvConf {
     pub port: u16,
     pub host: String,
     pub log: Log,
+    pub shutdown: Shutdown,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -40,6 +41,14 @@ pub struct Log {
     pub pretty: bool,
 }
 
+#[derive(Deserialize, Debug, Clone)]
+pub struct Shutdown {
+    // running games may go on this long after SIGTERM,
+    // keep below terminationGracePeriodSeconds of the deployment
+    #[serde(deserialize_with = "de_num")]
+    pub grace_secs: u64,
+}
+
 impl EnvConf {
     pub fn derive(env: Env) -> Self {
         fn join_filename(conf_dir: &std::path::PathBuf, filename: &str) -> String {
@@ -83,6 +92,7 @@ impl EnvConf {
             port: 0,
             host: "127.0.0.1".into(),
             log: Log { pretty: false },
+            shutdown: Shutdown { grace_secs: 1 },
         }
     }
 }
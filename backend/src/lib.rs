@@ -5,4 +5,5 @@ pub mod trace;
 
 mod mp;
 mod routes;
+mod shutdown;
 mod static_routes;
@@ -0,0 +1,101 @@
+// Open connections by con
+//
+// Con is expected to be unique, a new connection with a taken con
+// supersedes the old one, which is closed.
+
+use super::outbox::Outbox;
+use super::{Con, ServerMsg};
+use interfacing::snake::CloseReason;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Clone, Default)]
+pub struct Connections(Arc<Mutex<HashMap<Con, Connection>>>);
+
+struct Connection {
+    outbox: Outbox,
+    rtt: Option<Duration>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ConnectionMetrics {
+    pub open: usize,
+    // over connections which answered a ping
+    pub average_rtt_ms: Option<f64>,
+    pub max_rtt_ms: Option<f64>,
+}
+
+impl Connections {
+    // true when an older connection was superseded
+    pub fn open(&self, con: Con, outbox: Outbox) -> bool {
+        let old = self
+            .0
+            .lock()
+            .unwrap()
+            .insert(con, Connection { outbox, rtt: None });
+
+        match old {
+            Some(old) => {
+                tracing::info!(con, "connection superseded");
+                old.outbox.close(CloseReason::Superseded);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // true when it was not superseded, and the con is free to clean up
+    pub fn close(&self, con: Con, outbox: &Outbox) -> bool {
+        let mut connections = self.0.lock().unwrap();
+        match connections.get(&con) {
+            Some(current) if current.outbox.same(outbox) => {
+                connections.remove(&con);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // to every open connection, ones which cannot take it get closed
+    pub fn broadcast(&self, msg: ServerMsg) {
+        for connection in self.0.lock().unwrap().values() {
+            connection.outbox.send(msg.clone()).unwrap_or(());
+        }
+    }
+
+    pub fn close_all(&self, reason: CloseReason) {
+        for connection in self.0.lock().unwrap().values() {
+            connection.outbox.close(reason);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.lock().unwrap().is_empty()
+    }
+
+    pub fn set_rtt(&self, con: Con, outbox: &Outbox, rtt: Duration) {
+        if let Some(connection) = self.0.lock().unwrap().get_mut(&con) {
+            if connection.outbox.same(outbox) {
+                connection.rtt = Some(rtt);
+            }
+        }
+    }
+
+    pub fn metrics(&self) -> ConnectionMetrics {
+        let connections = self.0.lock().unwrap();
+        let rtts = connections
+            .values()
+            .filter_map(|connection| connection.rtt)
+            .map(|rtt| rtt.as_secs_f64() * 1000.)
+            .collect::<Vec<_>>();
+
+        ConnectionMetrics {
+            open: connections.len(),
+            average_rtt_ms: (!rtts.is_empty())
+                .then(|| rtts.iter().sum::<f64>() / rtts.len() as f64),
+            max_rtt_ms: rtts.into_iter().reduce(f64::max),
+        }
+    }
+}
@@ -0,0 +1,78 @@
+// Liveness of a connection, pinged by the server and answered by the client
+//
+// Browsers answer pings on their own, so every client keeps the connection alive.
+
+use std::time::Duration;
+use tokio::time::Instant;
+
+pub const PING_INTERVAL: Duration = Duration::from_secs(10);
+// without anything received from the client, pongs included
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct Heartbeat {
+    last_seen: Instant,
+    // of the last ping sent
+    ping: u64,
+    sent_at: Option<Instant>,
+}
+
+impl Heartbeat {
+    pub fn new() -> Self {
+        Self {
+            last_seen: Instant::now(),
+            ping: 0,
+            sent_at: None,
+        }
+    }
+
+    // anything received from the client
+    pub fn seen(&mut self) {
+        self.last_seen = Instant::now();
+    }
+
+    pub fn idle(&self) -> bool {
+        self.last_seen.elapsed() > IDLE_TIMEOUT
+    }
+
+    // payload of the next ping
+    pub fn ping(&mut self) -> Vec<u8> {
+        self.ping += 1;
+        self.sent_at = Some(Instant::now());
+        self.ping.to_be_bytes().to_vec()
+    }
+
+    // round trip of the last ping, none when the pong answers an older one
+    pub fn pong(&mut self, payload: &[u8]) -> Option<Duration> {
+        let ping = u64::from_be_bytes(payload.try_into().ok()?);
+        if ping != self.ping {
+            return None;
+        }
+
+        Some(self.sent_at.take()?.elapsed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn round_trip_and_idle() {
+        let mut heartbeat = Heartbeat::new();
+
+        let stale = heartbeat.ping();
+        let payload = heartbeat.ping();
+        tokio::time::advance(Duration::from_millis(40)).await;
+        assert_eq!(heartbeat.pong(&stale), None);
+        assert_eq!(heartbeat.pong(b"junk"), None);
+        assert_eq!(heartbeat.pong(&payload), Some(Duration::from_millis(40)));
+        // answered twice
+        assert_eq!(heartbeat.pong(&payload), None);
+
+        assert!(!heartbeat.idle());
+        tokio::time::advance(IDLE_TIMEOUT).await;
+        assert!(heartbeat.idle());
+        heartbeat.seen();
+        assert!(!heartbeat.idle());
+    }
+}
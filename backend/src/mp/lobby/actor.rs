@@ -0,0 +1,290 @@
+// Lobby task owning its state, driven by commands from its mailbox
+//
+// Commands are handled one at a time, so a lobby needs no locks. Requests
+// are answered through oneshot channels, the task itself never waits on anyone.
+
+use super::lobby::{Lobby, LobbyMsg};
+use super::scheduler::Scheduler;
+use crate::mp::{domain, Ch, Con, MsgId, UserName};
+use interfacing::snake::{lobby_state::LobbySettings, LobbyState, TeamId};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+
+type Reply<T> = oneshot::Sender<T>;
+
+// commands waiting for the lobby, senders wait when it is full
+const MAILBOX: usize = 256;
+
+pub enum LobbyCmd {
+    // state for the newcomer on success, the others get it broadcasted
+    Join {
+        con: Con,
+        ch: Ch,
+        un: UserName,
+        reply: Reply<Result<LobbyState, String>>,
+    },
+    State {
+        con: Con,
+        reply: Reply<LobbyState>,
+    },
+    // replies whether the lobby was abandoned and can be removed
+    Leave {
+        con: Con,
+        reply: Reply<bool>,
+    },
+    // on success the state is broadcasted, pinned to the id for the sender
+    VoteStart {
+        con: Con,
+        value: bool,
+        id: MsgId,
+        reply: Reply<Result<(), String>>,
+    },
+    SetSettings {
+        con: Con,
+        settings: LobbySettings,
+        id: MsgId,
+        reply: Reply<Result<(), String>>,
+    },
+    PickTeam {
+        con: Con,
+        team: TeamId,
+        id: MsgId,
+        reply: Reply<Result<(), String>>,
+    },
+    Respawn {
+        con: Con,
+        id: MsgId,
+        reply: Reply<Result<(), String>>,
+    },
+    SetDirection {
+        con: Con,
+        direction: domain::Direction,
+        tick: u32,
+    },
+    SetBoost {
+        con: Con,
+        boosting: bool,
+    },
+    InGame {
+        reply: Reply<bool>,
+    },
+    // server is going down
+    Drain,
+    // from the scheduler, with the instant it was due at
+    Timer {
+        msg: LobbyMsg,
+        deadline: Instant,
+        period: Duration,
+    },
+    // terminates the game and the task
+    Stop,
+}
+
+// address of a running lobby task, requests fail once it is stopped
+#[derive(Clone)]
+pub struct LobbyHandle(mpsc::Sender<LobbyCmd>);
+
+impl LobbyHandle {
+    pub fn spawn(lobby: Lobby, scheduler: Scheduler) -> Self {
+        let (s, r) = mpsc::channel(MAILBOX);
+        let handle = Self(s);
+        tokio::spawn(run(lobby.set_timers(scheduler, handle.clone()), r));
+        handle
+    }
+
+    pub async fn send(&self, cmd: LobbyCmd) {
+        // stopped lobby ignores everything
+        self.0.send(cmd).await.unwrap_or(());
+    }
+
+    // same lobby task, names are reused by later lobbies
+    pub fn same(&self, other: &LobbyHandle) -> bool {
+        self.0.same_channel(&other.0)
+    }
+
+    // for timers, which do not wait for a busy lobby
+    pub fn try_send(&self, cmd: LobbyCmd) -> Result<(), mpsc::error::TrySendError<LobbyCmd>> {
+        self.0.try_send(cmd)
+    }
+
+    async fn request<T>(&self, cmd: impl FnOnce(Reply<T>) -> LobbyCmd) -> Result<T, String> {
+        let (reply, response) = oneshot::channel();
+        self.0
+            .send(cmd(reply))
+            .await
+            .map_err(|_| "lobby does not exist".to_owned())?;
+        response.await.map_err(|_| "lobby does not exist".into())
+    }
+
+    pub async fn join(&self, con: Con, ch: Ch, un: UserName) -> Result<LobbyState, String> {
+        self.request(|reply| LobbyCmd::Join { con, ch, un, reply })
+            .await?
+    }
+
+    pub async fn state(&self, con: Con) -> Result<LobbyState, String> {
+        self.request(|reply| LobbyCmd::State { con, reply }).await
+    }
+
+    // abandoned when the lobby does not exist anymore
+    pub async fn leave(&self, con: Con) -> bool {
+        self.request(|reply| LobbyCmd::Leave { con, reply })
+            .await
+            .unwrap_or(true)
+    }
+
+    pub async fn vote_start(&self, con: Con, value: bool, id: MsgId) -> Result<(), String> {
+        self.request(|reply| LobbyCmd::VoteStart {
+            con,
+            value,
+            id,
+            reply,
+        })
+        .await?
+    }
+
+    pub async fn set_settings(
+        &self,
+        con: Con,
+        settings: LobbySettings,
+        id: MsgId,
+    ) -> Result<(), String> {
+        self.request(|reply| LobbyCmd::SetSettings {
+            con,
+            settings,
+            id,
+            reply,
+        })
+        .await?
+    }
+
+    pub async fn pick_team(&self, con: Con, team: TeamId, id: MsgId) -> Result<(), String> {
+        self.request(|reply| LobbyCmd::PickTeam {
+            con,
+            team,
+            id,
+            reply,
+        })
+        .await?
+    }
+
+    // false when the lobby does not exist anymore
+    pub async fn in_game(&self) -> bool {
+        self.request(|reply| LobbyCmd::InGame { reply })
+            .await
+            .unwrap_or(false)
+    }
+
+    pub async fn respawn(&self, con: Con, id: MsgId) -> Result<(), String> {
+        self.request(|reply| LobbyCmd::Respawn { con, id, reply })
+            .await?
+    }
+}
+
+async fn run(mut lobby: Lobby, mut r: mpsc::Receiver<LobbyCmd>) {
+    // replies are dropped when the requester gave up waiting
+    while let Some(cmd) = r.recv().await {
+        match cmd {
+            LobbyCmd::Join { con, ch, un, reply } => {
+                let result = lobby.join_con(con, ch, un).map(|()| {
+                    lobby.broadcast_state_except(con);
+                    lobby.state(con)
+                });
+                reply.send(result).unwrap_or(());
+            }
+            LobbyCmd::State { con, reply } => {
+                reply.send(lobby.state(con)).unwrap_or(());
+            }
+            LobbyCmd::Leave { con, reply } => {
+                lobby.disjoin_con(&con);
+                lobby.broadcast_state();
+                reply.send(lobby.abandoned()).unwrap_or(());
+            }
+            LobbyCmd::VoteStart {
+                con,
+                value,
+                id,
+                reply,
+            } => {
+                let result = lobby.vote_start(con, value);
+                if result.is_ok() {
+                    lobby.pinned_broadcast_state(id, con);
+                }
+                reply.send(result).unwrap_or(());
+            }
+            LobbyCmd::SetSettings {
+                con,
+                settings,
+                id,
+                reply,
+            } => {
+                let result = lobby.set_settings(settings);
+                if result.is_ok() {
+                    lobby.pinned_broadcast_state(id, con);
+                }
+                reply.send(result).unwrap_or(());
+            }
+            LobbyCmd::PickTeam {
+                con,
+                team,
+                id,
+                reply,
+            } => {
+                let result = lobby.pick_team(con, team);
+                if result.is_ok() {
+                    lobby.pinned_broadcast_state(id, con);
+                }
+                reply.send(result).unwrap_or(());
+            }
+            LobbyCmd::Respawn { con, id, reply } => {
+                let result = lobby.respawn(con);
+                if result.is_ok() {
+                    lobby.broadcast_events();
+                    lobby.pinned_broadcast_state(id, con);
+                }
+                reply.send(result).unwrap_or(());
+            }
+            LobbyCmd::SetDirection {
+                con,
+                direction,
+                tick,
+            } => lobby.set_con_direction(con, direction, tick).unwrap_or(()),
+            LobbyCmd::SetBoost { con, boosting } => {
+                lobby.set_con_boost(con, boosting).unwrap_or(())
+            }
+            LobbyCmd::InGame { reply } => {
+                reply.send(lobby.in_game()).unwrap_or(());
+            }
+            LobbyCmd::Drain => {
+                lobby.drain();
+                lobby.broadcast_state();
+            }
+            LobbyCmd::Timer {
+                msg,
+                deadline,
+                period,
+            } => {
+                let started = Instant::now();
+                lobby.handle_message(msg);
+                let took = started.elapsed();
+
+                if took > period {
+                    tracing::warn!(
+                        lobby = lobby.name,
+                        "{msg:?} took {took:?}, longer than the period of {period:?}"
+                    );
+                } else if started - deadline > period {
+                    tracing::warn!(
+                        lobby = lobby.name,
+                        "{msg:?} started {:?} late, ticks were skipped",
+                        started - deadline
+                    );
+                }
+            }
+            LobbyCmd::Stop => {
+                lobby.stop();
+                return;
+            }
+        }
+    }
+}
@@ -1,91 +1,97 @@
-use super::lobby::{Lobby, LobbyCtrlMsg};
+// Registry of lobbies and of who joined which, owned by a single task
+//
+// Lobbies are looked up and joined through the registry, which waits on lobby tasks
+// but is never waited on by them, so the two cannot block each other.
+
+use super::actor::{LobbyCmd, LobbyHandle};
+use super::lobby::Lobby;
+use super::scheduler::Scheduler;
 use crate::mp::{Ch, Con, LobbyName, UserName};
 use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{mpsc, oneshot};
 
-type LobbyMessagePasserAbortHandle = tokio::task::AbortHandle;
+type Reply<T> = oneshot::Sender<T>;
 
-type ThreadSafeLobby = Arc<RwLock<Lobby>>;
+// requests waiting for the registry, senders wait when it is full
+const MAILBOX: usize = 1024;
 
-#[derive(Clone, Default)]
-pub struct Lobbies(
-    Arc<RwLock<HashMap<LobbyName, ThreadSafeLobby>>>,
-    Arc<RwLock<HashMap<Con, LobbyName>>>,
-    Arc<RwLock<HashMap<LobbyName, LobbyMessagePasserAbortHandle>>>,
-);
+enum LobbiesCmd {
+    Insert {
+        lobby: Lobby,
+        reply: Reply<Result<(), String>>,
+    },
+    Join {
+        lobby_name: LobbyName,
+        con: Con,
+        ch: Ch,
+        un: UserName,
+        reply: Reply<Result<interfacing::snake::LobbyState, JoinLobbyError>>,
+    },
+    Disjoin {
+        con: Con,
+        reply: Reply<()>,
+    },
+    JoinedLobby {
+        con: Con,
+        reply: Reply<Option<LobbyHandle>>,
+    },
+    LobbyNames {
+        reply: Reply<Vec<LobbyName>>,
+    },
+    // lobbies are not created, nor games started anymore afterwards
+    Drain {
+        reply: Reply<()>,
+    },
+    GamesInProgress {
+        reply: Reply<usize>,
+    },
+}
+
+#[derive(Clone)]
+pub struct Lobbies(mpsc::Sender<LobbiesCmd>);
+
+#[derive(Default)]
+struct Registry {
+    lobbies: HashMap<LobbyName, LobbyHandle>,
+    con_to_lobby: HashMap<Con, LobbyName>,
+    draining: bool,
+}
 
 impl Lobbies {
-    pub async fn lobby_names(&self) -> Vec<LobbyName> {
-        self.0.read().await.keys().cloned().into_iter().collect()
+    // starts the registry and the scheduler of lobby timers
+    pub fn new() -> Self {
+        let (s, r) = mpsc::channel(MAILBOX);
+        tokio::spawn(run(Scheduler::spawn(), r));
+        Self(s)
     }
 
-    #[allow(dead_code)]
-    pub async fn lobby_state(&self, con: Con) -> Option<interfacing::snake::LobbyState> {
-        match self.joined_lobby(con).await {
-            None => None, // player not in any lobby
-            Some(lobby) => Some(lobby.read().await.state(con)),
-        }
+    async fn request<T>(&self, cmd: impl FnOnce(Reply<T>) -> LobbiesCmd) -> T {
+        let (reply, response) = oneshot::channel();
+        // registry lives as long as any of its handles
+        self.0
+            .send(cmd(reply))
+            .await
+            .unwrap_or_else(|_| unreachable!());
+        response.await.expect("registry to reply")
     }
 
-    pub async fn joined_lobby(&self, con: Con) -> Option<ThreadSafeLobby> {
-        match self.1.read().await.get(&con) {
-            None => None, // player not in any lobby
-            Some(ln) => Some(self.0.read().await[ln].clone()),
-        }
+    pub async fn lobby_names(&self) -> Vec<LobbyName> {
+        self.request(|reply| LobbiesCmd::LobbyNames { reply }).await
     }
 
-    pub async fn joined_any(&self, con: Con) -> bool {
-        self.joined_lobby(con).await.is_some()
+    pub async fn joined_lobby(&self, con: Con) -> Option<LobbyHandle> {
+        self.request(|reply| LobbiesCmd::JoinedLobby { con, reply })
+            .await
     }
 
-    // Remove lobby if exists
-    pub async fn remove_lobby(&self, lobby_name: LobbyName) {
-        // while you hold this lock, noone else touches players
-        let mut con_to_lobby = self.1.write().await;
-
-        {
-            let _lock = self.0.read().await;
-
-            let lobby = match _lock.get(&lobby_name) {
-                None => return,
-                Some(lobby) => lobby,
-            };
-
-            let mut _lobby_lock = lobby.write().await;
-
-            _lobby_lock.stop();
-
-            let players = &_lobby_lock.players;
-
-            for (con, _) in players {
-                con_to_lobby.remove(con);
-            }
-        }
-
-        self.0.write().await.remove(&lobby_name);
-        self.2
-            .write()
-            .await
-            .remove(&lobby_name)
-            .expect("to be in sync")
-            .abort();
+    pub async fn joined_any(&self, con: Con) -> bool {
+        self.joined_lobby(con).await.is_some()
     }
 
+    // leave joined lobby if any, lobby abandoned mid-game is removed
     pub async fn disjoin_con(&self, con: Con) {
-        // while you hold this lock, noone else touches players
-        let mut con_to_lobby = self.1.write().await;
-
-        match con_to_lobby.get(&con) {
-            None => {}
-            Some(_lobby_name) => {
-                let _lock = self.0.read().await;
-                let lobby = _lock.get(_lobby_name).expect("to be in sync");
-                con_to_lobby.remove(&con);
-                lobby.write().await.disjoin_con(&con);
-                lobby.read().await.broadcast_state();
-            }
-        }
+        self.request(|reply| LobbiesCmd::Disjoin { con, reply })
+            .await
     }
 
     /// Try join con to specified lobby
@@ -100,95 +106,143 @@ impl Lobbies {
         ch: Ch,
         un: UserName,
     ) -> Result<interfacing::snake::LobbyState, JoinLobbyError> {
-        // while you hold this lock, noone else touches players
-        let mut con_to_lobby = self.1.write().await;
-
-        match con_to_lobby.get(&con) {
-            None => {
-                let _lock = self.0.read().await;
-                let lobby = _lock.get(&lobby_name);
-
-                match lobby {
-                    None => Err(JoinLobbyError::NotFound),
-                    Some(lobby) => {
-                        con_to_lobby.insert(con.clone(), lobby_name);
-                        let mut lock = lobby.write().await;
-                        match lock.join_con(con, ch, un) {
-                            Ok(()) => {
-                                lock.broadcast_state_except(con);
-                                Ok(lock.state(con))
-                            }
-                            Err(_m) => Err(JoinLobbyError::AlreadyStarted),
-                        }
-                    }
-                }
-            }
-            Some(_lobby_name) => {
-                // idempotency
-                if lobby_name == *_lobby_name {
-                    // don't need to check lobby, since it must be in sync
-
-                    Ok(self
-                        .get(_lobby_name)
-                        .await
-                        .unwrap() // TODO verify no in between changes
-                        .read()
-                        .await
-                        .state(con))
-                } else {
-                    Err(JoinLobbyError::AlreadyJoined(lobby_name.clone()))
-                }
-            }
-        }
+        self.request(|reply| LobbiesCmd::Join {
+            lobby_name,
+            con,
+            ch,
+            un,
+            reply,
+        })
+        .await
     }
 
-    /// Get lobby by name
-    pub async fn get(&self, name: &LobbyName) -> Option<ThreadSafeLobby> {
-        self.0.read().await.get(name).cloned()
+    // refuse new lobbies and games, server is going down
+    pub async fn drain(&self) {
+        self.request(|reply| LobbiesCmd::Drain { reply }).await
+    }
+
+    // lobbies with players in countdown or running game
+    pub async fn games_in_progress(&self) -> usize {
+        self.request(|reply| LobbiesCmd::GamesInProgress { reply })
+            .await
     }
 
     /// Create lobby only if it's not already created
     pub async fn insert_if_missing(&self, lobby: Lobby) -> Result<(), String> {
-        use std::collections::hash_map::Entry;
-        let mut w_lock = self.0.write().await;
+        self.request(|reply| LobbiesCmd::Insert { lobby, reply })
+            .await
+    }
+}
 
-        match w_lock.entry(lobby.name.clone()) {
-            Entry::Occupied(_) => Err("Lobby with this name already exists".into()),
-            Entry::Vacant(_) => {
-                let lobby_name = lobby.name.clone();
+async fn run(scheduler: Scheduler, mut r: mpsc::Receiver<LobbiesCmd>) {
+    let mut registry = Registry::default();
 
-                let (s, mut r) = tokio::sync::mpsc::unbounded_channel::<LobbyCtrlMsg>();
-                let lobby = Arc::new(RwLock::new(lobby.set_ch(s)));
+    while let Some(cmd) = r.recv().await {
+        match cmd {
+            LobbiesCmd::Insert { lobby, reply } => {
+                use std::collections::hash_map::Entry;
 
-                {
-                    w_lock.insert(lobby_name.clone(), lobby.clone());
+                let result = match registry.lobbies.entry(lobby.name.clone()) {
+                    _ if registry.draining => {
+                        Err("Server is going down for maintenance, no new lobbies".into())
+                    }
+                    Entry::Occupied(_) => Err("Lobby with this name already exists".into()),
+                    Entry::Vacant(entry) => {
+                        entry.insert(LobbyHandle::spawn(lobby, scheduler.clone()));
+                        Ok(())
+                    }
+                };
+                reply.send(result).unwrap_or(());
+            }
+            LobbiesCmd::Join {
+                lobby_name,
+                con,
+                ch,
+                un,
+                reply,
+            } => {
+                let result = registry.join_con(lobby_name, con, ch, un).await;
+                reply.send(result).unwrap_or(());
+            }
+            LobbiesCmd::Disjoin { con, reply } => {
+                registry.disjoin_con(con).await;
+                reply.send(()).unwrap_or(());
+            }
+            LobbiesCmd::JoinedLobby { con, reply } => {
+                let lobby = registry
+                    .con_to_lobby
+                    .get(&con)
+                    .and_then(|lobby_name| registry.lobbies.get(lobby_name))
+                    .cloned();
+                reply.send(lobby).unwrap_or(());
+            }
+            LobbiesCmd::Drain { reply } => {
+                registry.draining = true;
+                for lobby in registry.lobbies.values() {
+                    lobby.send(LobbyCmd::Drain).await;
                 }
-
-                {
-                    let lobbies = self.clone();
-                    let lobby_msg_passer_handle = tokio::spawn(async move {
-                        while let Some(msg) = r.recv().await {
-                            match msg {
-                                LobbyCtrlMsg::LobbyMsg(msg) => {
-                                    lobby.write().await.handle_message(msg)
-                                }
-                                LobbyCtrlMsg::LobbiesMsg(msg) => match msg {
-                                    LobbiesMsg::RemoveLobby(ln) => {
-                                        lobbies.remove_lobby(ln).await;
-                                    }
-                                },
-                            }
-                        }
-                    })
-                    .abort_handle();
-                    self.2
-                        .write()
-                        .await
-                        .insert(lobby_name, lobby_msg_passer_handle);
+                reply.send(()).unwrap_or(());
+            }
+            LobbiesCmd::GamesInProgress { reply } => {
+                let mut count = 0;
+                for lobby in registry.lobbies.values() {
+                    if lobby.in_game().await {
+                        count += 1;
+                    }
                 }
+                reply.send(count).unwrap_or(());
+            }
+            LobbiesCmd::LobbyNames { reply } => {
+                reply
+                    .send(registry.lobbies.keys().cloned().collect())
+                    .unwrap_or(());
+            }
+        }
+    }
+}
 
-                Ok(())
+impl Registry {
+    async fn join_con(
+        &mut self,
+        lobby_name: LobbyName,
+        con: Con,
+        ch: Ch,
+        un: UserName,
+    ) -> Result<interfacing::snake::LobbyState, JoinLobbyError> {
+        if let Some(joined) = self.con_to_lobby.get(&con) {
+            // idempotency
+            if *joined != lobby_name {
+                return Err(JoinLobbyError::AlreadyJoined(lobby_name));
             }
+            let lobby = self.lobbies.get(joined).ok_or(JoinLobbyError::NotFound)?;
+            return lobby.state(con).await.map_err(|_| JoinLobbyError::NotFound);
+        }
+
+        let lobby = self
+            .lobbies
+            .get(&lobby_name)
+            .ok_or(JoinLobbyError::NotFound)?;
+        let state = lobby
+            .join(con, ch, un)
+            .await
+            .map_err(|_| JoinLobbyError::AlreadyStarted)?;
+        self.con_to_lobby.insert(con, lobby_name);
+        Ok(state)
+    }
+
+    async fn disjoin_con(&mut self, con: Con) {
+        let Some(lobby_name) = self.con_to_lobby.remove(&con) else {
+            return;
+        };
+        let Some(lobby) = self.lobbies.get(&lobby_name) else {
+            return;
+        };
+
+        // when everyone quits from running game, remove lobby
+        if lobby.leave(con).await {
+            lobby.send(LobbyCmd::Stop).await;
+            self.lobbies.remove(&lobby_name);
+            self.con_to_lobby.retain(|_, joined| *joined != lobby_name);
         }
     }
 }
@@ -200,7 +254,76 @@ pub enum JoinLobbyError {
     AlreadyStarted,
 }
 
-// internal use messages sent from Lobby
-pub enum LobbiesMsg {
-    RemoveLobby(LobbyName),
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interfacing::snake::LobbyState;
+
+    fn user_names(state: &LobbyState) -> Vec<UserName> {
+        match state {
+            LobbyState::Prep(s) => s.participants.iter().map(|p| p.user_name.clone()).collect(),
+            LobbyState::Countdown(s) => s
+                .running
+                .scoreboard
+                .iter()
+                .map(|p| p.user_name.clone())
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_joins_leaves_and_votes() {
+        const LOBBIES: u16 = 4;
+        const CONS: u16 = 64;
+
+        let lobbies = Lobbies::new();
+        for n in 0..LOBBIES {
+            lobbies
+                .insert_if_missing(Lobby::new(format!("lobby {n}")))
+                .await
+                .unwrap();
+        }
+
+        let tasks = (0..CONS).map(|con| {
+            let lobbies = lobbies.clone();
+            tokio::spawn(async move {
+                let ch = Ch::new();
+                // some stay in the last round
+                for round in 0..=20 {
+                    let lobby_name = format!("lobby {}", (con + round) % LOBBIES);
+                    let joined = lobbies
+                        .join_con(lobby_name, con, ch.clone(), format!("{con}"))
+                        .await;
+
+                    if let (Ok(_), Some(lobby)) = (joined, lobbies.joined_lobby(con).await) {
+                        let vote = lobby.vote_start(con, round % 2 == 0, round.to_string());
+                        vote.await.unwrap();
+                    }
+                    if round % 3 != 2 {
+                        lobbies.disjoin_con(con).await;
+                    }
+                }
+            })
+        });
+        for task in tasks.collect::<Vec<_>>() {
+            task.await.unwrap();
+        }
+
+        // everyone is where the registry says, with the others of the same lobby
+        let mut groups = HashMap::<Vec<UserName>, Vec<UserName>>::new();
+        for con in 0..CONS {
+            if let Some(lobby) = lobbies.joined_lobby(con).await {
+                let mut user_names = user_names(&lobby.state(con).await.unwrap());
+                user_names.sort();
+                groups.entry(user_names).or_default().push(format!("{con}"));
+            }
+        }
+        assert!(!groups.is_empty());
+        for (mut user_names, mut cons) in groups {
+            user_names.sort();
+            cons.sort();
+            assert_eq!(user_names, cons);
+        }
+    }
 }
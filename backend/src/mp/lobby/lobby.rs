@@ -1,17 +1,35 @@
-use super::lobbies::LobbiesMsg;
+use super::actor::LobbyHandle;
 use super::lobby_con_state::LobbyConState;
-use super::state::{LobbyState, PrepLobbyState, RunningLobbyState};
+use super::scheduler::Scheduler;
+use super::series::Series;
+use super::state::{
+    team_color, CountdownLobbyState, LobbyState, PrepLobbyState, RunningLobbyState,
+    COUNTDOWN_SECONDS,
+};
 use crate::mp::{domain, Ch, Con, LobbyName, MsgId, ServerMsg, UserName, WsMsg};
+use interfacing::snake::{
+    lobby_state::{LobbySettings, TeamSettings},
+    TeamId,
+};
 use std::collections::HashMap;
+use std::time::Duration;
+
+// between ticks, unless set by the lobby
+const TICK_MS: u32 = 500;
+const MIN_TICK_MS: u32 = 100;
+const MAX_TICK_MS: u32 = 2000;
 
 pub struct Lobby {
     pub name: LobbyName,
     pub players: HashMap<Con, LobbyConState>,
     pub state: LobbyState,
+    // when playing best-of-N, survives between rounds
+    pub series: Option<Series>,
+    // server is going down, no new games start
+    draining: bool,
 
-    ch: Option<tokio::sync::mpsc::UnboundedSender<LobbyCtrlMsg>>,
-    // TODO maybe ship with RunningLobbyState
-    _loop_handle: Option<tokio::task::AbortHandle>,
+    // set once the lobby task is spawned
+    timers: Option<(Scheduler, LobbyHandle)>,
 }
 
 impl Lobby {
@@ -20,46 +38,164 @@ impl Lobby {
             name,
             players: Default::default(),
             state: LobbyState::Prep(PrepLobbyState::default()),
+            series: None,
+            draining: false,
 
-            ch: None,
-            _loop_handle: None,
+            timers: None,
         }
     }
 
-    pub fn begin(&mut self) -> Result<(), String> {
+    // everyone voted, spawn and show snakes before the game starts
+    fn begin_countdown(&mut self) -> Result<(), String> {
         match &mut self.state {
             LobbyState::Prep(s) => {
-                self.state = LobbyState::Running(s.to_running());
-                self.start_loop();
+                let user_names = self
+                    .players
+                    .iter()
+                    .map(|(con, LobbyConState { un, .. })| (*con, un.clone()))
+                    .collect();
+                self.state = LobbyState::Countdown(Box::new(CountdownLobbyState {
+                    prep: s.clone(),
+                    running: s.to_running(user_names),
+                    seconds_left: COUNTDOWN_SECONDS,
+                }));
+                self.start_countdown();
                 Ok(())
             }
             _ => Err("Illegal state".into()),
         }
     }
 
-    fn start_loop(&mut self) {
-        let ch = self.ch.clone().expect("set up channel");
-        self._loop_handle.replace(
-            tokio::spawn(async move {
-                // TODO should be swaped, or added larger pause before loop
-                loop {
-                    ch.send(LobbyCtrlMsg::LobbyMsg(LobbyMsg::Advance)).unwrap();
-                    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
-                }
-            })
-            .abort_handle(),
+    // everyone still in the lobby voted
+    fn begin_countdown_if_ready(&mut self) {
+        match &self.state {
+            LobbyState::Prep(s) if !self.players.is_empty() && s.all_voted_to_start() => {
+                self.begin_countdown().unwrap();
+            }
+            _ => {}
+        }
+    }
+
+    // back to preparation, keeping votes of the others
+    fn cancel_countdown(&mut self) {
+        match std::mem::replace(&mut self.state, LobbyState::Terminated) {
+            LobbyState::Countdown(s) => {
+                self.stop_loop();
+                self.state = LobbyState::Prep(s.prep);
+            }
+            state => self.state = state,
+        }
+    }
+
+    pub fn begin(&mut self) -> Result<(), String> {
+        match std::mem::replace(&mut self.state, LobbyState::Terminated) {
+            LobbyState::Countdown(s) => {
+                let CountdownLobbyState { prep, running, .. } = *s;
+                self.series = prep
+                    .settings
+                    .series
+                    .clone()
+                    .map(|settings| Series::new(settings, prep));
+                self.state = LobbyState::Running(Box::new(running));
+                self.stop_loop();
+                self.start_loop();
+                self.broadcast_events();
+                Ok(())
+            }
+            state => {
+                self.state = state;
+                Err("Illegal state".into())
+            }
+        }
+    }
+
+    fn start_countdown(&mut self) {
+        let (scheduler, handle) = self.timers();
+        scheduler.start(
+            self.name.clone(),
+            handle.clone(),
+            LobbyMsg::Countdown,
+            Duration::from_secs(1),
         );
     }
 
+    // first tick comes after a full interval, like the following ones
+    fn start_loop(&mut self) {
+        let tick_ms = match &self.state {
+            LobbyState::Running(s) => s.settings.tick_ms.unwrap_or(TICK_MS),
+            _ => TICK_MS,
+        };
+        let period = Duration::from_millis(tick_ms.clamp(MIN_TICK_MS, MAX_TICK_MS).into());
+
+        let (scheduler, handle) = self.timers();
+        scheduler.start(self.name.clone(), handle.clone(), LobbyMsg::Advance, period);
+    }
+
     fn stop_loop(&mut self) {
-        self._loop_handle.take().map(|h| h.abort());
+        let (scheduler, handle) = self.timers();
+        scheduler.stop(self.name.clone(), handle.clone());
+    }
+
+    fn timers(&self) -> &(Scheduler, LobbyHandle) {
+        self.timers.as_ref().expect("spawned lobby")
+    }
+
+    // in a series the loop keeps running through the intermission
+    fn game_ended(&mut self) {
+        let LobbyState::Running(s) = &self.state else {
+            return;
+        };
+        let winner = s.game_over.as_ref().and_then(|game_over| game_over.winner);
+
+        match &mut self.series {
+            Some(series) => {
+                series.round_ended(winner);
+                if series.ended {
+                    self.stop_loop();
+                }
+            }
+            None => self.stop_loop(),
+        }
+    }
+
+    // fresh arena and spawns for everyone currently in the lobby
+    fn next_round(&mut self) {
+        let Some(series) = &mut self.series else {
+            return;
+        };
+        series.next_round();
+
+        let mut prep = series.prep.clone();
+        prep.start_votes.clear();
+        prep.teams.retain(|con, _| self.players.contains_key(con));
+        for con in self.players.keys() {
+            prep.join_con(*con);
+        }
+
+        let user_names = self
+            .players
+            .iter()
+            .map(|(con, LobbyConState { un, .. })| (*con, un.clone()))
+            .collect();
+        self.state = LobbyState::Running(Box::new(prep.to_running(user_names)));
+    }
+
+    // game not started yet is called off
+    pub fn drain(&mut self) {
+        self.draining = true;
+        if let LobbyState::Countdown(s) = &mut self.state {
+            s.prep
+                .start_votes
+                .values_mut()
+                .for_each(|vote| *vote = false);
+            self.cancel_countdown();
+        }
     }
 
     pub fn stop(&mut self) {
         match &self.state {
-            LobbyState::Running { .. } => {
+            LobbyState::Countdown(_) | LobbyState::Running(_) => {
                 self.stop_loop();
-                self.ch.take();
 
                 self.state = LobbyState::Terminated;
             }
@@ -68,11 +204,20 @@ impl Lobby {
     }
 
     pub fn vote_start(&mut self, con: Con, value: bool) -> Result<(), String> {
+        if self.draining && value {
+            return Err("Server is going down for maintenance, no new games".into());
+        }
+
         match &mut self.state {
             LobbyState::Prep(s) => {
                 s.vote_start(con, value);
-                if s.all_voted_to_start() {
-                    self.begin().unwrap();
+                self.begin_countdown_if_ready();
+                Ok(())
+            }
+            LobbyState::Countdown(s) => {
+                if !value {
+                    s.prep.vote_start(con, false);
+                    self.cancel_countdown();
                 }
 
                 Ok(())
@@ -81,14 +226,81 @@ impl Lobby {
         }
     }
 
+    pub fn set_settings(&mut self, settings: LobbySettings) -> Result<(), String> {
+        // with respawns only a mode can end a round
+        if settings.series.is_some()
+            && settings.respawn_cooldown.is_some()
+            && settings.hill.is_none()
+            && settings.timed.is_none()
+        {
+            return Err("Series needs respawns off, hill or timed mode".into());
+        }
+
+        if let Some(teams) = &settings.teams {
+            let (min, max) = (TeamSettings::MIN_COUNT, TeamSettings::MAX_COUNT);
+            if !(min..=max).contains(&teams.count) {
+                return Err(format!("Team count must be from {min} to {max}"));
+            }
+        }
+
+        match &mut self.state {
+            LobbyState::Prep(s) => {
+                s.set_settings(settings);
+                Ok(())
+            }
+            _ => Err("Illegal state".into()),
+        }
+    }
+
+    pub fn pick_team(&mut self, con: Con, team: TeamId) -> Result<(), String> {
+        match &mut self.state {
+            LobbyState::Prep(s) => s.pick_team(con, team),
+            _ => Err("Illegal state".into()),
+        }
+    }
+
+    pub fn respawn(&mut self, con: Con) -> Result<(), String> {
+        match &mut self.state {
+            LobbyState::Running(s) => s.respawn(con),
+            _ => Err("Illegal state".into()),
+        }
+    }
+
+    pub fn set_con_boost(&mut self, con: Con, boosting: bool) -> Result<(), String> {
+        match &mut self.state {
+            LobbyState::Running(s) => {
+                s.set_con_boost(con, boosting);
+                Ok(())
+            }
+            _ => Err("Illegal state".into()),
+        }
+    }
+
     pub fn set_con_direction(
         &mut self,
         con: Con,
         direction: domain::Direction,
+        tick: u32,
     ) -> Result<(), String> {
         match &mut self.state {
+            // turn before the start
+            LobbyState::Countdown(s) => {
+                s.running.set_con_direction(con, direction, tick);
+                Ok(())
+            }
+            // late turn may play ticks again and end the game
             LobbyState::Running(s) => {
-                s.set_con_direction(con, direction);
+                let was_over = s.game_over.is_some();
+                s.set_con_direction(con, direction, tick);
+                let ended = !was_over && s.game_over.is_some();
+                if ended {
+                    self.game_ended();
+                }
+                self.broadcast_events();
+                // no tick may follow with the final standings
+                if ended {
+                    self.broadcast_state();
+                }
                 Ok(())
             }
             _ => Err("Illegal state".into()),
@@ -102,7 +314,18 @@ impl Lobby {
                 s.join_con(con);
                 Ok(())
             }
-            _ => Err("Illegal state".into()),
+            // newcomer has not voted yet
+            LobbyState::Countdown(_) => {
+                self.cancel_countdown();
+                self.join_con(con, ch, un)
+            }
+            LobbyState::Running(s) => {
+                s.join_con(con, un.clone())?;
+                self.players.insert(con, LobbyConState::new(ch, un));
+                self.broadcast_events();
+                Ok(())
+            }
+            LobbyState::Terminated => Err("Illegal state".into()),
         }
     }
 
@@ -111,19 +334,23 @@ impl Lobby {
         match &mut self.state {
             LobbyState::Prep(s) => {
                 s.remove_con(con);
+                self.begin_countdown_if_ready();
+            }
+
+            // spawns are made again without the leaver
+            LobbyState::Countdown(s) => {
+                s.prep.remove_con(con);
+                self.cancel_countdown();
+                self.begin_countdown_if_ready();
             }
 
             LobbyState::Running(s) => {
-                // when everyone quits from running game, remove lobby
-                if self.players.is_empty() {
-                    let send = LobbiesMsg::RemoveLobby(self.name.clone());
-                    self.ch
-                        .as_ref()
-                        .unwrap()
-                        .send(LobbyCtrlMsg::LobbiesMsg(send))
-                        .unwrap();
-                }
+                let was_over = s.game_over.is_some();
                 s.remove_con(con);
+                if !was_over && s.game_over.is_some() {
+                    self.game_ended();
+                }
+                self.broadcast_events();
             }
 
             LobbyState::Terminated => {}
@@ -134,19 +361,56 @@ impl Lobby {
 // message passing impl
 impl Lobby {
     #[must_use = "to use message passing"]
-    pub fn set_ch(mut self, ch: tokio::sync::mpsc::UnboundedSender<LobbyCtrlMsg>) -> Self {
-        self.ch.replace(ch);
+    pub fn set_timers(mut self, scheduler: Scheduler, handle: LobbyHandle) -> Self {
+        self.timers.replace((scheduler, handle));
         self
     }
 
+    // game which players are in, or about to be
+    pub fn in_game(&self) -> bool {
+        let playing = match &self.state {
+            LobbyState::Countdown(_) => true,
+            // series goes on with the next round
+            LobbyState::Running(s) => {
+                s.game_over.is_none() || self.series.as_ref().map_or(false, |series| !series.ended)
+            }
+            _ => false,
+        };
+        playing && !self.players.is_empty()
+    }
+
+    // everyone quit from running game, lobby can be removed
+    pub fn abandoned(&self) -> bool {
+        matches!(self.state, LobbyState::Running(_)) && self.players.is_empty()
+    }
+
     pub fn handle_message(&mut self, msg: LobbyMsg) {
         match &mut self.state {
             LobbyState::Prep { .. } => {
                 tracing::warn!("unhandled message {msg:?}")
             }
+            LobbyState::Countdown(s) => match msg {
+                LobbyMsg::Countdown => {
+                    s.seconds_left = s.seconds_left.saturating_sub(1);
+                    if s.seconds_left == 0 {
+                        self.begin().unwrap();
+                    }
+                    self.broadcast_state();
+                }
+                LobbyMsg::Advance => tracing::warn!("unhandled message {msg:?}"),
+            },
             LobbyState::Running(s) => match msg {
+                LobbyMsg::Countdown => tracing::warn!("unhandled message {msg:?}"),
                 LobbyMsg::Advance => {
-                    s.advance();
+                    if s.game_over.is_none() {
+                        s.advance();
+                        if s.game_over.is_some() {
+                            self.game_ended();
+                        }
+                    } else if self.series.as_mut().map_or(false, Series::tick) {
+                        self.next_round();
+                    }
+                    self.broadcast_events();
                     self.broadcast_state();
                 }
             },
@@ -193,8 +457,36 @@ impl Lobby {
             .for_each(|(_con, LobbyConState { ch, .. })| ch.send(send(*_con)).unwrap_or(()));
     }
 
+    /// Broadcast and trace events of running game, which were not yet broadcasted
+    pub fn broadcast_events(&mut self) {
+        use interfacing::snake::{lobby_state::GameEvents, WsServerMsg};
+
+        let LobbyState::Running(s) = &mut self.state else {
+            return;
+        };
+
+        let events = GameEvents {
+            counter: s.counter,
+            events: s.take_events(),
+        };
+
+        if events.events.is_empty() {
+            return;
+        }
+
+        for event in &events.events {
+            tracing::info!(
+                lobby = self.name,
+                counter = events.counter,
+                ?event,
+                "game event"
+            );
+        }
+
+        self.broadcast(WsMsg::new(WsServerMsg::GameEvents(events)));
+    }
+
     /// Broadcast message to all lobby participants
-    #[allow(unused)]
     fn broadcast(&self, msg: ServerMsg) {
         self.players
             .values()
@@ -210,64 +502,380 @@ impl Lobby {
         match &self.state {
             // TODO it cannot impl From because State itself participates in calculation
             // one way would be to duplicate user_names to PrepLobbyState
-            LobbyState::Prep(PrepLobbyState { start_votes }) => {
-                interfacing::snake::LobbyState::Prep(LobbyPrep {
-                    participants: self
-                        .players
-                        .iter()
-                        .map(|(con, LobbyConState { un, .. })| LobbyPrepParticipant {
-                            user_name: un.clone(),
-                            vote_start: *start_votes.get(&con).expect("to be in sync"),
-                        })
-                        .collect(),
+            LobbyState::Prep(PrepLobbyState {
+                start_votes,
+                settings,
+                teams,
+            }) => interfacing::snake::LobbyState::Prep(LobbyPrep {
+                participants: self
+                    .players
+                    .iter()
+                    .map(|(con, LobbyConState { un, .. })| LobbyPrepParticipant {
+                        user_name: un.clone(),
+                        vote_start: *start_votes.get(con).expect("to be in sync"),
+                        team: teams.get(con).cloned(),
+                        color: teams.get(con).cloned().map(team_color),
+                    })
+                    .collect(),
+                settings: settings.clone(),
+            }),
+
+            LobbyState::Countdown(s) => {
+                use interfacing::snake::lobby_state::LobbyCountdown;
+
+                interfacing::snake::LobbyState::Countdown(LobbyCountdown {
+                    running: Box::new(self.running_state(&s.running, receiver)),
+                    seconds_left: s.seconds_left,
                 })
             }
-
-            LobbyState::Running(RunningLobbyState {
-                snakes,
-                foods,
-                boundaries,
-                counter,
-                cons,
-                ..
-            }) => {
-                use interfacing::snake::lobby_state::LobbyRunning;
-
-                let con: Con = receiver;
-
-                let snake = snakes
-                    .into_iter()
-                    .find(|(_con, _)| **_con == con)
-                    .map(|(_, snake)| snake.clone());
-
-                let other_snakes = snakes
-                    .into_iter()
-                    .filter(|(_con, _)| **_con != con)
-                    .map(|(_, snake)| snake.clone())
-                    .collect::<Vec<_>>();
-
-                interfacing::snake::LobbyState::Running(LobbyRunning {
-                    counter: *counter,
-                    player_counter: cons.len() as _,
-                    domain: domain::Domain {
-                        snake,
-                        foods: foods.clone(),
-                        other_snakes,
-                        boundaries: *boundaries,
-                    },
-                })
+            LobbyState::Running(s) => {
+                interfacing::snake::LobbyState::Running(Box::new(self.running_state(s, receiver)))
             }
             LobbyState::Terminated => interfacing::snake::LobbyState::Terminated,
         }
     }
+
+    // what the receiver sees of the game
+    fn running_state(
+        &self,
+        s: &RunningLobbyState,
+        receiver: Con,
+    ) -> interfacing::snake::lobby_state::LobbyRunning {
+        use interfacing::snake::lobby_state::{
+            LobbyRunning, LobbyRunningPlayer, LobbyRunningTeam, UpcomingBoundaries,
+        };
+
+        let RunningLobbyState {
+            snakes,
+            foods,
+            boundaries,
+            counter,
+            cons,
+            players,
+            game_over,
+            upcoming_boundaries,
+            zones,
+            portals,
+            pickups,
+            ..
+        } = s;
+
+        let con: Con = receiver;
+
+        let snake = snakes
+            .iter()
+            .find(|(_con, _)| **_con == con)
+            .map(|(_, snake)| snake.clone());
+
+        let other_snakes = snakes
+            .iter()
+            .filter(|(_con, _)| **_con != con)
+            .map(|(_, snake)| snake.clone())
+            .collect::<Vec<_>>();
+
+        let boosting = s.boosting();
+        let mut scoreboard = players
+            .iter()
+            .map(|(con, player)| LobbyRunningPlayer {
+                id: *con,
+                user_name: player.user_name.clone(),
+                color: player.color.clone(),
+                score: player.score.clone(),
+                alive: snakes.contains_key(con),
+                team: player.team,
+                boosting: boosting.contains(con),
+                input_delay: player.input_delay.clone(),
+            })
+            .collect::<Vec<_>>();
+
+        let mut teams = Vec::<LobbyRunningTeam>::new();
+        for player in scoreboard.iter() {
+            let Some(id) = player.team else {
+                continue;
+            };
+
+            match teams.iter_mut().find(|team| team.id == id) {
+                Some(team) => {
+                    team.score += player.score.score;
+                    team.alive |= player.alive;
+                }
+                None => teams.push(LobbyRunningTeam {
+                    id,
+                    color: team_color(id),
+                    score: player.score.score,
+                    alive: player.alive,
+                }),
+            }
+        }
+
+        teams.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.id.cmp(&b.id)));
+
+        scoreboard.sort_by(|a, b| {
+            b.score
+                .score
+                .cmp(&a.score.score)
+                .then_with(|| a.user_name.cmp(&b.user_name))
+        });
+
+        LobbyRunning {
+            counter: *counter,
+            player_counter: cons.len() as _,
+            domain: domain::Domain {
+                snake,
+                foods: foods.clone(),
+                other_snakes,
+                boundaries: *boundaries,
+                portals: portals.clone(),
+                pickups: pickups.clone(),
+            },
+            scoreboard,
+            respawn_in: s.respawn_in(&con),
+            teams,
+            game_over: game_over.clone(),
+            upcoming_boundaries: upcoming_boundaries.map(|(tick, boundaries)| UpcomingBoundaries {
+                boundaries,
+                in_ticks: tick.saturating_sub(*counter),
+            }),
+            zones: zones.clone(),
+            ticks_left: s.ticks_left(),
+            series: self.series.as_ref().map(Series::to_lobby_series),
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum LobbyMsg {
     Advance,
+    // one second of the start countdown passed
+    Countdown,
 }
 
-pub enum LobbyCtrlMsg {
-    LobbyMsg(LobbyMsg),
-    LobbiesMsg(LobbiesMsg),
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interfacing::snake::lobby_state::SeriesSettings;
+
+    // spawned lobby timers, which are driven by hand instead
+    fn lobby(cons: &[Con]) -> Lobby {
+        let scheduler = Scheduler::spawn();
+        let handle = LobbyHandle::spawn(Lobby::new("timers".into()), scheduler.clone());
+        let mut lobby = Lobby::new("lobby".into()).set_timers(scheduler, handle);
+        for con in cons {
+            lobby.join_con(*con, Ch::new(), format!("{con}")).unwrap();
+        }
+        lobby
+    }
+
+    fn start(lobby: &mut Lobby) {
+        let cons = lobby.players.keys().cloned().collect::<Vec<_>>();
+        for con in cons {
+            lobby.vote_start(con, true).unwrap();
+        }
+        for _ in 0..COUNTDOWN_SECONDS {
+            lobby.handle_message(LobbyMsg::Countdown);
+        }
+    }
+
+    fn running(lobby: &mut Lobby) -> &mut RunningLobbyState {
+        match &mut lobby.state {
+            LobbyState::Running(s) => s,
+            _ => panic!("game to be running"),
+        }
+    }
+
+    #[tokio::test]
+    async fn countdown() {
+        let mut lobby = lobby(&[1, 2, 3]);
+        lobby.vote_start(1, true).unwrap();
+        lobby.vote_start(2, true).unwrap();
+        assert!(matches!(lobby.state, LobbyState::Prep(_)));
+
+        // the last one not ready leaves
+        lobby.disjoin_con(&3);
+        assert!(matches!(lobby.state, LobbyState::Countdown(_)));
+
+        // unvote cancels, keeping the other vote
+        lobby.vote_start(1, false).unwrap();
+        let LobbyState::Prep(s) = &lobby.state else {
+            panic!("countdown to be cancelled");
+        };
+        assert_eq!(s.start_votes.get(&2), Some(&true));
+
+        lobby.join_con(3, Ch::new(), "3".into()).unwrap();
+        start(&mut lobby);
+        assert_eq!(running(&mut lobby).snakes.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn leaving_countdown() {
+        let mut lobby = lobby(&[1, 2]);
+        lobby.vote_start(1, true).unwrap();
+        lobby.vote_start(2, true).unwrap();
+        lobby.disjoin_con(&2);
+        let LobbyState::Countdown(s) = &lobby.state else {
+            panic!("countdown to go on without the leaver");
+        };
+        assert_eq!(s.running.snakes.len(), 1);
+
+        for _ in 0..COUNTDOWN_SECONDS {
+            lobby.handle_message(LobbyMsg::Countdown);
+        }
+        assert_eq!(running(&mut lobby).snakes.keys().collect::<Vec<_>>(), [&1]);
+    }
+
+    #[tokio::test]
+    async fn draining() {
+        let mut lobby = lobby(&[1, 2]);
+        lobby.vote_start(1, true).unwrap();
+        lobby.vote_start(2, true).unwrap();
+        assert!(lobby.in_game());
+
+        lobby.drain();
+        assert!(matches!(lobby.state, LobbyState::Prep(_)));
+        assert!(!lobby.in_game());
+        assert!(lobby.vote_start(1, true).is_err());
+        assert!(lobby.vote_start(1, false).is_ok());
+    }
+
+    #[tokio::test]
+    async fn game_over_is_not_in_game() {
+        let mut lobby = lobby(&[1, 2]);
+        lobby
+            .set_settings(LobbySettings {
+                teams: Some(TeamSettings {
+                    count: 2,
+                    teammate_collision:
+                        interfacing::snake::lobby_state::TeammateCollision::PassThrough,
+                }),
+                ..Default::default()
+            })
+            .unwrap();
+        start(&mut lobby);
+        assert!(lobby.in_game());
+
+        // the other team is out
+        running(&mut lobby).snakes.remove(&2);
+        lobby.handle_message(LobbyMsg::Advance);
+        assert!(running(&mut lobby).game_over.is_some());
+        assert!(!lobby.in_game());
+    }
+
+    #[tokio::test]
+    async fn team_count_in_range() {
+        use interfacing::snake::lobby_state::TeammateCollision;
+
+        let mut lobby = lobby(&[1, 2]);
+        let teams = |count| LobbySettings {
+            teams: Some(TeamSettings {
+                count,
+                teammate_collision: TeammateCollision::PassThrough,
+            }),
+            ..Default::default()
+        };
+
+        assert!(lobby.set_settings(teams(0)).is_err());
+        assert!(lobby
+            .set_settings(teams(TeamSettings::MAX_COUNT + 1))
+            .is_err());
+        assert!(lobby.set_settings(teams(TeamSettings::MAX_COUNT)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn free_for_all_series() {
+        let mut lobby = lobby(&[1, 2]);
+        let series = Some(SeriesSettings {
+            rounds: 3,
+            intermission: 1,
+        });
+
+        let respawns = LobbySettings {
+            series: series.clone(),
+            respawn_cooldown: Some(LobbySettings::DEFAULT_RESPAWN_COOLDOWN),
+            ..Default::default()
+        };
+        assert!(lobby.set_settings(respawns).is_err());
+        lobby
+            .set_settings(LobbySettings {
+                series,
+                respawn_cooldown: None,
+                ..Default::default()
+            })
+            .unwrap();
+        start(&mut lobby);
+
+        for round in 1..=2 {
+            assert_eq!(lobby.series.as_ref().unwrap().round, round);
+            // last one standing wins the round
+            running(&mut lobby).snakes.remove(&2);
+            lobby.handle_message(LobbyMsg::Advance);
+            assert!(running(&mut lobby).game_over.is_some());
+
+            lobby.handle_message(LobbyMsg::Advance);
+        }
+
+        let series = lobby.series.as_ref().unwrap();
+        assert!(series.ended);
+        assert_eq!(series.winner(), Some(domain::Winner::Player(1)));
+    }
+
+    #[tokio::test]
+    async fn late_turn_ending_game() {
+        use crate::mp::domain::{Direction, Pos, Sections, Snake};
+        use interfacing::snake::{lobby_state::TeammateCollision, LobbyState, WsServerMsg};
+
+        let mut lobby = lobby(&[]);
+        let chs = [Ch::new(), Ch::new()];
+        for (con, ch) in (1..).zip(chs.iter()) {
+            lobby.join_con(con, ch.clone(), format!("{con}")).unwrap();
+        }
+        lobby
+            .set_settings(LobbySettings {
+                teams: Some(TeamSettings {
+                    count: 2,
+                    teammate_collision: TeammateCollision::PassThrough,
+                }),
+                ..Default::default()
+            })
+            .unwrap();
+        start(&mut lobby);
+
+        // both go up along the side walls
+        let s = running(&mut lobby);
+        let b = s.boundaries;
+        let y = (b.min.y + b.max.y) / 2;
+        for (con, x) in [(1, b.max.x - 1), (2, b.min.x + 1)] {
+            let snake = Snake {
+                sections: Sections::from_directions(Pos::new(x, y), [Direction::Up]),
+                direction: Direction::Up,
+                id: Some(con),
+                effects: Default::default(),
+            };
+            s.foods.values.remove(&Pos::new(x, y - 2));
+            s.snakes.insert(con, snake);
+        }
+        s.take_events();
+
+        lobby.handle_message(LobbyMsg::Advance);
+        let tick = running(&mut lobby).counter;
+        for ch in chs.iter() {
+            while ch.depth() > 0 {
+                ch.recv().await;
+            }
+        }
+
+        // 1 turned into the wall on the tick played already
+        lobby.set_con_direction(1, Direction::Right, tick).unwrap();
+        assert!(running(&mut lobby).game_over.is_some());
+        for ch in chs.iter() {
+            let mut over = false;
+            while ch.depth() > 0 {
+                if let Some(WsMsg(_, WsServerMsg::LobbyState(LobbyState::Running(s)))) =
+                    ch.recv().await
+                {
+                    over = s.game_over.is_some();
+                }
+            }
+            assert!(over);
+        }
+    }
 }
@@ -1,5 +1,8 @@
+pub mod actor;
 pub mod lobbies;
 pub mod lobby;
 pub mod lobby_con_state;
+pub mod scheduler;
+pub mod series;
 pub mod state;
 pub mod usernames;
@@ -0,0 +1,179 @@
+// Timed messages of all lobbies, driven from a single task
+//
+// Every lobby has at most one timer. Timers are intervals which skip missed ticks
+// instead of catching up, so ticks stay on a fixed grid and do not drift
+// by the time spent processing them.
+
+use super::actor::{LobbyCmd, LobbyHandle};
+use super::lobby::LobbyMsg;
+use crate::mp::LobbyName;
+use std::collections::HashMap;
+use std::future::Future;
+use std::task::Poll;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::{Instant, Interval, MissedTickBehavior};
+
+// internal use messages sent from Lobby
+pub enum SchedulerMsg {
+    // replaces the timer of the lobby, first message comes after a full period
+    Start(LobbyName, LobbyHandle, LobbyMsg, Duration),
+    // leaves the timer of a later lobby with the same name
+    Stop(LobbyName, LobbyHandle),
+}
+
+#[derive(Clone)]
+pub struct Scheduler(mpsc::UnboundedSender<SchedulerMsg>);
+
+struct Timer {
+    lobby: LobbyHandle,
+    msg: LobbyMsg,
+    period: Duration,
+    interval: Interval,
+}
+
+impl Scheduler {
+    pub fn spawn() -> Self {
+        let (s, r) = mpsc::unbounded_channel();
+        tokio::spawn(run(r));
+        Self(s)
+    }
+
+    pub fn start(
+        &self,
+        lobby_name: LobbyName,
+        lobby: LobbyHandle,
+        msg: LobbyMsg,
+        period: Duration,
+    ) {
+        self.send(SchedulerMsg::Start(lobby_name, lobby, msg, period));
+    }
+
+    pub fn stop(&self, lobby_name: LobbyName, lobby: LobbyHandle) {
+        self.send(SchedulerMsg::Stop(lobby_name, lobby));
+    }
+
+    fn send(&self, msg: SchedulerMsg) {
+        // closed only when the runtime shuts down
+        self.0.send(msg).unwrap_or(());
+    }
+}
+
+async fn run(mut r: mpsc::UnboundedReceiver<SchedulerMsg>) {
+    let mut timers = HashMap::<LobbyName, Timer>::new();
+
+    loop {
+        tokio::select! {
+            // timer changes go first, a stopped timer must not fire once more
+            biased;
+
+            msg = r.recv() => match msg {
+                None => return,
+                Some(SchedulerMsg::Start(lobby_name, lobby, msg, period)) => {
+                    let mut interval = tokio::time::interval_at(Instant::now() + period, period);
+                    interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+                    timers.insert(lobby_name, Timer { lobby, msg, period, interval });
+                }
+                Some(SchedulerMsg::Stop(lobby_name, lobby)) => stop(&mut timers, &lobby_name, &lobby),
+            },
+
+            // processing time is measured by the lobby
+            (lobby_name, deadline) = next_due(&mut timers) => {
+                use mpsc::error::TrySendError;
+
+                let Timer { lobby, msg, period, .. } = &timers[&lobby_name];
+                match lobby.try_send(LobbyCmd::Timer { msg: *msg, deadline, period: *period }) {
+                    Ok(()) => {}
+                    Err(TrySendError::Full(_)) => {
+                        tracing::warn!("mailbox of lobby {lobby_name} is full, {msg:?} skipped");
+                    }
+                    Err(TrySendError::Closed(_)) => {
+                        timers.remove(&lobby_name);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn stop(timers: &mut HashMap<LobbyName, Timer>, lobby_name: &LobbyName, lobby: &LobbyHandle) {
+    if timers
+        .get(lobby_name)
+        .is_some_and(|timer| timer.lobby.same(lobby))
+    {
+        timers.remove(lobby_name);
+    }
+}
+
+// lobby with a timer that fired, with the instant it was due at
+fn next_due(
+    timers: &mut HashMap<LobbyName, Timer>,
+) -> impl Future<Output = (LobbyName, Instant)> + '_ {
+    std::future::poll_fn(move |cx| {
+        for (lobby_name, timer) in timers.iter_mut() {
+            if let Poll::Ready(deadline) = timer.interval.poll_tick(cx) {
+                return Poll::Ready((lobby_name.clone(), deadline));
+            }
+        }
+        Poll::Pending
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mp::lobby::lobby::Lobby;
+
+    #[tokio::test(start_paused = true)]
+    async fn skips_missed_ticks() {
+        let period = Duration::from_millis(500);
+        let start = Instant::now();
+        let mut timers = HashMap::new();
+        let mut interval = tokio::time::interval_at(start + period, period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        timers.insert(
+            "lobby".to_owned(),
+            Timer {
+                lobby: LobbyHandle::spawn(Lobby::new("lobby".into()), Scheduler::spawn()),
+                msg: LobbyMsg::Advance,
+                period,
+                interval,
+            },
+        );
+
+        let (_, deadline) = next_due(&mut timers).await;
+        assert_eq!(deadline, start + period);
+
+        // processing overran two periods, the grid is kept
+        tokio::time::advance(Duration::from_millis(1200)).await;
+        let (_, deadline) = next_due(&mut timers).await;
+        assert_eq!(deadline, start + period * 2);
+        let (_, deadline) = next_due(&mut timers).await;
+        assert_eq!(deadline, start + period * 4);
+    }
+
+    #[tokio::test]
+    async fn stop_of_removed_lobby() {
+        let scheduler = Scheduler::spawn();
+        let removed = LobbyHandle::spawn(Lobby::new("lobby".into()), scheduler.clone());
+        let lobby = LobbyHandle::spawn(Lobby::new("lobby".into()), scheduler);
+        let period = Duration::from_secs(1);
+        let mut timers = HashMap::new();
+        timers.insert(
+            "lobby".to_owned(),
+            Timer {
+                lobby: lobby.clone(),
+                msg: LobbyMsg::Advance,
+                period,
+                interval: tokio::time::interval(period),
+            },
+        );
+
+        // late stop of the removed one with the same name
+        stop(&mut timers, &"lobby".to_owned(), &removed);
+        assert!(timers.contains_key("lobby"));
+
+        stop(&mut timers, &"lobby".to_owned(), &lobby);
+        assert!(timers.is_empty());
+    }
+}
@@ -0,0 +1,149 @@
+use super::state::PrepLobbyState;
+use crate::mp::domain::Winner;
+use interfacing::snake::lobby_state::{LobbySeries, SeriesSettings, SeriesStanding};
+use std::collections::HashMap;
+
+// best-of-N rounds, kept next to the lobby state across rounds
+pub struct Series {
+    pub settings: SeriesSettings,
+    // settings and teams every round starts with
+    pub prep: PrepLobbyState,
+    // starting from 1
+    pub round: u32,
+    pub wins: HashMap<Winner, u32>,
+    // ticks until the next round, between rounds
+    pub intermission: Option<u32>,
+    pub ended: bool,
+}
+
+impl Series {
+    pub fn new(settings: SeriesSettings, prep: PrepLobbyState) -> Self {
+        Self {
+            settings,
+            prep,
+            round: 1,
+            wins: HashMap::new(),
+            intermission: None,
+            ended: false,
+        }
+    }
+
+    // credit the round winner, then either end the series or wait for the next round
+    pub fn round_ended(&mut self, winner: Option<Winner>) {
+        if let Some(winner) = winner {
+            *self.wins.entry(winner).or_default() += 1;
+        }
+
+        let majority = self.settings.rounds / 2 + 1;
+        if self.round >= self.settings.rounds || self.wins.values().any(|wins| *wins >= majority) {
+            self.ended = true;
+        } else {
+            self.intermission = Some(self.settings.intermission);
+        }
+    }
+
+    // counts the intermission down, true when the next round should start
+    pub fn tick(&mut self) -> bool {
+        match &mut self.intermission {
+            Some(0) => true,
+            Some(ticks) => {
+                *ticks -= 1;
+                *ticks == 0
+            }
+            None => false,
+        }
+    }
+
+    pub fn next_round(&mut self) {
+        self.round += 1;
+        self.intermission = None;
+    }
+
+    // most round wins, none on a tie
+    pub fn winner(&self) -> Option<Winner> {
+        let best = self.wins.values().max()?;
+        let mut leaders = self.wins.iter().filter(|(_, wins)| *wins == best);
+
+        match (leaders.next(), leaders.next()) {
+            (Some((winner, _)), None) => Some(*winner),
+            _ => None,
+        }
+    }
+
+    pub fn standings(&self) -> Vec<SeriesStanding> {
+        let mut standings = self
+            .wins
+            .iter()
+            .map(|(winner, wins)| SeriesStanding {
+                winner: *winner,
+                wins: *wins,
+            })
+            .collect::<Vec<_>>();
+        standings.sort_by_key(|standing| {
+            let id = match standing.winner {
+                Winner::Team(team) => team as u16,
+                Winner::Player(con) => con,
+            };
+            (std::cmp::Reverse(standing.wins), id)
+        });
+        standings
+    }
+
+    pub fn to_lobby_series(&self) -> LobbySeries {
+        LobbySeries {
+            round: self.round,
+            rounds: self.settings.rounds,
+            standings: self.standings(),
+            next_round_in: self.intermission,
+            ended: self.ended,
+            winner: self.ended.then(|| self.winner()).flatten(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn series(rounds: u32) -> Series {
+        Series::new(
+            SeriesSettings {
+                rounds,
+                intermission: 2,
+            },
+            Default::default(),
+        )
+    }
+
+    #[test]
+    fn majority_ends_series() {
+        let mut s = series(3);
+
+        s.round_ended(Some(Winner::Player(1)));
+        assert!(!s.ended);
+        assert_eq!(s.intermission, Some(2));
+        assert!(!s.tick());
+        assert!(s.tick());
+        s.next_round();
+
+        s.round_ended(Some(Winner::Player(1)));
+        assert!(s.ended);
+        assert_eq!(s.intermission, None);
+        assert_eq!(s.to_lobby_series().winner, Some(Winner::Player(1)));
+    }
+
+    #[test]
+    fn last_round_ends_series() {
+        let mut s = series(2);
+
+        s.round_ended(Some(Winner::Player(1)));
+        s.next_round();
+        s.round_ended(Some(Winner::Player(2)));
+
+        assert!(s.ended);
+        assert_eq!(s.round, 2);
+        // tied on wins
+        assert_eq!(s.winner(), None);
+        assert_eq!(s.standings().len(), 2);
+    }
+}
@@ -1,32 +1,240 @@
-use crate::mp::{domain, Con};
-use rand::{seq::IteratorRandom, Rng};
-use std::collections::{HashMap, HashSet};
+use crate::mp::{domain, Con, UserName};
+use interfacing::snake::{
+    lobby_state::{CaptureZone, GameOver, InputDelay, LobbySettings, Score, TeammateCollision},
+    Color, TeamId,
+};
+use rand::{rngs::StdRng, seq::IteratorRandom, Rng, SeedableRng};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+const FOOD_SCORE: u32 = 1;
+const KILL_SCORE: u32 = 5;
+// per tick of holding a capture zone
+const ZONE_SCORE: u32 = 1;
+// zones are squares of 2 * radius + 1 cells
+const ZONE_RADIUS: i32 = 1;
+
+// respawned snake is placed at least this far from other mouths and walls ahead
+const SPAWN_HEAD_DISTANCE: i32 = 5;
+const SPAWN_WALL_DISTANCE: i32 = 3;
+const SPAWN_SECTIONS: usize = 3;
+// small games keep the arena they always had
+const MIN_ARENA_RADIUS: i32 = 6;
+
+// share of the cells inside the walls kept covered with food
+const FOOD_DENSITY: f64 = 0.08;
+
+// power-ups in the arena at once
+const MAX_PICKUPS: usize = 3;
+// one in that many food refills drops a power-up
+const PICKUP_CHANCE: u32 = 3;
+
+// turns are accepted this many ticks before or after the tick they are meant for,
+// late ones play the ticks since again when no events of them were broadcasted,
+// otherwise they are applied on the next tick
+const INPUT_WINDOW: u32 = 3;
+// buffered turns per player
+const INPUT_BUFFER: usize = 8;
+
+// boost stops when the snake gets this short
+const BOOST_MIN_SECTIONS: usize = 2;
+
+// arena does not shrink below, leaves space for food figures
+const SHRINK_MIN_SIZE: u32 = 8;
+
+// assigned to players in order, repeats when players outnumber colors
+const PLAYER_COLORS: [&str; 8] = [
+    "#e6194b", "#3cb44b", "#4363d8", "#f58231", "#911eb4", "#42d4f4", "#f032e6", "#bfef45",
+];
+
+// indexed by team id, in team mode replace player colors
+const TEAM_COLORS: [&str; 4] = ["#e6194b", "#4363d8", "#3cb44b", "#ffe119"];
+
+pub fn team_color(team: TeamId) -> Color {
+    TEAM_COLORS[(team as usize - 1) % TEAM_COLORS.len()].into()
+}
+
+// in the middle of the upper half, more players get zones on the sides
+fn capture_zones(b: &domain::Boundaries, players: usize) -> Vec<CaptureZone> {
+    let center = domain::Pos::new(
+        (b.min.x + b.max.x) / 2,
+        (b.min.y + (b.min.y + b.max.y) / 2) / 2,
+    );
+    let offset = b.width() as i32 / 3;
+
+    let centers = if players > 3 {
+        vec![center.x - offset, center.x, center.x + offset]
+    } else {
+        vec![center.x]
+    };
+
+    centers
+        .into_iter()
+        .map(|x| CaptureZone {
+            min: domain::Pos::new(x - ZONE_RADIUS, center.y - ZONE_RADIUS),
+            max: domain::Pos::new(x + ZONE_RADIUS, center.y + ZONE_RADIUS),
+            holder: None,
+        })
+        .collect()
+}
+
+// pairs across the arena, one cell away from the walls
+fn portal_pairs(b: &domain::Boundaries) -> Vec<domain::Portal> {
+    let center = domain::Pos::new((b.min.x + b.max.x) / 2, (b.min.y + b.max.y) / 2);
+
+    vec![
+        domain::Portal {
+            a: domain::Pos::new(b.min.x + 2, center.y),
+            b: domain::Pos::new(b.max.x - 2, center.y),
+        },
+        domain::Portal {
+            a: domain::Pos::new(center.x, b.min.y + 2),
+            b: domain::Pos::new(center.x, b.max.y - 2),
+        },
+    ]
+}
+
+// snakes evenly around a ring at the center, each heading along the ring,
+// away from the walls and towards the tail of the one ahead,
+// the ring and the arena grow with the player count
+fn plan_spawns(players: usize) -> (domain::Boundaries, Vec<domain::Sections>) {
+    use domain::{Direction, Pos, Sections};
+    use std::f64::consts::PI;
+
+    // neighbouring heads are at least this far from each other's tails,
+    // one more for rounding to cells
+    let gap = (SPAWN_HEAD_DISTANCE + SPAWN_SECTIONS as i32 + 1) as f64;
+    let ring_radius = match players {
+        0 | 1 => 0,
+        // chebyshev distance is at least the chord over sqrt 2
+        n => (gap * 2f64.sqrt() / (2. * (PI / n as f64).sin())).ceil() as i32,
+    };
+    let arena_radius =
+        MIN_ARENA_RADIUS.max(ring_radius + SPAWN_SECTIONS.max(SPAWN_WALL_DISTANCE as _) as i32 + 1);
+
+    let spawns = (0..players)
+        .map(|i| {
+            let angle = 2. * PI * i as f64 / players as f64;
+            let mouth = Pos::new(
+                (ring_radius as f64 * angle.cos()).round() as i32,
+                (ring_radius as f64 * angle.sin()).round() as i32,
+            );
+
+            // clockwise tangent along the longer axis
+            let direction = if mouth.x.abs() >= mouth.y.abs() {
+                if mouth.x > 0 {
+                    Direction::Bottom
+                } else {
+                    Direction::Up
+                }
+            } else if mouth.y > 0 {
+                Direction::Left
+            } else {
+                Direction::Right
+            };
+
+            let tail = (0..SPAWN_SECTIONS).fold(mouth, |pos, _| pos.to(direction.opposite()));
+            Sections::from_directions(tail, (0..SPAWN_SECTIONS).map(|_| direction))
+        })
+        .collect();
+
+    (
+        Pos::new(0, 0).boundaries_in_radius(arena_radius, arena_radius),
+        spawns,
+    )
+}
+
+// least populated team of the given count, lower id on ties
+fn smallest_team(members: impl Iterator<Item = TeamId>, count: TeamId) -> TeamId {
+    let mut sizes = vec![0; count as usize];
+    for team in members.filter(|team| (1..=count).contains(team)) {
+        sizes[team as usize - 1] += 1;
+    }
+
+    (1..=count)
+        .min_by_key(|team| sizes[*team as usize - 1])
+        .unwrap_or(1)
+}
 
 pub enum LobbyState {
     Prep(PrepLobbyState),
-    Running(RunningLobbyState),
+    // everyone voted, spawns are shown before the game starts
+    Countdown(Box<CountdownLobbyState>),
+    // boxed, since it is much larger than the others
+    Running(Box<RunningLobbyState>),
     // terminated is scheduled for clean up
     Terminated,
 }
 
+// seconds from the last start vote to the first tick
+pub const COUNTDOWN_SECONDS: u32 = 3;
+
+pub struct CountdownLobbyState {
+    // returned to when someone takes back the vote
+    pub prep: PrepLobbyState,
+    // spawned, advances once the countdown is over
+    pub running: RunningLobbyState,
+    pub seconds_left: u32,
+}
+
 // lobby parameters
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct PrepLobbyState {
     // should contain all players in lobby
     pub start_votes: HashMap<Con, bool>,
+    pub settings: LobbySettings,
+    // in team mode should contain all players in lobby
+    pub teams: HashMap<Con, TeamId>,
 }
 
 impl PrepLobbyState {
-    pub fn to_running(&self) -> RunningLobbyState {
-        self.into()
+    pub fn to_running(&self, user_names: HashMap<Con, UserName>) -> RunningLobbyState {
+        RunningLobbyState::new(self, user_names)
     }
 
     pub fn join_con(&mut self, con: Con) {
         self.start_votes.insert(con, false);
+        self.assign_teams();
     }
 
     pub fn remove_con(&mut self, con: &Con) {
         self.start_votes.remove(con);
+        self.teams.remove(con);
+    }
+
+    pub fn pick_team(&mut self, con: Con, team: TeamId) -> Result<(), String> {
+        let Some(teams) = &self.settings.teams else {
+            return Err("Not in team mode".into());
+        };
+
+        if !(1..=teams.count).contains(&team) {
+            return Err(format!("Team {team} does not exist"));
+        }
+
+        if self.start_votes.contains_key(&con) {
+            self.teams.insert(con, team);
+        }
+
+        Ok(())
+    }
+
+    // put players without a valid team into the smallest ones
+    fn assign_teams(&mut self) {
+        let Some(count) = self.settings.teams.as_ref().map(|teams| teams.count) else {
+            self.teams.clear();
+            return;
+        };
+
+        self.teams.retain(|_, team| (1..=count).contains(team));
+
+        let mut cons = self.start_votes.keys().cloned().collect::<Vec<_>>();
+        cons.sort();
+
+        for con in cons {
+            if !self.teams.contains_key(&con) {
+                let team = smallest_team(self.teams.values().cloned(), count);
+                self.teams.insert(con, team);
+            }
+        }
     }
 
     pub fn vote_start(&mut self, con: Con, vote: bool) {
@@ -36,6 +244,13 @@ impl PrepLobbyState {
         }
     }
 
+    // votes were given for other settings
+    pub fn set_settings(&mut self, settings: LobbySettings) {
+        self.settings = settings;
+        self.start_votes.values_mut().for_each(|vote| *vote = false);
+        self.assign_teams();
+    }
+
     pub fn all_voted_to_start(&self) -> bool {
         self.start_votes
             .values()
@@ -44,6 +259,7 @@ impl PrepLobbyState {
     }
 }
 
+#[derive(Clone)]
 pub struct RunningLobbyState {
     // TODO merge into "con to con state"
     pub snakes: HashMap<Con, domain::Snake>,
@@ -51,62 +267,158 @@ pub struct RunningLobbyState {
     pub boundaries: domain::Boundaries,
     pub counter: u32,
     pub cons: HashSet<Con>,
+    // everyone who started the game, including dead and left
+    pub players: HashMap<Con, RunningPlayer>,
+    pub settings: LobbySettings,
+    pub game_over: Option<GameOver>,
+    // boundaries to shrink to on the tick
+    pub upcoming_boundaries: Option<(u32, domain::Boundaries)>,
+    // in king of the hill mode
+    pub zones: Vec<CaptureZone>,
+    pub portals: Vec<domain::Portal>,
+    // power-ups lying in the arena
+    pub pickups: Vec<domain::Pickup>,
+    // not yet broadcasted events
+    events: Vec<domain::GameEvent>,
+    // latest ticks without events, to be played again with late turns
+    history: VecDeque<Played>,
+}
+
+// tick as it was played
+#[derive(Clone)]
+struct Played {
+    // state before the tick, without history
+    before: RunningLobbyState,
+    // of foods and pickups dropped on the tick
+    seed: u64,
+    turns: Vec<(Con, domain::Direction)>,
+}
+
+#[derive(Clone)]
+pub struct RunningPlayer {
+    pub user_name: UserName,
+    pub color: Color,
+    pub score: Score,
+    // tick of the last death, while without a snake
+    pub died_at: Option<u32>,
+    pub team: Option<TeamId>,
+    // boost input is held
+    pub boosting: bool,
+    // ticks spent boosting, for shedding
+    pub boost_ticks: u32,
+    // turns waiting for their tick, ordered by it
+    pub inputs: VecDeque<(u32, domain::Direction)>,
+    pub input_delay: InputDelay,
 }
 
-impl From<&PrepLobbyState> for RunningLobbyState {
-    fn from(PrepLobbyState { start_votes }: &PrepLobbyState) -> Self {
+impl RunningPlayer {
+    // nth player to enter the game
+    fn new(user_name: UserName, n: usize, team: Option<TeamId>) -> Self {
+        Self {
+            user_name,
+            color: match team {
+                Some(team) => team_color(team),
+                None => PLAYER_COLORS[n % PLAYER_COLORS.len()].into(),
+            },
+            score: Default::default(),
+            died_at: None,
+            team,
+            boosting: false,
+            boost_ticks: 0,
+            inputs: VecDeque::new(),
+            input_delay: Default::default(),
+        }
+    }
+}
+
+impl RunningLobbyState {
+    pub fn new(
+        PrepLobbyState {
+            start_votes,
+            settings,
+            teams,
+        }: &PrepLobbyState,
+        mut user_names: HashMap<Con, UserName>,
+    ) -> Self {
         #[allow(unused)]
         use domain::{Direction, Food, Foods, Pos, Sections, Snake};
 
         let cons = start_votes.keys().cloned().collect::<HashSet<_>>();
 
-        let snakes = {
-            let mut snakes = vec![];
-
-            for (i, con) in cons.iter().cloned().enumerate() {
-                let i = i as i32;
-                // generate symmetrical placement over Y axis
-                // leave one empty cell between snakes: | | | |
-                let x_offset = if i % 2 == 0 { 0 - i } else { 0 + i + 1 };
-                let y_offset = 3;
-
-                let sections = Sections::from_directions(
-                    Pos::new(x_offset, y_offset),
-                    (0..3).into_iter().map(|_| Direction::Up),
-                );
+        let players = {
+            let mut cons = cons.iter().cloned().collect::<Vec<_>>();
+            cons.sort();
 
-                let snake = Snake {
-                    sections,
-                    direction: Direction::Up,
-                };
+            cons.into_iter()
+                .enumerate()
+                .map(|(i, con)| {
+                    let user_name = user_names.remove(&con).expect("to be in sync");
+                    (
+                        con,
+                        RunningPlayer::new(user_name, i, teams.get(&con).cloned()),
+                    )
+                })
+                .collect::<HashMap<_, _>>()
+        };
 
-                snakes.push((con, snake));
-            }
+        let (boundaries, spawns) = plan_spawns(cons.len());
+
+        let snakes = {
+            // same order as player indexes, so teams alternate around the ring
+            let mut cons = cons.iter().cloned().collect::<Vec<_>>();
+            cons.sort();
 
-            snakes.into_iter().collect()
+            cons.into_iter()
+                .zip(spawns)
+                .map(|(con, sections)| {
+                    let snake = Snake {
+                        direction: sections.head().direction(),
+                        sections,
+                        id: Some(con),
+                        effects: Default::default(),
+                    };
+
+                    (con, snake)
+                })
+                .collect()
         };
 
         let foods = Foods::default();
 
-        /* ensure enough space for placements */
-        // 1 => 2
-        // 2 => 4
-        // 3 => 4
-        // 4 => 6
-        // 5 => 6
-        // ...
-        let min_x_space_radius = cons.len() + 2 - (cons.len() % 2);
+        let zones = match settings.hill {
+            Some(_) => capture_zones(&boundaries, cons.len()),
+            None => vec![],
+        };
 
-        let boundaries =
-            domain::Pos::new(0, 0).boundaries_in_radius(6.max(min_x_space_radius as _), 6);
+        let portals = match settings.portals {
+            true => portal_pairs(&boundaries),
+            false => vec![],
+        };
 
-        Self {
+        let mut events = vec![domain::GameEvent::GameStarted];
+        events.extend(
+            cons.iter()
+                .map(|con| domain::GameEvent::SnakeSpawned { id: *con }),
+        );
+
+        let mut state = Self {
             snakes,
             foods,
             boundaries,
             counter: 0,
             cons,
-        }
+            players,
+            settings: settings.clone(),
+            game_over: None,
+            upcoming_boundaries: None,
+            zones,
+            portals,
+            pickups: vec![],
+            events: vec![],
+            history: VecDeque::new(),
+        };
+        state.emit(events);
+        state
     }
 }
 
@@ -116,91 +428,1500 @@ pub fn leave_food_trace(snake: &domain::Snake, foods: &mut domain::Foods) {
 
 impl RunningLobbyState {
     pub fn advance(&mut self) {
-        // TODO do not spawn on current snake positions,
-        //
-        // TODO figure can still spawn on boundaries, seems like by one problem
-        fn refill_foods(foods: &mut domain::Foods, boundaries: &domain::Boundaries) {
-            // TODO use config value after Env::current() is optimized
-            if foods.count() < 10 {
-                use strum::IntoEnumIterator;
-                let figures = domain::figures::Figures::iter();
-
-                let figure = figures.choose(&mut rand::thread_rng()).unwrap();
-
-                let x = rand::thread_rng()
-                    .gen_range((boundaries.min.x)..(boundaries.max.x - (figure.x_dim() as i32)));
-                let y = rand::thread_rng()
-                    .gen_range((boundaries.min.y)..(boundaries.max.y - (figure.y_dim() as i32)));
-
-                for (i, row) in figure.to_iter().into_iter().enumerate() {
-                    for (j, col) in row.into_iter().enumerate() {
-                        if col.is_food() {
-                            let food = domain::Food::new(x + (j as i32), y + (i as i32));
-
-                            if boundaries.relation(food.pos()).is_inside() {
-                                foods.insert(food);
-                            }
-                        }
+        if self.game_over.is_some() {
+            return;
+        }
+
+        let turns = self.due_inputs();
+        self.play(turns, rand::random());
+    }
+
+    // one tick, remembered while nothing of it is broadcasted
+    fn play(&mut self, turns: Vec<(Con, domain::Direction)>, seed: u64) {
+        fn refill_foods(
+            foods: &mut domain::Foods,
+            snakes: &HashMap<Con, domain::Snake>,
+            boundaries: &domain::Boundaries,
+            portals: &[domain::Portal],
+            settings: &LobbySettings,
+            // when power-ups are enabled
+            mut pickups: Option<&mut Vec<domain::Pickup>>,
+            seed: u64,
+        ) {
+            use domain::placement::FoodPlacement;
+
+            let taken = snakes
+                .values()
+                .flat_map(|snake| snake.iter_vertices())
+                .chain(portals.iter().flat_map(|portal| [portal.a, portal.b]))
+                .chain(
+                    pickups
+                        .iter()
+                        .flat_map(|pickups| pickups.iter().map(|p| p.pos)),
+                );
+            let placement = FoodPlacement::new(*boundaries, taken, settings.food_lifetime);
+
+            let target = placement.target(FOOD_DENSITY);
+            if foods.count() >= target {
+                return;
+            }
+
+            // the same when played again
+            let mut rng = StdRng::seed_from_u64(seed);
+            placement.fill(foods, target, settings.figures, &mut |n| {
+                rng.gen_range(0..n)
+            });
+
+            // power-ups drop along with some of the refills
+            if let Some(pickups) = pickups.as_mut() {
+                if pickups.len() < MAX_PICKUPS && rng.gen_ratio(1, PICKUP_CHANCE) {
+                    use domain::PowerUp;
+
+                    if let Some(pos) = placement.free_cells(foods).into_iter().choose(&mut rng) {
+                        let power_up = [PowerUp::Ghost, PowerUp::Shield, PowerUp::Magnet]
+                            .into_iter()
+                            .choose(&mut rng)
+                            .unwrap();
+                        pickups.push(domain::Pickup { pos, power_up });
                     }
                 }
             }
         }
 
-        use domain::AdvanceResult;
+        let history = std::mem::take(&mut self.history);
+        let before = self.clone();
+        self.history = history;
 
         self.counter += 1;
 
-        // indeces to remove
+        for snake in self.snakes.values_mut() {
+            snake.effects.expire();
+        }
+
+        self.shrink();
+        for (con, direction) in turns.iter() {
+            if let Some(snake) = self.snakes.get_mut(con) {
+                snake.set_direction(*direction).unwrap_or(());
+            }
+        }
+
+        let cons = self.snakes.keys().cloned().collect::<HashSet<_>>();
+        let mut events = self.step(&cons);
+
+        // boosted snakes make another step
+        let boosting = self.boosting();
+        events.extend(self.step(&boosting));
+        self.shed(&boosting);
+        self.pull_foods();
+        self.age_foods();
+
+        self.emit(events);
+        self.check_game_over();
+        self.score_zones();
+        self.check_hill_over();
+        self.check_time_over();
+
+        let pickups = self.settings.power_ups.then_some(&mut self.pickups);
+        refill_foods(
+            &mut self.foods,
+            &self.snakes,
+            &self.boundaries,
+            &self.portals,
+            &self.settings,
+            pickups,
+            seed,
+        );
+
+        if !self.events.is_empty() || self.game_over.is_some() {
+            self.history.clear();
+            return;
+        }
+        if self.history.len() == INPUT_WINDOW as usize {
+            self.history.pop_front();
+        }
+        self.history.push_back(Played {
+            before,
+            seed,
+            turns,
+        });
+    }
+
+    // move snakes of the given players by one cell,
+    // others stay in place and are hit by their current bodies
+    fn step(&mut self, movers: &HashSet<Con>) -> Vec<domain::GameEvent> {
+        use domain::GameEvent;
+
+        let teams = self
+            .players
+            .iter()
+            .filter_map(|(con, player)| Some((*con, player.team?)))
+            .collect::<HashMap<_, _>>();
+        let teammate_collision = self
+            .settings
+            .teams
+            .as_ref()
+            .map(|teams| teams.teammate_collision);
+
         let mut rm = vec![];
+        let mut events = vec![];
 
         let other_snakes = self.snakes.clone();
-        for (i, snake) in self.snakes.values_mut().enumerate() {
+        for (con, snake) in self.snakes.iter_mut() {
+            if !movers.contains(con) {
+                continue;
+            }
+
             let other_snakes = other_snakes
+                .iter()
+                .filter(|(other, _)| *other != con)
+                .map(|(_, snake)| snake.clone());
+
+            let team = teams.get(con);
+            let (teammates, other_snakes): (Vec<_>, Vec<_>) = other_snakes.partition(|other| {
+                team.is_some() && other.id.and_then(|id| teams.get(&id)) == team
+            });
+
+            let moves = |other: &domain::Snake| other.id.map_or(true, |id| movers.contains(&id));
+            let hits = |other: &domain::Snake| match moves(other) {
+                true => snake.bites(other, &self.portals),
+                false => snake.hits(other, &self.portals),
+            };
+
+            if teammate_collision == Some(TeammateCollision::Block) && teammates.iter().any(hits) {
+                continue;
+            }
+
+            let (moving, still): (Vec<_>, Vec<_>) = other_snakes.into_iter().partition(moves);
+            let len = snake.sections.len();
+
+            let result = match still.iter().find(|other| snake.hits(other, &self.portals)) {
+                Some(other) => snake.absorb(domain::AdvanceResult::BitSomeone(other.id)),
+                None => snake.advance(
+                    &mut self.foods,
+                    moving.as_slice(),
+                    &self.boundaries,
+                    &self.portals,
+                ),
+            };
+
+            match result.death() {
+                None => {
+                    if snake.sections.len() > len {
+                        events.push(GameEvent::FoodEaten {
+                            id: *con,
+                            pos: snake.mouth(),
+                        });
+                    }
+                    let mouth = snake.mouth();
+                    if let Some(i) = self.pickups.iter().position(|pickup| pickup.pos == mouth) {
+                        let power_up = self.pickups.swap_remove(i).power_up;
+                        snake.effects.apply(power_up);
+                        events.push(GameEvent::PowerUpPicked { id: *con, power_up });
+                    }
+                }
+                Some((cause, killer)) => {
+                    rm.push(*con);
+                    if let Some(player) = self.players.get_mut(con) {
+                        player.died_at = Some(self.counter);
+                    }
+                    leave_food_trace(snake, &mut self.foods);
+                    events.push(GameEvent::SnakeDied {
+                        victim: *con,
+                        cause,
+                        killer,
+                    });
+                }
+            }
+        }
+
+        self.snakes.retain(|con, _| !rm.contains(con));
+
+        events
+    }
+
+    // alive players holding boost, with snakes long enough to shed
+    pub fn boosting(&self) -> HashSet<Con> {
+        if self.settings.boost.is_none() {
+            return HashSet::new();
+        }
+
+        self.players
+            .iter()
+            .filter(|(_, player)| player.boosting)
+            .filter(|(con, _)| {
+                self.snakes
+                    .get(con)
+                    .map_or(false, |snake| snake.sections.len() > BOOST_MIN_SECTIONS)
+            })
+            .map(|(con, _)| *con)
+            .collect()
+    }
+
+    // boosted snakes drop tail cells as food, once per configured number of ticks
+    fn shed(&mut self, boosting: &HashSet<Con>) {
+        let Some(boost) = self.settings.boost.clone() else {
+            return;
+        };
+
+        for con in boosting {
+            let (Some(player), Some(snake)) = (self.players.get_mut(con), self.snakes.get_mut(con))
+            else {
+                continue;
+            };
+
+            player.boost_ticks += 1;
+            if player.boost_ticks % boost.shed_every.max(1) == 0 {
+                if let Some(pos) = snake.shed_tail() {
+                    self.foods.insert(domain::Food::from(pos));
+                }
+            }
+        }
+    }
+
+    // magnets pull foods toward mouths
+    fn pull_foods(&mut self) {
+        for snake in self.snakes.values() {
+            if snake.effects.magnet() {
+                self.foods
+                    .pull(snake.mouth(), domain::Effects::MAGNET_RADIUS);
+            }
+        }
+    }
+
+    // expire old foods, the rest may run from nearby heads
+    fn age_foods(&mut self) {
+        self.foods.tick();
+
+        if let Some(every) = self.settings.food_flee_every {
+            let heads = self
+                .snakes
                 .values()
-                .enumerate()
-                .filter(|(_i, _)| *_i != i)
-                .map(|(_, snake)| snake.clone())
+                .map(|snake| snake.mouth())
                 .collect::<Vec<_>>();
+            let blocked = self
+                .snakes
+                .values()
+                .flat_map(|snake| snake.iter_vertices())
+                .chain(self.portals.iter().flat_map(|portal| [portal.a, portal.b]))
+                .chain(self.pickups.iter().map(|pickup| pickup.pos))
+                .collect::<HashSet<_>>();
 
-            match snake.advance(&mut self.foods, other_snakes.as_slice(), &self.boundaries) {
-                AdvanceResult::Success => {}
-                AdvanceResult::BitYaSelf
-                | AdvanceResult::BitSomeone
-                | AdvanceResult::OutOfBounds => {
-                    rm.push(i);
-                    leave_food_trace(snake, &mut self.foods);
+            self.foods.flee(every, &heads, &self.boundaries, |pos| {
+                blocked.contains(&pos)
+            });
+        }
+    }
+
+    // apply upcoming boundaries when it's time, then announce the next ones
+    fn shrink(&mut self) {
+        use domain::GameEvent;
+
+        let Some(shrinking) = self.settings.shrinking.clone() else {
+            return;
+        };
+
+        match self.upcoming_boundaries {
+            Some((tick, boundaries)) if tick <= self.counter => {
+                self.boundaries = boundaries;
+                self.upcoming_boundaries = None;
+
+                let outside = |pos| !boundaries.relation(pos).is_inside();
+
+                let caught = self
+                    .snakes
+                    .iter()
+                    .filter(|(_, snake)| snake.iter_vertices().any(outside))
+                    .map(|(con, _)| *con)
+                    .collect::<Vec<_>>();
+
+                let mut events = vec![];
+                for con in caught {
+                    let snake = self.snakes.remove(&con).expect("to exist");
+                    leave_food_trace(&snake, &mut self.foods);
+                    if let Some(player) = self.players.get_mut(&con) {
+                        player.died_at = Some(self.counter);
+                    }
+                    events.push(GameEvent::SnakeDied {
+                        victim: con,
+                        cause: domain::DeathCause::OutOfBounds,
+                        killer: None,
+                    });
                 }
+
+                let foods = std::mem::take(&mut self.foods);
+                self.foods
+                    .extend(foods.iter().cloned().filter(|food| !outside(food.pos())));
+                self.portals
+                    .retain(|portal| !outside(portal.a) && !outside(portal.b));
+                self.pickups.retain(|pickup| !outside(pickup.pos));
+
+                self.emit(events);
             }
+            _ => {}
         }
 
-        let mut idx = 0;
-        self.snakes.retain(|_, _| {
-            let retain = !rm.contains(&idx);
-            idx += 1;
-            retain
-        });
+        if self.upcoming_boundaries.is_none() && shrinking.interval > 0 {
+            let b = self.boundaries;
+            let next = domain::Boundaries {
+                min: domain::Pos::new(b.min.x + 1, b.min.y + 1),
+                max: domain::Pos::new(b.max.x - 1, b.max.y - 1),
+            };
+            let tick = (self.counter / shrinking.interval + 1) * shrinking.interval;
 
-        refill_foods(&mut self.foods, &self.boundaries);
+            if tick - self.counter <= shrinking.warning
+                && next.width().min(next.height()) >= SHRINK_MIN_SIZE
+            {
+                self.upcoming_boundaries = Some((tick, next));
+            }
+        }
     }
 
-    pub fn set_con_direction(&mut self, con: Con, direction: domain::Direction) {
-        if self.snakes.contains_key(&con) {
-            self.snakes
-                .get_mut(&con)
-                .unwrap()
-                .set_direction(direction)
-                .unwrap_or(());
-            tracing::info!("set direction {:?}", direction);
+    // team mode game ends when at most one team can still play,
+    // free-for-all series round when at most one player can
+    fn check_game_over(&mut self) {
+        if self.game_over.is_some() {
+            return;
+        }
+
+        if self.settings.teams.is_none() {
+            if self.settings.series.is_some() && self.settings.respawn_cooldown.is_none() {
+                self.check_last_standing();
+            }
+            return;
+        }
+
+        let started = self
+            .players
+            .values()
+            .filter_map(|player| player.team)
+            .collect::<HashSet<_>>();
+
+        let respawns = self.settings.respawn_cooldown.is_some();
+        let alive = self
+            .players
+            .iter()
+            .filter(|(con, _)| {
+                self.snakes.contains_key(con) || (respawns && self.cons.contains(con))
+            })
+            .filter_map(|(_, player)| player.team)
+            .collect::<HashSet<_>>();
+
+        if started.len() >= 2 && alive.len() <= 1 {
+            let winner = alive.into_iter().next().map(domain::Winner::Team);
+            self.end_game(winner);
+        }
+    }
+
+    fn check_last_standing(&mut self) {
+        let mut alive = self.snakes.keys();
+
+        if self.players.len() >= 2 {
+            if let (winner, None) = (alive.next(), alive.next()) {
+                let winner = winner.cloned().map(domain::Winner::Player);
+                self.end_game(winner);
+            }
+        }
+    }
+
+    // zone is held by the only snake with any cell inside it
+    fn score_zones(&mut self) {
+        if self.game_over.is_some() {
+            return;
+        }
+
+        for zone in self.zones.iter_mut() {
+            let mut occupants = self
+                .snakes
+                .iter()
+                .filter(|(_, snake)| snake.iter_vertices().any(|pos| zone.contains(pos)))
+                .map(|(con, _)| *con);
+
+            zone.holder = match (occupants.next(), occupants.next()) {
+                (Some(con), None) => Some(con),
+                _ => None,
+            };
+
+            if let Some(player) = zone.holder.and_then(|con| self.players.get_mut(&con)) {
+                player.score.zone_points += ZONE_SCORE;
+                player.score.score += ZONE_SCORE;
+            }
+        }
+    }
+
+    // first to the target score wins, or the leader at the time limit
+    fn check_hill_over(&mut self) {
+        let Some(hill) = self.settings.hill.clone() else {
+            return;
+        };
+
+        if self.game_over.is_some() {
+            return;
+        }
+
+        let (winner, (best, _)) = self.leader(|score| (score.zone_points, 0));
+
+        if (winner.is_some() && best >= hill.target_score) || self.counter >= hill.time_limit {
+            self.end_game(winner);
+        }
+    }
+
+    // timed mode game ends when the clock runs out
+    fn check_time_over(&mut self) {
+        let Some(timed) = &self.settings.timed else {
+            return;
+        };
+
+        if self.game_over.is_some() || self.counter < timed.ticks {
+            return;
+        }
+
+        let (winner, _) = self.leader(|score| self.rank(score));
+        self.end_game(winner);
+    }
+
+    // the only best contender by key, teams in team mode or players otherwise,
+    // with the best key
+    fn leader(&self, key: impl Fn(&Score) -> (u32, u32)) -> (Option<domain::Winner>, (u32, u32)) {
+        let mut totals = HashMap::new();
+        for (con, player) in self.players.iter() {
+            let contender = match player.team {
+                Some(team) => domain::Winner::Team(team),
+                None => domain::Winner::Player(*con),
+            };
+            let (primary, secondary) = key(&player.score);
+            let total = totals.entry(contender).or_insert((0, 0));
+            total.0 += primary;
+            total.1 += secondary;
+        }
+
+        let best = totals.values().max().cloned().unwrap_or_default();
+        let mut leaders = totals
+            .into_iter()
+            .filter(|(_, total)| *total == best)
+            .map(|(contender, _)| contender);
+
+        let winner = match (leaders.next(), leaders.next()) {
+            (Some(leader), None) => Some(leader),
+            _ => None,
+        };
+
+        (winner, best)
+    }
+
+    // what players are ranked by in the current mode, greater is better
+    fn rank(&self, score: &Score) -> (u32, u32) {
+        use interfacing::snake::lobby_state::TimedRanking;
+
+        match (&self.settings.hill, &self.settings.timed) {
+            (Some(_), _) => (score.zone_points, score.score),
+            (None, Some(timed)) => match timed.ranking {
+                TimedRanking::Length => (score.length, score.kills),
+                TimedRanking::Score => (score.score, score.kills),
+            },
+            (None, None) => (score.score, score.kills),
         }
     }
 
-    // no join_con because joining midgame is forbidden
+    fn end_game(&mut self, winner: Option<domain::Winner>) {
+        let mut standings = self.players.keys().cloned().collect::<Vec<_>>();
+        standings.sort_by_key(|con| (std::cmp::Reverse(self.rank(&self.players[con].score)), *con));
+
+        self.game_over = Some(GameOver { winner, standings });
+        self.emit([domain::GameEvent::GameEnded { winner }]);
+    }
+
+    // to the nearest time limit
+    pub fn ticks_left(&self) -> Option<u32> {
+        let hill = self.settings.hill.as_ref().map(|hill| hill.time_limit);
+        let timed = self.settings.timed.as_ref().map(|timed| timed.ticks);
+        let limit = hill.into_iter().chain(timed).min()?;
+        Some(limit.saturating_sub(self.counter))
+    }
+
+    // ignored when boost is disabled
+    pub fn set_con_boost(&mut self, con: Con, boosting: bool) {
+        if self.settings.boost.is_none() {
+            return;
+        }
+
+        if let Some(player) = self.players.get_mut(&con) {
+            player.boosting = boosting;
+        }
+        self.history.clear();
+    }
+
+    // turn on the given tick, see INPUT_WINDOW for late ones
+    pub fn set_con_direction(&mut self, con: Con, direction: domain::Direction, tick: u32) {
+        let next = self.counter + 1;
+        let Some(player) = self.players.get_mut(&con) else {
+            return;
+        };
+
+        let stats = &mut player.input_delay;
+        stats.turns += 1;
+
+        let delay = next.saturating_sub(tick);
+        if delay > INPUT_WINDOW || tick > next + INPUT_WINDOW || player.inputs.len() >= INPUT_BUFFER
+        {
+            stats.dropped += 1;
+            return;
+        }
+
+        if delay > 0 {
+            stats.late += 1;
+        }
+        stats.total_ticks += delay;
+        stats.max_ticks = stats.max_ticks.max(delay);
+
+        if delay > 0 && self.rewind(con, direction, tick) {
+            return;
+        }
+
+        let player = self.players.get_mut(&con).expect("checked above");
+        let at = player.inputs.partition_point(|(t, _)| *t <= tick);
+        player.inputs.insert(at, (tick, direction));
+        tracing::info!("set direction {:?} for tick {}", direction, tick);
+    }
+
+    // turns of the earliest buffered tick which is due on the next one, later ones wait
+    // so that late turns do not overwrite each other
+    fn due_inputs(&mut self) -> Vec<(Con, domain::Direction)> {
+        let next = self.counter + 1;
+        let mut turns = vec![];
+
+        for (con, player) in self.players.iter_mut() {
+            let Some(&(due, _)) = player.inputs.front() else {
+                continue;
+            };
+            if due > next {
+                continue;
+            }
+
+            while let Some((_, direction)) =
+                player.inputs.front().copied().filter(|(t, _)| *t == due)
+            {
+                turns.push((*con, direction));
+                player.inputs.pop_front();
+            }
+        }
+        turns
+    }
+
+    // plays the ticks from the given one again with the turn,
+    // false when it is not remembered anymore
+    fn rewind(&mut self, con: Con, direction: domain::Direction, tick: u32) -> bool {
+        let Some(from) = self
+            .history
+            .iter()
+            .position(|played| played.before.counter + 1 == tick)
+        else {
+            return false;
+        };
+
+        let mut replay = self.history.split_off(from);
+        let kept = std::mem::take(&mut self.history);
+        let mut first = replay.pop_front().expect("found above");
+        first.turns.push((con, direction));
+
+        // buffered turns and delay stats are not rewound
+        let mut current = std::mem::replace(self, first.before);
+        self.history = kept;
+        self.events = std::mem::take(&mut current.events);
+        for (con, player) in self.players.iter_mut() {
+            if let Some(current) = current.players.get_mut(con) {
+                player.inputs = std::mem::take(&mut current.inputs);
+                player.input_delay = current.input_delay.clone();
+            }
+        }
+
+        self.play(first.turns, first.seed);
+        for played in replay {
+            if self.game_over.is_some() {
+                break;
+            }
+            self.play(played.turns, played.seed);
+        }
+        true
+    }
+
+    // only when allowed by settings
+    pub fn join_con(&mut self, con: Con, user_name: UserName) -> Result<(), String> {
+        if !self.settings.mid_game_join {
+            return Err("Joining running game is not allowed".into());
+        }
+
+        if self.game_over.is_some() {
+            return Err("Game is over".into());
+        }
+
+        let snake = self.spawn_snake(con).ok_or("No space to spawn")?;
+
+        let team = self.settings.teams.as_ref().map(|teams| {
+            let members = self
+                .players
+                .iter()
+                .filter(|(con, _)| self.cons.contains(con))
+                .filter_map(|(_, player)| player.team);
+            smallest_team(members, teams.count)
+        });
+
+        let n = self.players.len();
+        let player = self
+            .players
+            .entry(con)
+            .or_insert_with(|| RunningPlayer::new(user_name, n, team));
+        player.died_at = None;
+
+        self.cons.insert(con);
+        self.snakes.insert(con, snake);
+        self.emit([domain::GameEvent::SnakeSpawned { id: con }]);
+
+        Ok(())
+    }
 
     pub fn remove_con(&mut self, con: &Con) {
-        if let Some(snake) = self.snakes.get(con) {
-            leave_food_trace(snake, &mut self.foods);
+        self.history.clear();
+        if let Some(snake) = self.snakes.remove(con) {
+            leave_food_trace(&snake, &mut self.foods);
+            self.emit([domain::GameEvent::SnakeDied {
+                victim: *con,
+                cause: domain::DeathCause::Left,
+                killer: None,
+            }]);
         }
         self.cons.remove(con);
-        self.snakes.remove(con);
+        self.check_game_over();
+    }
+
+    pub fn respawn_in(&self, con: &Con) -> Option<u32> {
+        let cooldown = self.settings.respawn_cooldown?;
+        let died_at = self.players.get(con)?.died_at?;
+        Some((died_at + cooldown).saturating_sub(self.counter))
+    }
+
+    pub fn respawn(&mut self, con: Con) -> Result<(), String> {
+        if self.game_over.is_some() {
+            return Err("Game is over".into());
+        }
+
+        match self.respawn_in(&con) {
+            _ if self.settings.respawn_cooldown.is_none() => {
+                return Err("Respawns are disabled".into())
+            }
+            None => return Err("Snake is alive".into()),
+            Some(0) => {}
+            Some(ticks) => return Err(format!("Respawn available in {ticks} ticks")),
+        }
+
+        let snake = self.spawn_snake(con).ok_or("No space to respawn")?;
+        if let Some(player) = self.players.get_mut(&con) {
+            player.died_at = None;
+        }
+        self.snakes.insert(con, snake);
+        self.emit([domain::GameEvent::SnakeSpawned { id: con }]);
+
+        Ok(())
+    }
+
+    // straight snake on free cells with some space ahead,
+    // preferably far from other mouths
+    fn spawn_snake(&self, con: Con) -> Option<domain::Snake> {
+        use domain::{Direction, Pos, Sections, Snake};
+
+        let occupied = self
+            .snakes
+            .values()
+            .flat_map(|snake| snake.iter_vertices())
+            .chain(self.portals.iter().flat_map(|portal| [portal.a, portal.b]))
+            .collect::<HashSet<_>>();
+        let mouths = self.snakes.values().map(|snake| snake.mouth());
+        let b = self.boundaries;
+
+        let mut candidates = vec![];
+
+        for x in (b.min.x + 1)..b.max.x {
+            for y in (b.min.y + 1)..b.max.y {
+                let mouth = Pos::new(x, y);
+
+                for direction in [
+                    Direction::Up,
+                    Direction::Bottom,
+                    Direction::Left,
+                    Direction::Right,
+                ] {
+                    let tail =
+                        (0..SPAWN_SECTIONS).fold(mouth, |pos, _| pos.to(direction.opposite()));
+                    let ahead = (0..SPAWN_WALL_DISTANCE).scan(mouth, |pos, _| {
+                        *pos = pos.to(direction);
+                        Some(*pos)
+                    });
+                    let sections =
+                        Sections::from_directions(tail, (0..SPAWN_SECTIONS).map(|_| direction));
+
+                    let free = sections
+                        .iter_vertices()
+                        .chain(ahead)
+                        .all(|pos| b.relation(pos).is_inside() && !occupied.contains(&pos));
+
+                    if free {
+                        let distance = mouths
+                            .clone()
+                            .map(|m| (m.x - mouth.x).abs() + (m.y - mouth.y).abs())
+                            .min()
+                            .unwrap_or(i32::MAX);
+
+                        candidates.push((distance, sections, direction));
+                    }
+                }
+            }
+        }
+
+        let safe = candidates
+            .iter()
+            .filter(|(distance, ..)| *distance >= SPAWN_HEAD_DISTANCE)
+            .collect::<Vec<_>>();
+
+        let (_, sections, direction) = safe
+            .into_iter()
+            .choose(&mut rand::thread_rng())
+            .or_else(|| candidates.iter().max_by_key(|(distance, ..)| *distance))?
+            .clone();
+
+        Some(Snake {
+            sections,
+            direction,
+            id: Some(con),
+            effects: Default::default(),
+        })
+    }
+
+    // drain events to broadcast
+    pub fn take_events(&mut self) -> Vec<domain::GameEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    // account events to scores and schedule for broadcast
+    fn emit(&mut self, events: impl IntoIterator<Item = domain::GameEvent>) {
+        use domain::GameEvent;
+
+        for event in events {
+            match &event {
+                GameEvent::FoodEaten { id, .. } => {
+                    if let Some(player) = self.players.get_mut(id) {
+                        player.score.foods_eaten += 1;
+                        player.score.score += FOOD_SCORE;
+                    }
+                }
+                GameEvent::SnakeDied {
+                    killer: Some(killer),
+                    ..
+                } => {
+                    if let Some(player) = self.players.get_mut(killer) {
+                        player.score.kills += 1;
+                        player.score.score += KILL_SCORE;
+                    }
+                }
+                GameEvent::SnakeDied { killer: None, .. }
+                | GameEvent::PowerUpPicked { .. }
+                | GameEvent::GameStarted
+                | GameEvent::SnakeSpawned { .. }
+                | GameEvent::GameEnded { .. } => {}
+            }
+
+            // ticks before an event are not played again
+            self.history.clear();
+            self.events.push(event);
+        }
+
+        self.update_lengths();
+    }
+
+    fn update_lengths(&mut self) {
+        for (con, player) in self.players.iter_mut() {
+            player.score.length = self
+                .snakes
+                .get(con)
+                .map(|snake| snake.iter_vertices().count() as u32)
+                .unwrap_or(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use self::domain::{DeathCause, Direction, GameEvent, Pos, Sections, Snake};
+    use super::*;
+
+    fn prep(cons: &[Con], settings: LobbySettings) -> PrepLobbyState {
+        let mut prep = PrepLobbyState::default();
+        prep.set_settings(settings);
+        for con in cons {
+            prep.join_con(*con);
+        }
+        prep
+    }
+
+    fn running(cons: &[Con]) -> RunningLobbyState {
+        to_running(&prep(cons, Default::default()))
+    }
+
+    fn to_running(prep: &PrepLobbyState) -> RunningLobbyState {
+        let user_names = prep
+            .start_votes
+            .keys()
+            .map(|con| (*con, format!("{con}")))
+            .collect();
+        prep.to_running(user_names)
+    }
+
+    fn teams(teammate_collision: TeammateCollision) -> LobbySettings {
+        use interfacing::snake::lobby_state::TeamSettings;
+
+        LobbySettings {
+            teams: Some(TeamSettings {
+                count: 2,
+                teammate_collision,
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn snake_at(
+        id: Con,
+        start: Pos,
+        sections: impl IntoIterator<Item = Direction>,
+        direction: Direction,
+    ) -> Snake {
+        Snake {
+            sections: Sections::from_directions(start, sections),
+            direction,
+            id: Some(id),
+            effects: Default::default(),
+        }
+    }
+
+    // 1 moves right into the body of 2
+    fn collide(s: &mut RunningLobbyState) {
+        s.snakes.insert(
+            1,
+            snake_at(1, Pos::new(0, 0), [Direction::Right], Direction::Right),
+        );
+        s.snakes.insert(
+            2,
+            snake_at(
+                2,
+                Pos::new(2, 2),
+                [Direction::Up, Direction::Up],
+                Direction::Up,
+            ),
+        );
+    }
+
+    #[test]
+    fn power_ups() {
+        use self::domain::{Food, Pickup, PowerUp};
+
+        // ghost passes through 2
+        let mut s = running(&[1, 2]);
+        collide(&mut s);
+        s.snakes.get_mut(&1).unwrap().effects.apply(PowerUp::Ghost);
+        s.advance();
+        assert_eq!(s.snakes[&1].mouth(), Pos::new(2, 0));
+
+        // shield absorbs the bite once
+        let mut s = running(&[1, 2]);
+        collide(&mut s);
+        s.snakes.get_mut(&1).unwrap().effects.apply(PowerUp::Shield);
+        s.advance();
+        assert_eq!(s.snakes[&1].mouth(), Pos::new(1, 0));
+        assert!(!s.snakes[&1].effects.shield);
+
+        // magnet is picked up and pulls food
+        let mut s = running(&[1, 2]);
+        collide(&mut s);
+        s.snakes.remove(&2);
+        s.take_events();
+        s.foods.values.clear();
+        s.foods.insert(Food::new(5, 0));
+        s.pickups.push(Pickup {
+            pos: Pos::new(2, 0),
+            power_up: PowerUp::Magnet,
+        });
+        s.advance();
+        assert!(s.pickups.is_empty());
+        assert!(s.snakes[&1].effects.magnet());
+        assert!(s.foods.has_pos(Pos::new(4, 0)));
+        assert!(s.take_events().contains(&GameEvent::PowerUpPicked {
+            id: 1,
+            power_up: PowerUp::Magnet,
+        }));
+    }
+
+    #[test]
+    fn spawn_planner() {
+        let mut widths = vec![];
+
+        for players in 1..=16 {
+            let (b, spawns) = plan_spawns(players);
+            assert_eq!(spawns.len(), players);
+            widths.push(b.width());
+
+            for (i, sections) in spawns.iter().enumerate() {
+                let mouth = sections.head().end();
+                let direction = sections.head().direction();
+
+                // whole body and the cells ahead are inside, away from the walls
+                let ahead = (1..=SPAWN_WALL_DISTANCE)
+                    .map(|k| (0..k).fold(mouth, |pos, _| pos.to(direction)));
+                for pos in sections.iter_vertices().chain([mouth]).chain(ahead) {
+                    assert!(pos.x > b.min.x && pos.x < b.max.x, "{players}: {pos:?}");
+                    assert!(pos.y > b.min.y && pos.y < b.max.y, "{players}: {pos:?}");
+                }
+
+                // other snakes are far from the mouth
+                for other in spawns.iter().enumerate().filter(|(j, _)| *j != i) {
+                    let closest = other
+                        .1
+                        .iter_vertices()
+                        .chain([other.1.head().end()])
+                        .map(|pos| mouth.distance(pos))
+                        .min()
+                        .unwrap();
+                    assert!(
+                        closest >= SPAWN_HEAD_DISTANCE as u32,
+                        "{players}: {closest}"
+                    );
+                }
+            }
+        }
+
+        assert_eq!(widths[0] as i32, 2 * MIN_ARENA_RADIUS);
+        assert!(widths.windows(2).all(|w| w[0] <= w[1]));
+        assert!(widths[15] > widths[1]);
+    }
+
+    #[test]
+    fn start_events() {
+        let mut s = running(&[1, 2]);
+
+        let events = s.take_events();
+        assert_eq!(events[0], GameEvent::GameStarted);
+        assert!(events.contains(&GameEvent::SnakeSpawned { id: 1 }));
+        assert!(events.contains(&GameEvent::SnakeSpawned { id: 2 }));
+        assert!(s.take_events().is_empty());
+    }
+
+    #[test]
+    fn kill_attribution() {
+        let mut s = running(&[1, 2]);
+        s.take_events();
+        collide(&mut s);
+
+        s.advance();
+
+        assert!(s.take_events().contains(&GameEvent::SnakeDied {
+            victim: 1,
+            cause: DeathCause::BitSomeone,
+            killer: Some(2),
+        }));
+        assert!(!s.snakes.contains_key(&1));
+        assert_eq!(s.players[&2].score.kills, 1);
+        assert_eq!(s.players[&1].score.length, 0);
+    }
+
+    #[test]
+    fn respawn() {
+        let mut s = running(&[1, 2]);
+        s.settings.respawn_cooldown = Some(2);
+
+        // 1 moves into the wall
+        let b = s.boundaries;
+        s.snakes.insert(
+            1,
+            snake_at(
+                1,
+                Pos::new(b.max.x - 2, 0),
+                [Direction::Right],
+                Direction::Right,
+            ),
+        );
+
+        s.advance();
+        assert!(!s.snakes.contains_key(&1));
+        assert_eq!(s.respawn_in(&1), Some(2));
+        assert!(s.respawn(1).is_err());
+
+        s.advance();
+        s.advance();
+        assert_eq!(s.respawn_in(&1), Some(0));
+        s.take_events();
+        assert!(s.respawn(1).is_ok());
+        assert_eq!(s.take_events(), vec![GameEvent::SnakeSpawned { id: 1 }]);
+
+        let mouth = s.snakes[&1].mouth();
+        let other = s.snakes[&2].mouth();
+        assert!((mouth.x - other.x).abs() + (mouth.y - other.y).abs() >= SPAWN_HEAD_DISTANCE);
+        assert_eq!(s.respawn_in(&1), None);
+        assert!(s.respawn(1).is_err());
+    }
+
+    #[test]
+    fn mid_game_join() {
+        let mut s = running(&[1]);
+        assert!(s.join_con(2, "2".into()).is_err());
+
+        s.settings.mid_game_join = true;
+        s.take_events();
+        assert!(s.join_con(2, "2".into()).is_ok());
+
+        assert!(s.cons.contains(&2));
+        assert_eq!(s.snakes[&2].id, Some(2));
+        assert_ne!(s.players[&2].color, s.players[&1].color);
+        assert_eq!(s.take_events(), vec![GameEvent::SnakeSpawned { id: 2 }]);
+    }
+
+    #[test]
+    fn team_assignment() {
+        let mut prep = prep(&[1, 2, 3], teams(TeammateCollision::PassThrough));
+        assert_eq!(prep.teams, HashMap::from([(1, 1), (2, 2), (3, 1)]));
+
+        assert!(prep.pick_team(3, 2).is_ok());
+        assert!(prep.pick_team(3, 3).is_err());
+        assert_eq!(prep.teams[&3], 2);
+
+        prep.set_settings(Default::default());
+        assert!(prep.teams.is_empty());
+        assert!(prep.pick_team(3, 1).is_err());
+    }
+
+    #[test]
+    fn teammates_pass_through() {
+        let mut prep = prep(&[1, 2, 3], teams(TeammateCollision::PassThrough));
+        prep.pick_team(2, 1).unwrap();
+        prep.pick_team(3, 2).unwrap();
+        let mut s = to_running(&prep);
+        collide(&mut s);
+        s.snakes.insert(
+            3,
+            snake_at(3, Pos::new(-4, 4), [Direction::Up], Direction::Up),
+        );
+        s.take_events();
+
+        s.advance();
+
+        assert_eq!(s.snakes[&1].mouth(), Pos::new(2, 0));
+        assert_eq!(s.game_over, None);
+
+        // last member of team 2 leaves
+        s.remove_con(&3);
+
+        assert_eq!(
+            s.game_over.as_ref().map(|game_over| game_over.winner),
+            Some(Some(domain::Winner::Team(1)))
+        );
+        assert!(s.take_events().contains(&GameEvent::GameEnded {
+            winner: Some(domain::Winner::Team(1))
+        }));
+    }
+
+    #[test]
+    fn teammates_block() {
+        let mut prep = prep(&[1, 2, 3], teams(TeammateCollision::Block));
+        prep.pick_team(2, 1).unwrap();
+        let mut s = to_running(&prep);
+        collide(&mut s);
+
+        s.advance();
+
+        assert_eq!(s.snakes[&1].mouth(), Pos::new(1, 0));
+    }
+
+    #[test]
+    fn shrinking() {
+        use interfacing::snake::lobby_state::ShrinkSettings;
+
+        let settings = LobbySettings {
+            shrinking: Some(ShrinkSettings {
+                interval: 4,
+                warning: 2,
+            }),
+            ..Default::default()
+        };
+        let mut s = to_running(&prep(&[1], settings));
+        let b = s.boundaries;
+
+        // 1 moves along the left wall
+        s.snakes.insert(
+            1,
+            snake_at(
+                1,
+                Pos::new(b.min.x + 1, 0),
+                [Direction::Bottom],
+                Direction::Bottom,
+            ),
+        );
+        s.foods.insert(domain::Food::new(b.max.x - 1, b.max.y - 1));
+
+        s.advance();
+        assert_eq!(s.upcoming_boundaries, None);
+
+        s.advance();
+        let next = domain::Boundaries {
+            min: Pos::new(b.min.x + 1, b.min.y + 1),
+            max: Pos::new(b.max.x - 1, b.max.y - 1),
+        };
+        assert_eq!(s.upcoming_boundaries, Some((4, next)));
+
+        s.advance();
+        assert!(s.snakes.contains_key(&1));
+
+        s.take_events();
+        s.advance();
+        assert_eq!(s.boundaries, next);
+        assert!(!s.snakes.contains_key(&1));
+        assert!(s.take_events().contains(&GameEvent::SnakeDied {
+            victim: 1,
+            cause: DeathCause::OutOfBounds,
+            killer: None,
+        }));
+        assert!(s
+            .foods
+            .iter()
+            .all(|food| next.relation(food.pos()).is_inside()));
+    }
+
+    #[test]
+    fn king_of_the_hill() {
+        use interfacing::snake::lobby_state::HillSettings;
+
+        let settings = LobbySettings {
+            hill: Some(HillSettings {
+                target_score: 3,
+                time_limit: 100,
+            }),
+            ..Default::default()
+        };
+        let mut s = to_running(&prep(&[1, 2], settings));
+        assert_eq!(s.zones.len(), 1);
+        let zone = s.zones[0].clone();
+
+        // 1 crosses the zone, 2 stays out of it
+        let y = zone.min.y + 1;
+        s.snakes.insert(
+            1,
+            snake_at(
+                1,
+                Pos::new(zone.min.x - 3, y),
+                [Direction::Right],
+                Direction::Right,
+            ),
+        );
+        s.snakes.insert(
+            2,
+            snake_at(2, Pos::new(5, 3), [Direction::Left], Direction::Left),
+        );
+
+        s.advance();
+        assert_eq!(s.zones[0].holder, None);
+
+        s.advance();
+        assert_eq!(s.zones[0].holder, Some(1));
+        assert_eq!(s.players[&1].score.zone_points, 1);
+
+        s.advance();
+        assert_eq!(s.game_over, None);
+
+        s.take_events();
+        s.advance();
+        let winner = Some(domain::Winner::Player(1));
+        assert_eq!(
+            s.game_over,
+            Some(GameOver {
+                winner,
+                standings: vec![1, 2]
+            })
+        );
+        assert!(s.take_events().contains(&GameEvent::GameEnded { winner }));
+
+        // contested zone is held by nobody, 2 stays off the row of 1
+        // which may have grown from foods on the way
+        s.game_over = None;
+        s.snakes.insert(
+            2,
+            snake_at(
+                2,
+                Pos::new(zone.min.x, zone.max.y + 2),
+                [Direction::Up],
+                Direction::Up,
+            ),
+        );
+
+        s.advance();
+        assert_eq!(s.zones[0].holder, None);
+    }
+
+    #[test]
+    fn timed_survival() {
+        use interfacing::snake::lobby_state::TimedSettings;
+
+        let settings = LobbySettings {
+            timed: Some(TimedSettings {
+                ticks: 3,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut s = to_running(&prep(&[1, 2], settings));
+        assert_eq!(s.ticks_left(), Some(3));
+
+        // 2 is longer
+        s.snakes.insert(
+            2,
+            snake_at(2, Pos::new(3, 5), [Direction::Up; 5], Direction::Up),
+        );
+
+        s.advance();
+        s.advance();
+        assert_eq!(s.game_over, None);
+        assert_eq!(s.ticks_left(), Some(1));
+
+        s.advance();
+        let winner = Some(domain::Winner::Player(2));
+        assert_eq!(
+            s.game_over,
+            Some(GameOver {
+                winner,
+                standings: vec![2, 1]
+            })
+        );
+        assert!(s.take_events().contains(&GameEvent::GameEnded { winner }));
+
+        // same length, kills decide
+        s.game_over = None;
+        s.players.get_mut(&1).unwrap().score.length = 4;
+        s.players.get_mut(&2).unwrap().score.length = 4;
+        s.players.get_mut(&1).unwrap().score.kills = 1;
+        assert_eq!(
+            s.leader(|score| s.rank(score)).0,
+            Some(domain::Winner::Player(1))
+        );
+    }
+
+    #[test]
+    fn portals() {
+        let settings = LobbySettings {
+            portals: true,
+            ..Default::default()
+        };
+        let mut s = to_running(&prep(&[1], settings));
+        let portal = s.portals[0];
+
+        // 1 enters the left portal
+        s.snakes.insert(
+            1,
+            snake_at(
+                1,
+                portal.a.to(Direction::Right).to(Direction::Right),
+                [Direction::Left],
+                Direction::Left,
+            ),
+        );
+
+        s.advance();
+
+        assert_eq!(s.snakes[&1].mouth(), portal.b);
+        assert!(s
+            .foods
+            .iter()
+            .all(|food| food.pos() != portal.a && food.pos() != portal.b));
+    }
+
+    #[test]
+    fn food_decay_and_fleeing() {
+        use self::domain::Food;
+
+        let settings = LobbySettings {
+            food_lifetime: Some(3),
+            food_flee_every: Some(1),
+            ..Default::default()
+        };
+        let mut s = to_running(&prep(&[1], settings));
+        s.snakes.insert(
+            1,
+            snake_at(1, Pos::new(0, 0), [Direction::Right], Direction::Right),
+        );
+        s.foods.values.clear();
+        s.foods.insert(Food {
+            lifetime: Some(2),
+            ..Food::new(4, 0)
+        });
+
+        // runs from the head at 2, 0
+        s.advance();
+        assert!(s.foods.has_pos(Pos::new(5, 0)));
+
+        // expired, refills have the lobby lifetime
+        s.advance();
+        assert!(s.foods.iter().all(|food| food.lifetime == Some(3)));
+    }
+
+    #[test]
+    fn tick_stamped_inputs() {
+        let mut s = running(&[1]);
+        s.snakes.insert(
+            1,
+            snake_at(1, Pos::new(0, 0), [Direction::Right], Direction::Right),
+        );
+        let mouth = |s: &RunningLobbyState| s.snakes[&1].mouth();
+
+        // early turn waits for its tick
+        s.set_con_direction(1, Direction::Up, 2);
+        s.advance();
+        assert_eq!(mouth(&s), Pos::new(2, 0));
+        s.advance();
+        assert_eq!(mouth(&s), Pos::new(2, -1));
+
+        // late turn after a tick with events not yet broadcasted is applied
+        // on the next tick, the one after it is not lost
+        s.set_con_direction(1, Direction::Left, 2);
+        s.set_con_direction(1, Direction::Up, 3);
+        s.advance();
+        assert_eq!(mouth(&s), Pos::new(1, -1));
+        s.advance();
+        assert_eq!(mouth(&s), Pos::new(1, -2));
+
+        // outside of the window
+        s.set_con_direction(1, Direction::Left, 0);
+        s.set_con_direction(1, Direction::Left, 20);
+        assert!(s.players[&1].inputs.is_empty());
+
+        let delay = &s.players[&1].input_delay;
+        assert_eq!((delay.turns, delay.late, delay.dropped), (5, 1, 2));
+        assert_eq!((delay.total_ticks, delay.max_ticks), (1, 1));
+        assert_eq!(delay.average(), 1. / 3.);
+    }
+
+    #[test]
+    fn late_input_replayed() {
+        use self::domain::{placement::FoodPlacement, Food};
+
+        for delay in 1..=INPUT_WINDOW {
+            let mut s = running(&[1]);
+            s.snakes.insert(
+                1,
+                snake_at(1, Pos::new(0, 0), [Direction::Right], Direction::Right),
+            );
+            s.take_events();
+
+            // foods off the way, enough of them not to be refilled
+            s.foods.values.retain(|pos, _| pos.y > 0);
+            let b = s.boundaries;
+            let target = FoodPlacement::new(b, std::iter::empty(), None).target(FOOD_DENSITY);
+            let free = ((b.min.x + 1)..b.max.x)
+                .flat_map(|x| (1..b.max.y).map(move |y| Pos::new(x, y)))
+                .filter(|pos| {
+                    s.snakes
+                        .values()
+                        .all(|snake| snake.iter_vertices().all(|v| v != *pos))
+                })
+                .collect::<Vec<_>>();
+            for pos in free {
+                if s.foods.count() >= target {
+                    break;
+                }
+                s.foods.insert(Food::new(pos.x, pos.y));
+            }
+
+            // turned on it, the snake reaches the food
+            let tick = 4 - delay as i32;
+            s.foods.insert(Food::new(tick, -1));
+
+            for _ in 0..3 {
+                s.advance();
+            }
+            assert_eq!(s.snakes[&1].mouth(), Pos::new(4, 0));
+            assert_eq!(s.history.len(), INPUT_WINDOW as usize);
+
+            s.set_con_direction(1, Direction::Up, tick as u32);
+            assert_eq!(s.counter, 3);
+            assert_eq!(s.snakes[&1].mouth(), Pos::new(tick, tick - 4));
+            assert_eq!(s.players[&1].score.foods_eaten, 1);
+            assert!(s.take_events().contains(&GameEvent::FoodEaten {
+                id: 1,
+                pos: Pos::new(tick, -1),
+            }));
+
+            s.advance();
+            assert_eq!(s.snakes[&1].mouth(), Pos::new(tick, tick - 5));
+            let input_delay = &s.players[&1].input_delay;
+            assert_eq!((input_delay.late, input_delay.max_ticks), (1, delay));
+        }
+    }
+
+    #[test]
+    fn boost() {
+        use interfacing::snake::lobby_state::BoostSettings;
+
+        let settings = LobbySettings {
+            boost: Some(BoostSettings { shed_every: 1 }),
+            ..Default::default()
+        };
+        let mut s = to_running(&prep(&[1, 2], settings));
+        s.foods.values.clear();
+
+        let b = s.boundaries;
+        let start = Pos {
+            x: b.min.x + 1,
+            y: (b.min.y + b.max.y) / 2,
+        };
+
+        // 1 boosts right, 2 is out of the way
+        s.snakes.insert(
+            1,
+            snake_at(1, start, [Direction::Right; 4], Direction::Right),
+        );
+        s.snakes.insert(
+            2,
+            snake_at(
+                2,
+                Pos {
+                    x: start.x,
+                    y: b.max.y - 1,
+                },
+                [Direction::Right; 2],
+                Direction::Right,
+            ),
+        );
+        s.set_con_boost(1, true);
+
+        let len = s.snakes[&1].sections.len();
+        s.advance();
+
+        let head = s.snakes[&1].mouth();
+        assert_eq!(head.x, start.x + 6);
+        assert_eq!(s.snakes[&1].sections.len(), len - 1);
+        assert!(s.foods.has_pos(s.snakes[&1].tail_end().to(Direction::Left)));
+
+        // 2 arrives at the cell 1 enters on its extra step
+        let cell = Pos {
+            x: head.x + 2,
+            y: head.y,
+        };
+        s.snakes.insert(
+            2,
+            snake_at(
+                2,
+                Pos {
+                    x: cell.x,
+                    y: cell.y + 4,
+                },
+                [Direction::Up; 3],
+                Direction::Up,
+            ),
+        );
+        s.advance();
+
+        assert!(!s.snakes.contains_key(&1));
+        assert_eq!(s.snakes[&2].mouth(), cell);
     }
 }
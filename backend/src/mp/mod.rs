@@ -6,7 +6,10 @@ pub use interfacing::snake_domain as domain;
 pub type Con = u16;
 
 type ServerMsg = WsMsg<interfacing::snake::WsServerMsg>;
-type Ch = tokio::sync::mpsc::UnboundedSender<ServerMsg>;
+type Ch = outbox::Outbox;
 
 pub mod con_state;
+pub mod connections;
+pub mod heartbeat;
 pub mod lobby;
+pub mod outbox;
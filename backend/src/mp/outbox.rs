@@ -0,0 +1,272 @@
+// Bounded queue of messages waiting to be written to a connection
+//
+// Lobby states are snapshots, the one not yet written is dropped for a newer one,
+// which goes to the back, so it never comes before events queued in between.
+// Connection whose queue stays long for too long, or overflows, is closed.
+
+use crate::mp::ServerMsg;
+use interfacing::snake::{CloseReason, WsMsg, WsServerMsg};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{watch, Notify};
+use tokio::time::Instant;
+
+// client is closed right away when even more is queued
+const CAPACITY: usize = 64;
+// client is behind with at least this many messages queued
+const BEHIND_DEPTH: usize = 16;
+const BEHIND_TIMEOUT: Duration = Duration::from_secs(5);
+
+static METRICS: Metrics = Metrics {
+    queued: AtomicUsize::new(0),
+    peak_depth: AtomicUsize::new(0),
+    replaced_states: AtomicUsize::new(0),
+    closed_laggards: AtomicUsize::new(0),
+};
+
+// of all connections since the start
+struct Metrics {
+    queued: AtomicUsize,
+    peak_depth: AtomicUsize,
+    replaced_states: AtomicUsize,
+    closed_laggards: AtomicUsize,
+}
+
+#[derive(Serialize, Debug)]
+pub struct QueueMetrics {
+    // messages waiting in all queues now
+    pub queued: usize,
+    // longest a single queue has been
+    pub peak_depth: usize,
+    pub replaced_states: usize,
+    pub closed_laggards: usize,
+}
+
+pub fn metrics() -> QueueMetrics {
+    QueueMetrics {
+        queued: METRICS.queued.load(Ordering::Relaxed),
+        peak_depth: METRICS.peak_depth.load(Ordering::Relaxed),
+        replaced_states: METRICS.replaced_states.load(Ordering::Relaxed),
+        closed_laggards: METRICS.closed_laggards.load(Ordering::Relaxed),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Closed;
+
+#[derive(Clone)]
+pub struct Outbox(Arc<Shared>);
+
+struct Shared {
+    queue: Mutex<Queue>,
+    // message queued
+    notify: Notify,
+    closed: watch::Sender<Option<CloseReason>>,
+}
+
+#[derive(Default)]
+struct Queue {
+    msgs: VecDeque<ServerMsg>,
+    // since the queue got long
+    behind_since: Option<Instant>,
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        let queue = self.queue.get_mut().unwrap();
+        METRICS
+            .queued
+            .fetch_sub(queue.msgs.len(), Ordering::Relaxed);
+    }
+}
+
+// unpinned state is not a response to anything, only the latest matters
+fn replaceable(msg: &ServerMsg) -> bool {
+    matches!(msg, WsMsg(None, WsServerMsg::LobbyState(_)))
+}
+
+impl Outbox {
+    pub fn new() -> Self {
+        Self(Arc::new(Shared {
+            queue: Default::default(),
+            notify: Notify::new(),
+            closed: watch::channel(None).0,
+        }))
+    }
+
+    pub fn send(&self, msg: ServerMsg) -> Result<(), Closed> {
+        if self.is_closed() {
+            return Err(Closed);
+        }
+
+        let mut queue = self.0.queue.lock().unwrap();
+
+        // stale state is dropped, not overwritten in place
+        if replaceable(&msg) {
+            if let Some(stale) = queue.msgs.iter().position(replaceable) {
+                queue.msgs.remove(stale);
+                METRICS.queued.fetch_sub(1, Ordering::Relaxed);
+                METRICS.replaced_states.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        if queue.msgs.len() >= CAPACITY {
+            drop(queue);
+            self.close_laggard("queue is full");
+            return Err(Closed);
+        }
+
+        queue.msgs.push_back(msg);
+        METRICS.queued.fetch_add(1, Ordering::Relaxed);
+        METRICS
+            .peak_depth
+            .fetch_max(queue.msgs.len(), Ordering::Relaxed);
+
+        if queue.msgs.len() >= BEHIND_DEPTH {
+            let since = *queue.behind_since.get_or_insert_with(Instant::now);
+            if since.elapsed() > BEHIND_TIMEOUT {
+                drop(queue);
+                self.close_laggard("queue stayed long");
+                return Err(Closed);
+            }
+        }
+
+        self.0.notify.notify_one();
+        Ok(())
+    }
+
+    // none once closed, queued messages are dropped then
+    pub async fn recv(&self) -> Option<ServerMsg> {
+        loop {
+            if self.is_closed() {
+                return None;
+            }
+
+            {
+                let mut queue = self.0.queue.lock().unwrap();
+                if let Some(msg) = queue.msgs.pop_front() {
+                    METRICS.queued.fetch_sub(1, Ordering::Relaxed);
+                    if queue.msgs.len() < BEHIND_DEPTH {
+                        queue.behind_since = None;
+                    }
+                    return Some(msg);
+                }
+            }
+
+            // permit is stored when notified in between
+            tokio::select! {
+                () = self.0.notify.notified() => {}
+                () = self.closed() => {}
+            }
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.0.queue.lock().unwrap().msgs.len()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.close_reason().is_some()
+    }
+
+    pub fn close_reason(&self) -> Option<CloseReason> {
+        *self.0.closed.borrow()
+    }
+
+    pub async fn closed(&self) {
+        let mut closed = self.0.closed.subscribe();
+        // sender lives in self
+        closed.wait_for(Option::is_some).await.unwrap();
+    }
+
+    // clones of the same outbox
+    pub fn same(&self, other: &Outbox) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    fn close_laggard(&self, reason: &str) {
+        tracing::warn!("closing slow connection, {reason}");
+        METRICS.closed_laggards.fetch_add(1, Ordering::Relaxed);
+        self.close(CloseReason::Kicked);
+    }
+
+    // the first reason is kept
+    pub fn close(&self, reason: CloseReason) {
+        let mut queue = self.0.queue.lock().unwrap();
+        METRICS
+            .queued
+            .fetch_sub(queue.msgs.len(), Ordering::Relaxed);
+        queue.msgs.clear();
+        self.0.closed.send_if_modified(|closed| {
+            let first = closed.is_none();
+            closed.get_or_insert(reason);
+            first
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interfacing::snake::LobbyState;
+
+    fn state() -> ServerMsg {
+        WsMsg::new(WsServerMsg::LobbyState(LobbyState::Terminated))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn replaces_states_and_closes_laggards() {
+        let outbox = Outbox::new();
+        outbox.send(state()).unwrap();
+        outbox.send(WsMsg::new(WsServerMsg::Ack)).unwrap();
+        outbox.send(state()).unwrap();
+        // pinned state is a response
+        outbox.send(state().id("1")).unwrap();
+        assert_eq!(outbox.depth(), 3);
+
+        assert_eq!(outbox.recv().await, Some(WsMsg::new(WsServerMsg::Ack)));
+        assert_eq!(outbox.recv().await, Some(state()));
+
+        // behind for too long
+        for _ in 0..BEHIND_DEPTH {
+            outbox.send(WsMsg::new(WsServerMsg::Ack)).unwrap();
+        }
+        tokio::time::advance(BEHIND_TIMEOUT * 2).await;
+        assert_eq!(outbox.send(WsMsg::new(WsServerMsg::Ack)), Err(Closed));
+        assert_eq!(outbox.recv().await, None);
+        outbox.closed().await;
+
+        let outbox = Outbox::new();
+        for _ in 0..CAPACITY {
+            outbox.send(WsMsg::new(WsServerMsg::Ack)).unwrap();
+        }
+        assert_eq!(outbox.send(WsMsg::new(WsServerMsg::Ack)), Err(Closed));
+        assert_eq!(outbox.close_reason(), Some(CloseReason::Kicked));
+    }
+
+    #[tokio::test]
+    async fn newer_state_comes_after_events() {
+        use interfacing::snake::lobby_state::{GameEvents, LobbyPrep};
+
+        let events = WsMsg::new(WsServerMsg::GameEvents(GameEvents {
+            counter: 1,
+            events: vec![],
+        }));
+        let newer = WsMsg::new(WsServerMsg::LobbyState(LobbyState::Prep(LobbyPrep {
+            participants: vec![],
+            settings: Default::default(),
+        })));
+
+        let outbox = Outbox::new();
+        outbox.send(state()).unwrap();
+        outbox.send(events.clone()).unwrap();
+        outbox.send(newer.clone()).unwrap();
+
+        assert_eq!(outbox.recv().await, Some(events));
+        assert_eq!(outbox.recv().await, Some(newer));
+        assert_eq!(outbox.depth(), 0);
+    }
+}
@@ -1,5 +1,6 @@
 use crate::server::UserConnectInfo;
 use crate::{conf::Conf, routes::imports::*};
+use axum::extract::ws::CloseFrame;
 use axum::extract::{
     connect_info::ConnectInfo,
     ws::{Message, WebSocket, WebSocketUpgrade},
@@ -8,23 +9,46 @@ use futures_util::{
     sink::SinkExt,
     stream::{SplitSink, SplitStream, StreamExt},
 };
-use interfacing::snake::WsMsg;
+use interfacing::snake::{CloseReason, WsMsg};
+use serde::Serialize;
 use std::sync::Arc;
-use tokio::sync::{mpsc, Mutex};
+use std::time::Duration;
+use tokio::sync::Mutex;
 
 use crate::mp::{
     con_state::ConState,
+    connections::{ConnectionMetrics, Connections},
+    heartbeat::{self, Heartbeat},
     lobby::{
+        actor::LobbyCmd,
         lobbies::{JoinLobbyError, Lobbies},
         lobby::Lobby,
         usernames::PlayerUserNames,
     },
+    outbox::{self, Outbox},
     Con,
 };
 
 // for debugging, to skip name entering everytime
 const AUTO_GEN_USER_NAME: bool = true;
 
+// for the writer to send the close frame, once the connection is closed
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+type SharedHeartbeat = Arc<std::sync::Mutex<Heartbeat>>;
+
+// what the reader and message handlers of a connection share
+#[derive(Clone)]
+struct ConCtx {
+    con: Con,
+    con_state: Arc<Mutex<ConState>>,
+    server_msg_sender: Outbox,
+    lobbies: Lobbies,
+    uns: PlayerUserNames,
+    connections: Connections,
+    heartbeat: SharedHeartbeat,
+}
+
 pub async fn ws(
     maybe_ws: Result<WebSocketUpgrade, axum::extract::ws::rejection::WebSocketUpgradeRejection>,
     ConnectInfo(con_info): ConnectInfo<UserConnectInfo>,
@@ -32,6 +56,7 @@ pub async fn ws(
     Extension(lobbies): Extension<Lobbies>,
     Extension(uns): Extension<PlayerUserNames>,
     Extension(conf): Extension<Conf>,
+    Extension(connections): Extension<Connections>,
 ) -> Response {
     let ws = match maybe_ws {
         Ok(ws) => ws,
@@ -53,11 +78,24 @@ pub async fn ws(
     // expected to be unique across current state
     let con = sock_addr.port();
 
-    ws.on_upgrade(move |socket| handle_socket(socket, con, lobbies, uns, conf))
+    ws.on_upgrade(move |socket| handle_socket(socket, con, lobbies, uns, conf, connections))
 }
 
 type ClientMsg = WsMsg<interfacing::snake::WsClientMsg>;
-type ServerMsg = WsMsg<interfacing::snake::WsServerMsg>;
+
+#[derive(Serialize)]
+pub struct Metrics {
+    queues: outbox::QueueMetrics,
+    connections: ConnectionMetrics,
+}
+
+// queue depths and round trips of all connections
+pub async fn metrics(Extension(connections): Extension<Connections>) -> Json<Metrics> {
+    Json(Metrics {
+        queues: outbox::metrics(),
+        connections: connections.metrics(),
+    })
+}
 
 async fn handle_socket(
     socket: WebSocket,
@@ -65,7 +103,16 @@ async fn handle_socket(
     lobbies: Lobbies,
     uns: PlayerUserNames,
     conf: Conf,
+    connections: Connections,
 ) {
+    let server_msg_sender = Outbox::new();
+
+    // old connection with the same con is gone, without having cleaned up
+    if connections.open(con, server_msg_sender.clone()) {
+        lobbies.disjoin_con(con).await;
+        uns.clean_con(con).await;
+    }
+
     let con_state = {
         let mut con_state = ConState::default();
 
@@ -81,26 +128,44 @@ async fn handle_socket(
         Arc::new(Mutex::new(con_state))
     };
 
-    let (server_msg_sender, server_msg_receiver) = mpsc::unbounded_channel::<ServerMsg>();
+    let heartbeat = Arc::new(std::sync::Mutex::new(Heartbeat::new()));
 
     let (sender, receiver) = socket.split();
     let rh = tokio::spawn(read(
         receiver,
-        con_state.clone(),
-        server_msg_sender.clone(),
-        lobbies.clone(),
-        con.clone(),
-        uns.clone(),
-        conf,
+        ConCtx {
+            con,
+            con_state,
+            server_msg_sender: server_msg_sender.clone(),
+            lobbies: lobbies.clone(),
+            uns: uns.clone(),
+            connections: connections.clone(),
+            heartbeat: heartbeat.clone(),
+        },
     ));
-    let wh = tokio::spawn(write(sender, server_msg_receiver));
+    let mut wh = tokio::spawn(write(sender, server_msg_sender.clone(), heartbeat));
+    let (ra, wa) = (rh.abort_handle(), wh.abort_handle());
 
     // as soon as a closed channel error returns from any of these procedures,
-    // cancel the other
-    () = tokio::select! {
-        _ = rh => (),
-        _ = wh => (),
+    // or the connection gets closed, cancel the other
+    let closed = tokio::select! {
+        _ = rh => false,
+        _ = &mut wh => false,
+        () = server_msg_sender.closed() => true,
     };
+    ra.abort();
+    if closed {
+        // writer sends the close frame with the reason
+        tokio::time::timeout(CLOSE_TIMEOUT, &mut wh).await.ok();
+    }
+    wa.abort();
+    // refuse what lobbies still send, a reason is kept if already closed
+    server_msg_sender.close(CloseReason::Shutdown);
+
+    // superseded connection leaves the con to the new one
+    if !connections.close(con, &server_msg_sender) {
+        return;
+    }
 
     // TODO investigate when port becomes free
     // undefined behavior is possible, if port can become free before this sections running
@@ -110,36 +175,50 @@ async fn handle_socket(
     uns.clean_con(con).await;
 }
 
-async fn read(
-    mut receiver: SplitStream<WebSocket>,
-    con_state: Arc<Mutex<ConState>>,
-    server_msg_sender: mpsc::UnboundedSender<ServerMsg>,
-    lobbies: Lobbies,
-    con: Con,
-    uns: PlayerUserNames,
-    conf: Conf,
-) {
+async fn read(mut receiver: SplitStream<WebSocket>, ctx: ConCtx) {
+    let ConCtx {
+        con,
+        server_msg_sender,
+        connections,
+        heartbeat,
+        ..
+    } = ctx.clone();
+
     loop {
-        match receiver.next().await {
+        let msg = receiver.next().await;
+        if let Some(Ok(_)) = msg {
+            heartbeat.lock().unwrap().seen();
+        }
+
+        match msg {
             Some(Ok(Message::Text(text))) => match serde_json::from_str::<ClientMsg>(&text) {
                 Ok(msg) => {
                     tracing::info!("Received message: {text:?}");
-                    tokio::task::spawn(handle_received_message(
-                        msg,
-                        con_state.clone(),
-                        server_msg_sender.clone(),
-                        lobbies.clone(),
-                        con.clone(),
-                        uns.clone(),
-                        conf.clone(),
-                    ));
+                    tokio::task::spawn(handle_received_message(msg, ctx.clone()));
                 }
                 Err(_) => {
                     tracing::info!("Received unexpected message: {text:?}");
+                    server_msg_sender.close(CloseReason::ProtocolError);
+                    return;
                 }
             },
-            Some(Ok(msg)) => {
+            Some(Ok(Message::Pong(payload))) => {
+                let rtt = heartbeat.lock().unwrap().pong(&payload);
+                if let Some(rtt) = rtt {
+                    tracing::debug!(con, "round trip {rtt:?}");
+                    connections.set_rtt(con, &server_msg_sender, rtt);
+                }
+            }
+            // answered by axum
+            Some(Ok(Message::Ping(_))) => {}
+            Some(Ok(Message::Close(frame))) => {
+                tracing::info!("Client closed connection: {frame:?}");
+                return;
+            }
+            Some(Ok(msg @ Message::Binary(_))) => {
                 tracing::info!("Received unhandled message: {msg:?}");
+                server_msg_sender.close(CloseReason::ProtocolError);
+                return;
             }
             Some(Err(_)) => {
                 tracing::info!("Client disconnected");
@@ -153,18 +232,17 @@ async fn read(
     }
 }
 
-async fn handle_received_message(
-    msg: ClientMsg,
-    con_state: Arc<Mutex<ConState>>,
-    server_msg_sender: mpsc::UnboundedSender<ServerMsg>,
-    lobbies: Lobbies,
-    con: Con,
-    uns: PlayerUserNames,
-    conf: Conf,
-) {
+async fn handle_received_message(msg: ClientMsg, ctx: ConCtx) {
     use interfacing::snake::{PinnedMessage, WsClientMsg::*, WsServerMsg};
 
-    let con = con;
+    let ConCtx {
+        con,
+        con_state,
+        server_msg_sender,
+        lobbies,
+        uns,
+        ..
+    } = ctx;
 
     match msg {
         WsMsg(Some(id), CreateLobby(value)) => {
@@ -176,7 +254,7 @@ async fn handle_received_message(
                 Err(msg) => WsServerMsg::Err(msg),
             };
 
-            server_msg_sender.send(id.pinned_msg(send)).unwrap();
+            server_msg_sender.send(id.pinned_msg(send)).unwrap_or(());
         }
 
         WsMsg(Some(id), SetUserName(value)) => {
@@ -192,13 +270,13 @@ async fn handle_received_message(
                     Err(()) => interfacing::snake::WsServerMsg::UserNameOccupied,
                 }
             };
-            server_msg_sender.send(id.pinned_msg(send)).unwrap();
+            server_msg_sender.send(id.pinned_msg(send)).unwrap_or(());
         }
 
         WsMsg(Some(id), UserName) => {
             let un = con_state.lock().await.un.clone();
             let send = interfacing::snake::WsServerMsg::UserName(un);
-            server_msg_sender.send(id.pinned_msg(send)).unwrap();
+            server_msg_sender.send(id.pinned_msg(send)).unwrap_or(());
         }
 
         WsMsg(Some(id), JoinLobby(lobby_name)) => {
@@ -231,7 +309,7 @@ async fn handle_received_message(
                 }
             };
 
-            server_msg_sender.send(id.pinned_msg(send)).unwrap();
+            server_msg_sender.send(id.pinned_msg(send)).unwrap_or(());
         }
 
         WsMsg(Some(id), LobbyList) => {
@@ -243,57 +321,92 @@ async fn handle_received_message(
                 .collect::<Vec<_>>();
 
             let send = WsServerMsg::LobbyList(lobby_list);
-            server_msg_sender.send(id.pinned_msg(send)).unwrap();
+            server_msg_sender.send(id.pinned_msg(send)).unwrap_or(());
         }
 
         WsMsg(Some(id), VoteStart(value)) => {
-            let lobby = lobbies.joined_lobby(con).await;
+            let result = match lobbies.joined_lobby(con).await {
+                None => Err("lobby does not exist".into()),
+                // player might have left the lobby in between
+                Some(lobby) => lobby.vote_start(con, value, id.clone()).await,
+            };
 
-            match lobby {
-                None => {
-                    let send = WsServerMsg::Err("lobby does not exist".into());
-                    server_msg_sender.send(id.pinned_msg(send)).unwrap();
-                }
-                Some(lobby) => {
-                    let mut lock = lobby.write().await;
-                    let result = lock.vote_start(con, value);
+            // on success the lobby state is sent with the id
+            if let Err(m) = result {
+                let send = WsServerMsg::Err(m);
+                server_msg_sender.send(id.pinned_msg(send)).unwrap_or(());
+            }
+        }
 
-                    match result {
-                        Ok(()) => {
-                            lock.pinned_broadcast_state(id, con);
-                        }
-                        Err(m) => {
-                            // this branch handling is required because
-                            // it's possible that between lobbies.joined_lobby and lobby.vote_start
-                            // player leaves the lobby
-                            let send = WsServerMsg::Err(m);
-                            server_msg_sender.send(id.pinned_msg(send)).unwrap();
-                        }
-                    };
-                }
+        WsMsg(Some(id), SetLobbySettings(settings)) => {
+            let result = match lobbies.joined_lobby(con).await {
+                None => Err("lobby does not exist".into()),
+                Some(lobby) => lobby.set_settings(con, settings, id.clone()).await,
+            };
+
+            if let Err(m) = result {
+                let send = WsServerMsg::Err(m);
+                server_msg_sender.send(id.pinned_msg(send)).unwrap_or(());
+            }
+        }
+
+        WsMsg(Some(id), PickTeam(team)) => {
+            let result = match lobbies.joined_lobby(con).await {
+                None => Err("lobby does not exist".into()),
+                Some(lobby) => lobby.pick_team(con, team, id.clone()).await,
+            };
+
+            if let Err(m) = result {
+                let send = WsServerMsg::Err(m);
+                server_msg_sender.send(id.pinned_msg(send)).unwrap_or(());
+            }
+        }
+
+        WsMsg(Some(id), Respawn) => {
+            let result = match lobbies.joined_lobby(con).await {
+                None => Err("lobby does not exist".into()),
+                Some(lobby) => lobby.respawn(con, id.clone()).await,
             };
+
+            if let Err(m) = result {
+                let send = WsServerMsg::Err(m);
+                server_msg_sender.send(id.pinned_msg(send)).unwrap_or(());
+            }
         }
 
         WsMsg(Some(id), LeaveLobby) => {
             lobbies.disjoin_con(con).await;
             server_msg_sender
                 .send(id.pinned_msg(WsServerMsg::Ack))
-                .unwrap();
+                .unwrap_or(());
         }
 
-        WsMsg(Some(_id), SetDirection(_)) => {
-            unreachable!("id not expected");
+        WsMsg(Some(_id), SetDirection(..) | SetBoost(_)) => {
+            tracing::info!("id not expected");
+            server_msg_sender.close(CloseReason::ProtocolError);
         }
 
-        WsMsg(None, SetDirection(direction)) => {
+        WsMsg(None, SetDirection(direction, tick)) => {
             let lobby = lobbies.joined_lobby(con).await;
 
             if let Some(lobby) = lobby {
                 lobby
-                    .write()
-                    .await
-                    .set_con_direction(con, direction)
-                    .unwrap_or(());
+                    .send(LobbyCmd::SetDirection {
+                        con,
+                        direction,
+                        tick,
+                    })
+                    .await;
+            }
+
+            // do not send response
+        }
+
+        WsMsg(None, SetBoost(boosting)) => {
+            let lobby = lobbies.joined_lobby(con).await;
+
+            if let Some(lobby) = lobby {
+                lobby.send(LobbyCmd::SetBoost { con, boosting }).await;
             }
 
             // do not send response
@@ -302,27 +415,50 @@ async fn handle_received_message(
         WsMsg(
             None,
             CreateLobby(_) | JoinLobby(_) | UserName | LobbyList | SetUserName(_) | VoteStart(_)
-            | LeaveLobby,
+            | LeaveLobby | SetLobbySettings(_) | Respawn | PickTeam(_),
         ) => {
-            if conf.env.prod() {
-                tracing::info!("ack expected")
-            } else {
-                unreachable!("ack expected")
-            }
+            tracing::info!("ack expected");
+            server_msg_sender.close(CloseReason::ProtocolError);
         }
     }
 }
 
 async fn write(
     mut sender: SplitSink<WebSocket, Message>,
-    mut server_msg_receiver: mpsc::UnboundedReceiver<ServerMsg>,
+    outbox: Outbox,
+    heartbeat: SharedHeartbeat,
 ) {
-    while let Some(msg) = server_msg_receiver.recv().await {
-        let msg = Message::Text(serde_json::to_string(&msg).unwrap());
+    let mut ping = tokio::time::interval(heartbeat::PING_INTERVAL);
+    ping.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        let msg = tokio::select! {
+            msg = outbox.recv() => match msg {
+                Some(msg) => Message::Text(serde_json::to_string(&msg).unwrap()),
+                None => {
+                    let reason = outbox.close_reason().unwrap_or(CloseReason::Shutdown);
+                    let frame = CloseFrame {
+                        code: reason.code(),
+                        reason: reason.description().into(),
+                    };
+                    sender.send(Message::Close(Some(frame))).await.unwrap_or(());
+                    return;
+                }
+            },
+            _ = ping.tick() => {
+                let mut heartbeat = heartbeat.lock().unwrap();
+                if heartbeat.idle() {
+                    tracing::info!("Client idle for too long");
+                    outbox.close(CloseReason::IdleTimeout);
+                    continue;
+                }
+                Message::Ping(heartbeat.ping())
+            }
+        };
 
         match sender.send(msg.clone()).await {
             Ok(()) => {
-                tracing::info!("Sent message: {msg:?}")
+                tracing::info!(queued = outbox.depth(), "Sent message: {msg:?}")
             }
             Err(_) => {
                 tracing::info!("Client disconnected");
@@ -22,10 +22,26 @@ impl Application {
         let address = format!("{}:{}", host, port);
         tracing::info!("Serving on http://{}", address);
 
+        let draining = crate::shutdown::Draining::default();
+        let lobbies = crate::mp::lobby::lobbies::Lobbies::new();
+        let connections = crate::mp::connections::Connections::default();
+        // stops accepting once connections are drained
+        let drain = crate::shutdown::drain(
+            crate::shutdown::signal(),
+            draining.clone(),
+            lobbies.clone(),
+            connections.clone(),
+            std::time::Duration::from_secs(conf.shutdown.grace_secs),
+        );
+        let router = routing::router(conf, draining, lobbies, connections);
+
         return Self {
-            server: Box::pin(axum::Server::from_tcp(listener).unwrap().serve(
-                routing::router(conf).into_make_service_with_connect_info::<UserConnectInfo>(),
-            )),
+            server: Box::pin(
+                axum::Server::from_tcp(listener)
+                    .unwrap()
+                    .serve(router.into_make_service_with_connect_info::<UserConnectInfo>())
+                    .with_graceful_shutdown(drain),
+            ),
             port,
             host,
         };
@@ -54,31 +70,44 @@ mod routing {
     use tower_http::{add_extension::AddExtensionLayer, compression::CompressionLayer};
 
     mod routes {
+        use crate::shutdown::Draining;
+        use axum::Extension;
         use hyper::StatusCode;
 
-        pub async fn health() -> StatusCode {
-            StatusCode::OK
+        // not ready while draining, to be taken out of the service
+        pub async fn health(Extension(draining): Extension<Draining>) -> StatusCode {
+            if draining.get() {
+                StatusCode::SERVICE_UNAVAILABLE
+            } else {
+                StatusCode::OK
+            }
         }
     }
 
-    pub fn router(conf: crate::conf::Conf) -> Router {
+    pub fn router(
+        conf: crate::conf::Conf,
+        draining: crate::shutdown::Draining,
+        lobbies: crate::mp::lobby::lobbies::Lobbies,
+        connections: crate::mp::connections::Connections,
+    ) -> Router {
         let routes = static_routes::routes().api;
 
         let api_router = Router::new()
             .route(routes.health_check.get().postfix(), get(routes::health))
             // TODO investigate why POST on /lobby gives 200
-            .route("/snake/ws", get(snake_ws::ws));
+            .route("/snake/ws", get(snake_ws::ws))
+            .route("/snake/metrics", get(snake_ws::metrics));
 
         Router::new()
             .nest("/api", api_router)
             .layer(CompressionLayer::new())
             .layer(AddExtensionLayer::new(conf))
-            .layer(AddExtensionLayer::new(
-                crate::mp::lobby::lobbies::Lobbies::default(),
-            ))
+            .layer(AddExtensionLayer::new(draining))
+            .layer(AddExtensionLayer::new(lobbies))
             .layer(AddExtensionLayer::new(
                 crate::mp::lobby::usernames::PlayerUserNames::default(),
             ))
+            .layer(AddExtensionLayer::new(connections))
             .layer(crate::trace::request_trace_layer())
     }
 }
@@ -0,0 +1,147 @@
+// Graceful shutdown, on SIGTERM sent by kubernetes or Ctrl-C locally
+//
+// Draining server reports not ready, so it stops getting new connections, and refuses
+// new lobbies. Players are told, games in progress go on for a grace period,
+// then every connection is closed with the shutdown close code.
+
+use crate::mp::{connections::Connections, lobby::lobbies::Lobbies};
+use interfacing::snake::{CloseReason, WsMsg, WsServerMsg};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+
+// how often games in progress are checked for having ended
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+// for close frames to be written and connections cleaned up
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Default)]
+pub struct Draining(Arc<AtomicBool>);
+
+impl Draining {
+    pub fn get(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn start(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+pub async fn signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.expect("Ctrl-C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        signal(SignalKind::terminate())
+            .expect("SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        () = ctrl_c => tracing::info!("received Ctrl-C"),
+        () = terminate => tracing::info!("received SIGTERM"),
+    }
+}
+
+// resolves once connections are closed, and the server may stop
+pub async fn drain(
+    signal: impl Future<Output = ()>,
+    draining: Draining,
+    lobbies: Lobbies,
+    connections: Connections,
+    grace: Duration,
+) {
+    signal.await;
+    tracing::info!("draining, grace period of {grace:?}");
+
+    draining.start();
+    lobbies.drain().await;
+    let closing_in = grace.as_secs().try_into().unwrap_or(u32::MAX);
+    connections.broadcast(WsMsg::new(WsServerMsg::Maintenance(closing_in)));
+
+    let deadline = Instant::now() + grace;
+    loop {
+        let games = lobbies.games_in_progress().await;
+        if games == 0 {
+            tracing::info!("no games in progress");
+            break;
+        }
+        if Instant::now() >= deadline {
+            tracing::warn!("grace period is over, {games} games cut short");
+            break;
+        }
+        tokio::time::sleep(POLL_INTERVAL.min(deadline.saturating_duration_since(Instant::now())))
+            .await;
+    }
+
+    connections.close_all(CloseReason::Shutdown);
+    let closed = async {
+        while !connections.is_empty() {
+            tokio::time::sleep(POLL_INTERVAL / 10).await;
+        }
+    };
+    if tokio::time::timeout(CLOSE_TIMEOUT, closed).await.is_err() {
+        tracing::warn!("some connections did not close in time");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mp::{lobby::lobby::Lobby, outbox::Outbox};
+
+    #[tokio::test(start_paused = true)]
+    async fn drains_then_closes() {
+        let draining = Draining::default();
+        let lobbies = Lobbies::new();
+        let connections = Connections::default();
+        let outbox = Outbox::new();
+        connections.open(1, outbox.clone());
+
+        // as the socket handler would
+        let client = tokio::spawn({
+            let connections = connections.clone();
+            async move {
+                let notice = outbox.recv().await;
+                outbox.closed().await;
+                connections.close(1, &outbox);
+                (notice, outbox.close_reason())
+            }
+        });
+
+        let started = Instant::now();
+        drain(
+            std::future::ready(()),
+            draining.clone(),
+            lobbies.clone(),
+            connections.clone(),
+            Duration::from_secs(30),
+        )
+        .await;
+
+        // nothing was in progress to wait for
+        assert!(started.elapsed() < POLL_INTERVAL);
+        assert!(draining.get());
+        assert!(lobbies
+            .insert_if_missing(Lobby::new("late".into()))
+            .await
+            .is_err());
+        assert_eq!(
+            client.await.unwrap(),
+            (
+                Some(WsMsg::new(WsServerMsg::Maintenance(30))),
+                Some(CloseReason::Shutdown)
+            )
+        );
+    }
+}
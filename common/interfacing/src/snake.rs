@@ -18,6 +18,10 @@ pub struct JoinLobbyAs {
 
 pub type UserName = String;
 pub type LobbyName = String;
+pub type PlayerId = domain::SnakeId;
+pub type TeamId = domain::TeamId;
+// css compatible color, like #ff0000
+pub type Color = String;
 
 pub type MsgId = String;
 pub type MaybeMsgId = Option<MsgId>;
@@ -31,7 +35,16 @@ pub enum WsClientMsg {
     LobbyList,
     VoteStart(bool),
     LeaveLobby,
-    SetDirection(domain::Direction),
+    // with the tick it is meant for, no response
+    SetDirection(domain::Direction, u32),
+    // only before the game started, resets start votes
+    SetLobbySettings(lobby_state::LobbySettings),
+    // only when dead and respawns are allowed
+    Respawn,
+    // only before the game started, in team mode
+    PickTeam(TeamId),
+    // while on, snake moves faster and sheds its tail, no response
+    SetBoost(bool),
 }
 
 impl WsMsg<WsClientMsg> {
@@ -54,6 +67,9 @@ pub enum WsServerMsg {
     Err(String),
     LobbyState(LobbyState),
     LeaveLobbyDecline(LeaveLobbyDecline),
+    GameEvents(lobby_state::GameEvents),
+    // server is going down for maintenance, connections close in seconds
+    Maintenance(u32),
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -89,6 +105,54 @@ impl<M> WsMsg<M> {
     }
 }
 
+// why the server closed the websocket, sent as the close code
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CloseReason {
+    Shutdown,
+    // by the server, for example for falling behind
+    Kicked,
+    ProtocolError,
+    // same client connected again
+    Superseded,
+    IdleTimeout,
+}
+
+impl CloseReason {
+    const ALL: [Self; 5] = [
+        Self::Shutdown,
+        Self::Kicked,
+        Self::ProtocolError,
+        Self::Superseded,
+        Self::IdleTimeout,
+    ];
+
+    // from the range reserved for applications
+    pub fn code(self) -> u16 {
+        match self {
+            Self::Shutdown => 4000,
+            Self::Kicked => 4001,
+            Self::ProtocolError => 4002,
+            Self::Superseded => 4003,
+            Self::IdleTimeout => 4004,
+        }
+    }
+
+    pub fn from_code(code: u16) -> Option<Self> {
+        Self::ALL.into_iter().find(|reason| reason.code() == code)
+    }
+
+    // shown to the player
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::Shutdown => "Server is shutting down",
+            Self::Kicked => "Disconnected by the server",
+            Self::ProtocolError => "Server did not understand the client",
+            Self::Superseded => "Connected from another session",
+            Self::IdleTimeout => "Connection timed out",
+        }
+    }
+}
+
 pub mod list {
     use crate::imports::*;
 
@@ -107,20 +171,172 @@ pub mod lobby_state {
     use super::domain;
     use crate::imports::*;
 
-    use super::UserName;
+    use super::{Color, PlayerId, TeamId, UserName};
 
     #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
     pub enum LobbyState {
         Prep(LobbyPrep),
-        Running(LobbyRunning),
+        // everyone voted, game starts when the countdown is over
+        Countdown(LobbyCountdown),
+        // boxed, since it is much larger than the others
+        Running(Box<LobbyRunning>),
         Terminated,
     }
 
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    pub struct LobbyCountdown {
+        // upcoming game, with spawned snakes
+        pub running: Box<LobbyRunning>,
+        pub seconds_left: u32,
+    }
+
     #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
     pub struct LobbyRunning {
         pub counter: u32,
         pub player_counter: u32,
         pub domain: domain::Domain,
+        // every player who started the game, best score first
+        pub scoreboard: Vec<LobbyRunningPlayer>,
+        // ticks left until the receiver can respawn, zero when already can,
+        // none when alive or respawns are disabled
+        pub respawn_in: Option<u32>,
+        // in team mode, best score first
+        pub teams: Vec<LobbyRunningTeam>,
+        // set when the game has ended, lobby no longer advances
+        pub game_over: Option<GameOver>,
+        // in battle royale mode, known some ticks before the shrink
+        pub upcoming_boundaries: Option<UpcomingBoundaries>,
+        // in king of the hill mode
+        pub zones: Vec<CaptureZone>,
+        // ticks left until the time limit, in king of the hill and timed modes
+        pub ticks_left: Option<u32>,
+        // when the lobby plays a series of rounds
+        #[serde(default)]
+        pub series: Option<LobbySeries>,
+    }
+
+    // progress of a best-of-N series
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    pub struct LobbySeries {
+        // current round, starting from 1
+        pub round: u32,
+        pub rounds: u32,
+        // round wins, most first
+        pub standings: Vec<SeriesStanding>,
+        // ticks until the next round starts, between rounds
+        pub next_round_in: Option<u32>,
+        pub ended: bool,
+        // once ended, none on a tie
+        pub winner: Option<domain::Winner>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    pub struct SeriesStanding {
+        pub winner: domain::Winner,
+        pub wins: u32,
+    }
+
+    // rectangle including its edges, scores for the only snake inside
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    pub struct CaptureZone {
+        pub min: domain::Pos,
+        pub max: domain::Pos,
+        // player who scored on the last tick
+        pub holder: Option<PlayerId>,
+    }
+
+    impl CaptureZone {
+        pub fn contains(&self, pos: domain::Pos) -> bool {
+            (self.min.x..=self.max.x).contains(&pos.x) && (self.min.y..=self.max.y).contains(&pos.y)
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    pub struct UpcomingBoundaries {
+        pub boundaries: domain::Boundaries,
+        // ticks left until the shrink
+        pub in_ticks: u32,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    pub struct LobbyRunningTeam {
+        pub id: TeamId,
+        pub color: Color,
+        // sum of members scores
+        pub score: u32,
+        // any member is alive
+        pub alive: bool,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    pub struct GameOver {
+        pub winner: Option<domain::Winner>,
+        // final ranking of players, best first
+        #[serde(default)]
+        pub standings: Vec<PlayerId>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    pub struct LobbyRunningPlayer {
+        // matches id of the player's snake
+        pub id: PlayerId,
+        pub user_name: UserName,
+        pub color: Color,
+        pub score: Score,
+        pub alive: bool,
+        pub team: Option<TeamId>,
+        #[serde(default)]
+        pub boosting: bool,
+        #[serde(default)]
+        pub input_delay: InputDelay,
+    }
+
+    // how late turns of a player reach the server, in ticks
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+    pub struct InputDelay {
+        pub turns: u32,
+        // applied a tick after the one they were meant for
+        pub late: u32,
+        // outside of the input window, not applied
+        pub dropped: u32,
+        // over the turns not dropped
+        pub total_ticks: u32,
+        pub max_ticks: u32,
+    }
+
+    impl InputDelay {
+        pub fn average(&self) -> f32 {
+            let applied = self.turns - self.dropped;
+            if applied == 0 {
+                return 0.;
+            }
+            self.total_ticks as f32 / applied as f32
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+    pub struct Score {
+        pub score: u32,
+        // length of the snake, zero when dead
+        pub length: u32,
+        pub kills: u32,
+        pub foods_eaten: u32,
+        // ticks spent holding capture zones
+        #[serde(default)]
+        pub zone_points: u32,
+    }
+
+    // events happened on tick
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    pub struct GameEvents {
+        pub counter: u32,
+        pub events: Vec<domain::GameEvent>,
+    }
+
+    impl LobbyRunning {
+        pub fn player(&self, id: PlayerId) -> Option<&LobbyRunningPlayer> {
+            self.scoreboard.iter().find(|player| player.id == id)
+        }
     }
 
     #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -131,12 +347,177 @@ pub mod lobby_state {
     #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
     pub struct LobbyPrep {
         pub participants: Vec<LobbyPrepParticipant>,
+        pub settings: LobbySettings,
+    }
+
+    // game rules, set up before the game starts
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+    pub struct LobbySettings {
+        // ticks to wait after death before respawning, no respawns when none
+        #[serde(default)]
+        pub respawn_cooldown: Option<u32>,
+        // players can join while the game is running
+        #[serde(default)]
+        pub mid_game_join: bool,
+        // team mode when set
+        #[serde(default)]
+        pub teams: Option<TeamSettings>,
+        // battle royale mode when set
+        #[serde(default)]
+        pub shrinking: Option<ShrinkSettings>,
+        // king of the hill mode when set
+        #[serde(default)]
+        pub hill: Option<HillSettings>,
+        // timed survival mode when set
+        #[serde(default)]
+        pub timed: Option<TimedSettings>,
+        // arena has linked portal pairs
+        #[serde(default)]
+        pub portals: bool,
+        // snakes can trade length for speed when set
+        #[serde(default)]
+        pub boost: Option<BoostSettings>,
+        // ghost, shield and magnet pickups drop with foods
+        #[serde(default)]
+        pub power_ups: bool,
+        // best-of-N rounds instead of a single game
+        #[serde(default)]
+        pub series: Option<SeriesSettings>,
+        // food figures dropped in the arena
+        #[serde(default)]
+        pub figures: domain::figures::FigureSet,
+        // foods expire after the given ticks when set
+        #[serde(default)]
+        pub food_lifetime: Option<u32>,
+        // foods run from nearby heads every given ticks when set
+        #[serde(default)]
+        pub food_flee_every: Option<u32>,
+        // milliseconds between ticks, server default when none
+        #[serde(default)]
+        pub tick_ms: Option<u32>,
+    }
+
+    // boosted snake moves two cells per tick
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    pub struct BoostSettings {
+        // boosted ticks per one shed tail cell
+        pub shed_every: u32,
+    }
+
+    impl Default for BoostSettings {
+        fn default() -> Self {
+            Self { shed_every: 2 }
+        }
+    }
+
+    // game ends after the given ticks, best by ranking wins, ties broken by kills
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    pub struct TimedSettings {
+        pub ticks: u32,
+        pub ranking: TimedRanking,
+    }
+
+    impl Default for TimedSettings {
+        fn default() -> Self {
+            Self {
+                ticks: 300,
+                ranking: TimedRanking::Length,
+            }
+        }
+    }
+
+    // series ends once someone won the majority of rounds
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    pub struct SeriesSettings {
+        pub rounds: u32,
+        // ticks between rounds, showing standings
+        pub intermission: u32,
+    }
+
+    impl Default for SeriesSettings {
+        fn default() -> Self {
+            Self {
+                rounds: 3,
+                intermission: 10,
+            }
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+    pub enum TimedRanking {
+        // longest snake at the end, dead snakes have zero length
+        Length,
+        Score,
+    }
+
+    // game ends when someone reaches target zone points,
+    // or at the time limit with the leader winning
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    pub struct HillSettings {
+        pub target_score: u32,
+        // in ticks
+        pub time_limit: u32,
+    }
+
+    impl Default for HillSettings {
+        fn default() -> Self {
+            Self {
+                target_score: 100,
+                time_limit: 600,
+            }
+        }
+    }
+
+    // arena shrinks by one cell from every side on schedule
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    pub struct ShrinkSettings {
+        // ticks between shrinks
+        pub interval: u32,
+        // ticks the upcoming boundaries are known before the shrink
+        pub warning: u32,
+    }
+
+    impl Default for ShrinkSettings {
+        fn default() -> Self {
+            Self {
+                interval: 20,
+                warning: 6,
+            }
+        }
+    }
+
+    impl LobbySettings {
+        pub const DEFAULT_RESPAWN_COOLDOWN: u32 = 10;
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    pub struct TeamSettings {
+        // teams are numbered from 1 up to count
+        pub count: TeamId,
+        pub teammate_collision: TeammateCollision,
+    }
+
+    impl TeamSettings {
+        pub const MIN_COUNT: TeamId = 2;
+        pub const MAX_COUNT: TeamId = 4;
+    }
+
+    // what happens when a snake runs into its teammate
+    #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+    pub enum TeammateCollision {
+        // snakes move through each other
+        PassThrough,
+        // snake stays in place until the way is free
+        Block,
     }
 
     #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
     pub struct LobbyPrepParticipant {
         pub user_name: UserName,
         pub vote_start: bool,
+        // in team mode
+        pub team: Option<TeamId>,
+        pub color: Option<Color>,
     }
 }
 
@@ -10,20 +10,189 @@ pub struct Domain {
     pub other_snakes: Vec<Snake>,
     pub foods: Foods,
     pub boundaries: Boundaries,
+    #[serde(default)]
+    pub portals: Vec<Portal>,
+    #[serde(default)]
+    pub pickups: Vec<Pickup>,
 }
 
+// linked pair of cells, snake entering one exits from the other,
+// keeping its direction
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Portal {
+    pub a: Pos,
+    pub b: Pos,
+}
+
+impl Portal {
+    pub fn exit(&self, entrance: Pos) -> Option<Pos> {
+        if entrance == self.a {
+            Some(self.b)
+        } else if entrance == self.b {
+            Some(self.a)
+        } else {
+            None
+        }
+    }
+}
+
+pub fn portal_exit(portals: &[Portal], entrance: Pos) -> Option<Pos> {
+    portals.iter().find_map(|portal| portal.exit(entrance))
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PowerUp {
+    // passes through other snakes and is passed through
+    Ghost,
+    // survives one collision, staying in place
+    Shield,
+    // pulls nearby foods
+    Magnet,
+}
+
+// power-up lying in the arena, applied when eaten
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Pickup {
+    pub pos: Pos,
+    pub power_up: PowerUp,
+}
+
+// active power-ups of a snake, timed ones count down remaining ticks
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Effects {
+    #[serde(default)]
+    pub ghost: u32,
+    #[serde(default)]
+    pub shield: bool,
+    #[serde(default)]
+    pub magnet: u32,
+}
+
+impl Effects {
+    pub const GHOST_TICKS: u32 = 30;
+    pub const MAGNET_TICKS: u32 = 50;
+    // chebyshev distance from the mouth
+    pub const MAGNET_RADIUS: u32 = 4;
+
+    pub fn apply(&mut self, power_up: PowerUp) {
+        match power_up {
+            PowerUp::Ghost => self.ghost = Self::GHOST_TICKS,
+            PowerUp::Shield => self.shield = true,
+            PowerUp::Magnet => self.magnet = Self::MAGNET_TICKS,
+        }
+    }
+
+    // called once per tick
+    pub fn expire(&mut self) {
+        self.ghost = self.ghost.saturating_sub(1);
+        self.magnet = self.magnet.saturating_sub(1);
+    }
+
+    pub fn ghost(&self) -> bool {
+        self.ghost > 0
+    }
+
+    pub fn magnet(&self) -> bool {
+        self.magnet > 0
+    }
+
+    pub fn active(&self) -> Vec<PowerUp> {
+        [
+            (PowerUp::Ghost, self.ghost()),
+            (PowerUp::Shield, self.shield),
+            (PowerUp::Magnet, self.magnet()),
+        ]
+        .into_iter()
+        .filter_map(|(power_up, active)| active.then_some(power_up))
+        .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+// distinguishes snakes from each other,
+// in multiplayer matches identifier of the player controlling snake
+pub type SnakeId = u16;
+
+// in multiplayer team mode, numbered from 1
+pub type TeamId = u8;
+
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct Snake {
     pub sections: Sections,
     // direction snake will move on advance, always valid
     pub direction: Direction,
+    // unset in singleplayer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<SnakeId>,
+    #[serde(default, skip_serializing_if = "Effects::is_empty")]
+    pub effects: Effects,
 }
 
 pub enum AdvanceResult {
     Success,
+    // contains id of the snake that was bitten
+    BitSomeone(Option<SnakeId>),
+    BitYaSelf,
+    OutOfBounds,
+    // collision absorbed by the shield, snake did not move
+    Shielded,
+}
+
+impl AdvanceResult {
+    // None if snake survived, otherwise cause and killer
+    pub fn death(&self) -> Option<(DeathCause, Option<SnakeId>)> {
+        match self {
+            Self::Success | Self::Shielded => None,
+            Self::BitSomeone(killer) => Some((DeathCause::BitSomeone, *killer)),
+            Self::BitYaSelf => Some((DeathCause::BitYaSelf, None)),
+            Self::OutOfBounds => Some((DeathCause::OutOfBounds, None)),
+        }
+    }
+}
+
+// things that happen during the game
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum GameEvent {
+    GameStarted,
+    SnakeSpawned {
+        id: SnakeId,
+    },
+    FoodEaten {
+        id: SnakeId,
+        pos: Pos,
+    },
+    SnakeDied {
+        victim: SnakeId,
+        cause: DeathCause,
+        // owner of the bitten snake
+        killer: Option<SnakeId>,
+    },
+    PowerUpPicked {
+        id: SnakeId,
+        power_up: PowerUp,
+    },
+    // none when nobody won
+    GameEnded {
+        winner: Option<Winner>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Winner {
+    Team(TeamId),
+    Player(SnakeId),
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum DeathCause {
     BitSomeone,
     BitYaSelf,
     OutOfBounds,
+    // player left the game
+    Left,
 }
 
 impl Snake {
@@ -56,7 +225,7 @@ impl Snake {
         self.iter_vertices().skip(1)
     }
 
-    fn bit_snake(&self, advanced_head: Section, myself: bool) -> bool {
+    fn bit_snake(&self, advanced_head: Section, myself: bool, portals: &[Portal]) -> bool {
         if myself {
             // all sections except tail, because it won't be here when head advances
             self.iter_vertices_without_tail()
@@ -71,8 +240,9 @@ impl Snake {
                     min: Pos::new(-1000, -1000),
                     max: Pos::new(1000, 1000),
                 },
+                portals,
             ) {
-                AdvanceResult::Success => {}
+                AdvanceResult::Success | AdvanceResult::Shielded => {}
                 _ => unreachable!(),
             }
             let mut vertices = snake.iter_vertices_without_tail();
@@ -80,8 +250,74 @@ impl Snake {
         }
     }
 
-    fn advance_head(&mut self, other_snakes: &[Snake], boundaries: &Boundaries) -> AdvanceResult {
-        let advanced_head = self.head().next(self.direction).unwrap();
+    // whether advancing on the next step would bite the other snake
+    pub fn bites(&self, other: &Snake, portals: &[Portal]) -> bool {
+        if self.passes(other) {
+            return false;
+        }
+
+        match self.next_head(portals) {
+            Ok(advanced_head) => other.bit_snake(advanced_head, false, portals),
+            Err(()) => false,
+        }
+    }
+
+    // whether the next step lands on the current body of the other snake,
+    // for snakes which stay in place
+    pub fn hits(&self, other: &Snake, portals: &[Portal]) -> bool {
+        if self.passes(other) {
+            return false;
+        }
+
+        match self.next_head(portals) {
+            Ok(advanced_head) => other.iter_vertices().any(|pos| pos == advanced_head.end()),
+            Err(()) => false,
+        }
+    }
+
+    // ghosts do not collide with other snakes
+    fn passes(&self, other: &Snake) -> bool {
+        self.effects.ghost() || other.effects.ghost()
+    }
+
+    // deadly result turns into staying in place, if shield is up
+    pub fn absorb(&mut self, result: AdvanceResult) -> AdvanceResult {
+        if result.death().is_some() && self.effects.shield {
+            self.effects.shield = false;
+            AdvanceResult::Shielded
+        } else {
+            result
+        }
+    }
+
+    // remove the tail cell, returns its position
+    pub fn shed_tail(&mut self) -> Option<Pos> {
+        if self.sections.len() <= 1 {
+            return None;
+        }
+
+        let pos = self.tail_end();
+        self.rm_tail();
+        Some(pos)
+    }
+
+    // head section after the next step, jumps when entering a portal
+    fn next_head(&self, portals: &[Portal]) -> Result<Section, ()> {
+        let advanced_head = self.head().next(self.direction)?;
+
+        Ok(match portal_exit(portals, advanced_head.end()) {
+            Some(exit) => advanced_head.jump(exit),
+            None => advanced_head,
+        })
+    }
+
+    fn advance_head(
+        &mut self,
+        other_snakes: &[Snake],
+        boundaries: &Boundaries,
+        portals: &[Portal],
+    ) -> AdvanceResult {
+        let advanced_head = self.next_head(portals).unwrap();
 
         // TODO duplicate logic
         let out_of_bounds = match boundaries.relation(advanced_head.end()) {
@@ -90,19 +326,25 @@ impl Snake {
             RelationToBoundaries::Outside => true,
         };
 
-        if out_of_bounds {
+        let bitten = || {
+            other_snakes
+                .iter()
+                .filter(|snake| !self.passes(snake))
+                .find(|snake| snake.bit_snake(advanced_head, false, portals))
+        };
+
+        let result = if out_of_bounds {
             AdvanceResult::OutOfBounds
-        } else if self.bit_snake(advanced_head, true) {
+        } else if self.bit_snake(advanced_head, true, portals) {
             AdvanceResult::BitYaSelf
-        } else if other_snakes
-            .iter()
-            .any(|snake| snake.bit_snake(advanced_head, false))
-        {
-            AdvanceResult::BitSomeone
+        } else if let Some(snake) = bitten() {
+            AdvanceResult::BitSomeone(snake.id)
         } else {
-            self.sections.push_head(self.direction).unwrap();
+            self.sections.push_head(advanced_head);
             AdvanceResult::Success
-        }
+        };
+
+        self.absorb(result)
     }
 
     pub fn advance(
@@ -110,8 +352,9 @@ impl Snake {
         foods: &mut Foods,
         other_snakes: &[Snake],
         boundaries: &Boundaries,
+        portals: &[Portal],
     ) -> AdvanceResult {
-        match self.advance_head(other_snakes, boundaries) {
+        match self.advance_head(other_snakes, boundaries, portals) {
             AdvanceResult::Success => {
                 // if on next step mouth will eat food -
                 // remove food and don't remove tail
@@ -159,23 +402,51 @@ impl Snake {
 #[derive(PartialEq, Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct Food {
     pub pos: Pos,
+    // ticks since placed
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub age: u32,
+    // expires when the age reaches it, never when none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lifetime: Option<u32>,
+}
+
+fn is_zero(value: &u32) -> bool {
+    *value == 0
 }
 
 impl Food {
+    // ticks before expiring, when drawn fading
+    pub const FADE_TICKS: u32 = 10;
+
     pub fn new(x: i32, y: i32) -> Self {
-        Self {
-            pos: Pos::new(x, y),
-        }
+        Self::from(Pos::new(x, y))
     }
 
     pub fn pos(&self) -> Pos {
         self.pos
     }
+
+    pub fn expires_in(&self) -> Option<u32> {
+        self.lifetime
+            .map(|lifetime| lifetime.saturating_sub(self.age))
+    }
+
+    // from 1 when fresh to 0 at expiry, for drawing
+    pub fn freshness(&self) -> f64 {
+        match self.expires_in() {
+            Some(left) if left < Self::FADE_TICKS => left as f64 / Self::FADE_TICKS as f64,
+            _ => 1.,
+        }
+    }
 }
 
 impl From<Pos> for Food {
     fn from(pos: Pos) -> Self {
-        Self { pos }
+        Self {
+            pos,
+            age: 0,
+            lifetime: None,
+        }
     }
 }
 
@@ -225,6 +496,84 @@ impl Foods {
         self.values.contains_key(&pos)
     }
 
+    // ages foods by a tick, expired ones disappear
+    pub fn tick(&mut self) {
+        self.values.retain(|_, food| {
+            food.age += 1;
+            food.expires_in() != Some(0)
+        });
+    }
+
+    // heads closer than this scare foods away
+    pub const FLEE_RADIUS: u32 = 5;
+
+    // every that many ticks of their age, foods near a head step one cell away
+    // from the nearest one, only onto free cells inside the boundaries
+    pub fn flee(
+        &mut self,
+        every: u32,
+        heads: &[Pos],
+        boundaries: &Boundaries,
+        blocked: impl Fn(Pos) -> bool,
+    ) {
+        let fleeing = self
+            .values
+            .values()
+            .filter(|food| every > 0 && food.age > 0 && food.age % every == 0)
+            .filter_map(|food| {
+                let head = heads.iter().min_by_key(|head| head.distance(food.pos))?;
+                (head.distance(food.pos) < Self::FLEE_RADIUS).then_some((food.pos, *head))
+            })
+            .collect::<Vec<_>>();
+
+        let squared = |a: Pos, b: Pos| {
+            let diff = a - b;
+            diff.x * diff.x + diff.y * diff.y
+        };
+
+        for (pos, head) in fleeing {
+            let away = [
+                Direction::Up,
+                Direction::Bottom,
+                Direction::Left,
+                Direction::Right,
+            ]
+            .into_iter()
+            .map(|direction| pos.to(direction))
+            .filter(|next| {
+                boundaries.relation(*next).is_inside() && !self.has_pos(*next) && !blocked(*next)
+            })
+            .filter(|next| squared(*next, head) > squared(pos, head))
+            .max_by_key(|next| squared(*next, head));
+
+            if let Some(next) = away {
+                let mut food = self.values.remove(&pos).unwrap();
+                food.pos = next;
+                self.insert(food);
+            }
+        }
+    }
+
+    // moves foods within radius one cell closer to the point,
+    // foods next to it stay until eaten
+    pub fn pull(&mut self, to: Pos, radius: u32) {
+        let pulled = self
+            .values
+            .keys()
+            .filter(|pos| (2..=radius).contains(&pos.distance(to)))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for pos in pulled {
+            let next = pos.toward(to);
+            if !self.has_pos(next) {
+                let mut food = self.values.remove(&pos).unwrap();
+                food.pos = next;
+                self.insert(food);
+            }
+        }
+    }
+
     pub fn remove_with_pos(&mut self, pos: Pos) {
         self.values.remove(&pos);
     }
@@ -378,10 +727,201 @@ fn test_serde_sections() {
     }
 }
 
+#[test]
+fn test_bit_someone_id() {
+    // moves right into the body of the other snake
+    let mut snake = Snake {
+        sections: Sections::from_directions(Pos::new(0, 0), [Direction::Right]),
+        direction: Direction::Right,
+        id: Some(1),
+        effects: Default::default(),
+    };
+
+    let other = Snake {
+        sections: Sections::from_directions(Pos::new(2, 2), [Direction::Up, Direction::Up]),
+        direction: Direction::Up,
+        id: Some(2),
+        effects: Default::default(),
+    };
+
+    let boundaries = Pos::new(0, 0).boundaries_in_radius(10, 10);
+
+    assert!(matches!(
+        snake.advance(&mut Foods::default(), &[other], &boundaries, &[]),
+        AdvanceResult::BitSomeone(Some(2))
+    ));
+}
+
+#[test]
+fn test_foods_pull() {
+    let mut foods = Foods::default();
+    foods.extend([Food::new(3, 1), Food::new(1, 0), Food::new(9, 0)].into_iter());
+
+    foods.pull(Pos::new(0, 0), Effects::MAGNET_RADIUS);
+
+    // adjacent and far away foods stay
+    assert!(foods.has_pos(Pos::new(2, 1)));
+    assert!(foods.has_pos(Pos::new(1, 0)));
+    assert!(foods.has_pos(Pos::new(9, 0)));
+}
+
+#[test]
+fn test_food_placement() {
+    use placement::FoodPlacement;
+
+    let boundaries = Pos::new(0, 0).boundaries_in_radius(5, 5);
+    let snake = Sections::from_directions(Pos::new(0, 0), [Direction::Right; 4]);
+    let placement = FoodPlacement::new(boundaries, snake.iter_vertices(), None);
+
+    let mut foods = Foods::default();
+    let target = placement.target(0.2);
+    placement.fill(&mut foods, target, Default::default(), &mut |n| n / 2);
+
+    assert_eq!(foods.count(), target);
+    assert!(foods
+        .iter()
+        .all(|food| boundaries.relation(food.pos()).is_inside()
+            && snake.iter_vertices().all(|pos| pos != food.pos())));
+
+    // stops once every free cell has food
+    let boundaries = Pos::new(0, 0).boundaries_in_radius(2, 2);
+    let placement = FoodPlacement::new(boundaries, [Pos::new(0, 0)].into_iter(), None);
+
+    let mut foods = Foods::default();
+    placement.fill(&mut foods, 100, Default::default(), &mut |_| 0);
+
+    assert_eq!(foods.count(), 8);
+    assert!(placement.free_cells(&foods).is_empty());
+}
+
+#[test]
+fn test_food_lifetime() {
+    let mut foods = Foods::default();
+    foods.insert(Food {
+        lifetime: Some(2),
+        ..Food::new(0, 0)
+    });
+    foods.insert(Food::new(1, 0));
+
+    foods.tick();
+    assert!(foods.has_pos(Pos::new(0, 0)));
+    assert!(foods.iter().any(|food| food.freshness() < 1.));
+
+    foods.tick();
+    assert!(!foods.has_pos(Pos::new(0, 0)));
+    assert!(foods.has_pos(Pos::new(1, 0)));
+    assert_eq!(foods.iter().next().unwrap().age, 2);
+}
+
+#[test]
+fn test_foods_flee() {
+    let boundaries = Pos::new(0, 0).boundaries_in_radius(5, 5);
+    let mut foods = Foods::default();
+    foods.extend([Food::new(2, 0), Food::new(4, 0), Food::new(0, -4)].into_iter());
+    foods.tick();
+
+    foods.flee(1, &[Pos::new(0, 0)], &boundaries, |pos| {
+        pos == Pos::new(0, -5)
+    });
+
+    // wall and the blocked cell turn foods aside, only away from the head
+    assert!(foods.has_pos(Pos::new(3, 0)));
+    assert!(foods.has_pos(Pos::new(4, 1)) || foods.has_pos(Pos::new(4, -1)));
+    assert!(foods.has_pos(Pos::new(1, -4)) || foods.has_pos(Pos::new(-1, -4)));
+    assert_eq!(foods.count(), 3);
+}
+
+#[test]
+fn test_figures() {
+    use figures::{parse, FigureSet};
+    use strum::IntoEnumIterator;
+
+    for set in FigureSet::iter() {
+        assert!(!set.figures().is_empty());
+    }
+
+    let figure = parse("corner 1\n#.\n#.\n##").unwrap().remove(0);
+    assert_eq!((figure.width(), figure.height()), (2, 3));
+
+    let rotated = figure.rotated();
+    assert_eq!((rotated.width(), rotated.height()), (3, 2));
+    assert_eq!(
+        rotated.food_offsets(),
+        vec![
+            Pos::new(0, 0),
+            Pos::new(1, 0),
+            Pos::new(2, 0),
+            Pos::new(0, 1)
+        ]
+    );
+    assert_eq!(figure.mirrored().food_offsets().len(), 4);
+    assert_eq!(figure.oriented(4).oriented(4), figure.mirrored().mirrored());
+    assert_eq!((0..4).fold(figure.clone(), |f, _| f.rotated()), figure);
+
+    assert!(parse("bad 1\n#.\n#").is_err());
+    assert!(parse("bad 1\n#x").is_err());
+    assert!(parse("bad\n#").is_err());
+    assert!(parse("empty 1\n..").is_err());
+}
+
+#[test]
+fn test_portal() {
+    let portals = [Portal {
+        a: Pos::new(3, 0),
+        b: Pos::new(-3, 2),
+    }];
+
+    let mut snake = Snake {
+        sections: Sections::from_directions(Pos::new(0, 0), [Direction::Right, Direction::Right]),
+        direction: Direction::Right,
+        id: None,
+        effects: Default::default(),
+    };
+
+    let boundaries = Pos::new(0, 0).boundaries_in_radius(10, 10);
+
+    assert!(matches!(
+        snake.advance(&mut Foods::default(), &[], &boundaries, &portals),
+        AdvanceResult::Success
+    ));
+    assert_eq!(snake.mouth(), Pos::new(-3, 2));
+    assert_eq!(
+        snake.iter_vertices().collect::<Vec<_>>(),
+        vec![Pos::new(1, 0), Pos::new(2, 0), Pos::new(-3, 2)]
+    );
+    assert_eq!(
+        snake.sections.parts(),
+        vec![
+            vec![Pos::new(1, 0), Pos::new(2, 0), Pos::new(3, 0)],
+            vec![Pos::new(-3, 2)]
+        ]
+    );
+
+    snake.advance(&mut Foods::default(), &[], &boundaries, &portals);
+    assert_eq!(snake.mouth(), Pos::new(-2, 2));
+
+    let de = serde_json::from_str::<Sections>(&serde_json::to_string(&snake.sections).unwrap());
+    assert_eq!(de.unwrap(), snake.sections);
+
+    // body on the exit side is bitten
+    let mut other = Snake {
+        sections: Sections::from_directions(Pos::new(-2, 4), [Direction::Up]),
+        direction: Direction::Up,
+        id: Some(2),
+        effects: Default::default(),
+    };
+    assert!(matches!(
+        other.advance(&mut Foods::default(), &[snake], &boundaries, &portals),
+        AdvanceResult::BitSomeone(None)
+    ));
+}
+
 // efficiently serialize Sections struct
 // binary package structure:
 //  - 4 and 4 bytes for X and Y dimensions of the beginning of the first section respectively
 //  - 1 byte designated for the number of directions to decode in the last byte (see pack_values for more)
+//  - 2 bytes for the number of portal jumps, then 2, 4 and 4 bytes
+//    for the section index and X and Y of its end, per jump
 //  - the rest are packed directions
 impl Serialize for Sections {
     fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
@@ -409,6 +949,14 @@ impl Serialize for Sections {
 
         bytes.push(values_in_last_byte);
 
+        let jumps = self.iter_jumps().collect::<Vec<_>>();
+        bytes.extend((jumps.len() as u16).to_be_bytes());
+        for (i, end) in jumps {
+            bytes.extend((i as u16).to_be_bytes());
+            bytes.extend(end.x().to_be_bytes());
+            bytes.extend(end.y().to_be_bytes());
+        }
+
         let packed = pack_values(dirs.as_ref());
 
         bytes.extend(packed);
@@ -460,6 +1008,27 @@ impl<'de> Deserialize<'de> for Sections {
                     .next_element()?
                     .ok_or_else(|| serde::de::Error::invalid_length(2, &self))?;
 
+                fn next_bytes<'de, A: serde::de::SeqAccess<'de>, const N: usize>(
+                    seq: &mut A,
+                ) -> Result<[u8; N], A::Error> {
+                    let mut bytes = [0; N];
+                    for (i, byte) in bytes.iter_mut().enumerate() {
+                        *byte = seq.next_element()?.ok_or_else(|| {
+                            serde::de::Error::invalid_length(i, &"Not enough bytes")
+                        })?;
+                    }
+                    Ok(bytes)
+                }
+
+                let jumps_count = u16::from_be_bytes(next_bytes(&mut seq)?);
+                let mut jumps = vec![];
+                for _ in 0..jumps_count {
+                    let i = u16::from_be_bytes(next_bytes(&mut seq)?) as usize;
+                    let x = i32::from_be_bytes(next_bytes(&mut seq)?);
+                    let y = i32::from_be_bytes(next_bytes(&mut seq)?);
+                    jumps.push((i, Pos::new(x, y)));
+                }
+
                 let mut bytes = vec![];
 
                 // TODO handle it better
@@ -469,7 +1038,15 @@ impl<'de> Deserialize<'de> for Sections {
 
                 let directions = unpack_values(&bytes, values_in_last_byte);
 
-                Ok(Sections::from_directions(Pos::new(x, y), directions))
+                let mut sections = Sections::from_directions(Pos::new(x, y), directions);
+                for (i, end) in jumps {
+                    if i >= sections.len() {
+                        return Err(serde::de::Error::custom("Jump out of sections"));
+                    }
+                    sections.jump(i, end);
+                }
+
+                Ok(sections)
             }
         }
 
@@ -482,17 +1059,34 @@ impl<'de> Deserialize<'de> for Sections {
 impl Sections {
     // iter directions starting from the start of the first section
     pub fn iter_directions(&self) -> impl Iterator<Item = Direction> + '_ {
-        self.sections.iter().map(|section| {
-            let diff = section.end() - section.start();
-
-            match (diff.x(), diff.y()) {
-                (1, 0) => Direction::Right,
-                (-1, 0) => Direction::Left,
-                (0, 1) => Direction::Bottom,
-                (0, -1) => Direction::Up,
-                _ => unreachable!(),
+        self.sections.iter().map(Section::direction)
+    }
+
+    // indices of sections going through portals, with their ends
+    pub fn iter_jumps(&self) -> impl Iterator<Item = (usize, Pos)> + '_ {
+        self.sections
+            .iter()
+            .enumerate()
+            .filter(|(_, section)| section.is_jump())
+            .map(|(i, section)| (i, section.end()))
+    }
+
+    // continuous parts of the body, split on portal jumps,
+    // part before a jump ends in the entrance portal
+    pub fn parts(&self) -> Vec<Vec<Pos>> {
+        let mut parts = vec![vec![self.tail().start()]];
+
+        for section in self.sections.iter() {
+            let part = parts.last_mut().unwrap();
+            if section.is_jump() {
+                part.push(section.start().to(section.direction()));
+                parts.push(vec![section.end()]);
+            } else {
+                part.push(section.end());
             }
-        })
+        }
+
+        parts
     }
 
     pub fn iter_vertices(&self) -> impl Iterator<Item = Pos> + '_ {
@@ -518,15 +1112,21 @@ impl Sections {
         self.as_mut().remove(0);
     }
 
-    fn push_head(&mut self, direction: Direction) -> Result<(), ()> {
-        let advanced_head = self.head().next(direction);
+    fn push_head(&mut self, advanced_head: Section) {
+        self.as_mut().push(advanced_head);
+    }
+
+    // make the section end at the exit portal, moving the following ones with it
+    fn jump(&mut self, i: usize, exit: Pos) {
+        let shift = exit - self.sections[i].end();
 
-        match advanced_head {
-            Ok(advanced_head) => {
-                self.as_mut().push(advanced_head);
-                Ok(())
-            }
-            Err(()) => Err(()),
+        self.sections[i] = self.sections[i].jump(exit);
+        for section in self.sections[i + 1..].iter_mut() {
+            *section = Section::new(
+                section.start() + shift,
+                section.end() + shift,
+                section.direction(),
+            );
         }
     }
 
@@ -571,12 +1171,15 @@ impl AsMut<Vec<Section>> for Sections {
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Copy)]
 pub struct Section {
     vector: Vector,
+    // kept, since the vector of a portal jump is not a unit step
+    direction: Direction,
 }
 
 impl Section {
-    fn new(start: Pos, end: Pos) -> Self {
+    fn new(start: Pos, end: Pos, direction: Direction) -> Self {
         Self {
             vector: Vector { start, end },
+            direction,
         }
     }
 
@@ -589,11 +1192,21 @@ impl Section {
     }
 
     pub fn initial(start: Pos, direction: Direction) -> Self {
-        Self::new(start, start.to(direction))
+        Self::new(start, start.to(direction), direction)
     }
 
     pub fn direction(&self) -> Direction {
-        self.vector.direction().unwrap()
+        self.direction
+    }
+
+    // went through a portal
+    pub fn is_jump(&self) -> bool {
+        self.start().to(self.direction) != self.end()
+    }
+
+    // same step, but ending at the exit portal
+    fn jump(&self, exit: Pos) -> Self {
+        Self::new(self.start(), exit, self.direction)
     }
 
     pub fn is_opposite_direction(&self, direction: Direction) -> bool {
@@ -604,7 +1217,7 @@ impl Section {
         if self.is_opposite_direction(direction) {
             Err(())
         } else {
-            Ok(Self::new(self.end(), self.end().to(direction)))
+            Ok(Self::new(self.end(), self.end().to(direction), direction))
         }
     }
 }
@@ -660,6 +1273,22 @@ impl Pos {
         self.x
     }
 
+    // chebyshev distance
+    pub fn distance(self, other: Pos) -> u32 {
+        let diff = other - self;
+        diff.x.unsigned_abs().max(diff.y.unsigned_abs())
+    }
+
+    // one cell closer to the other position, along the longer axis
+    pub fn toward(self, other: Pos) -> Pos {
+        let diff = other - self;
+        if diff.x.abs() >= diff.y.abs() {
+            Pos::new(self.x + diff.x.signum(), self.y)
+        } else {
+            Pos::new(self.x, self.y + diff.y.signum())
+        }
+    }
+
     pub fn y(self) -> i32 {
         self.y
     }
@@ -801,97 +1430,324 @@ pub enum RelationToBoundaries {
 }
 
 pub mod figures {
+    use super::Pos;
+    use serde::{Deserialize, Serialize};
+    use std::sync::OnceLock;
+
+    // food figure sets embedded from the figures directory
+    #[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, strum::EnumIter)]
+    pub enum FigureSet {
+        #[default]
+        Classic,
+        Shapes,
+        // no figures, foods are scattered
+        Singles,
+    }
+
+    impl FigureSet {
+        fn source(self) -> &'static str {
+            match self {
+                Self::Classic => include_str!("../figures/classic.txt"),
+                Self::Shapes => include_str!("../figures/shapes.txt"),
+                Self::Singles => include_str!("../figures/singles.txt"),
+            }
+        }
 
-    #[derive(strum::EnumIter)]
-    pub enum Figures {
-        Diagonal2F,
-        Diagonal3,
-        X,
+        // parsed on first use
+        pub fn figures(self) -> &'static [Figure] {
+            static PARSED: [OnceLock<Vec<Figure>>; 3] =
+                [OnceLock::new(), OnceLock::new(), OnceLock::new()];
+
+            PARSED[self as usize]
+                .get_or_init(|| parse(self.source()).expect("embedded figures to be valid"))
+        }
     }
 
-    impl Figures {
-        pub fn to_iter(&self) -> Vec<Vec<FigureCell>> {
-            use matrix_to_iter as mi;
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct Figure {
+        pub name: String,
+        // relative chance to be picked within the set
+        pub weight: u32,
+        // rows of cells, all of the same length
+        cells: Vec<Vec<FigureCell>>,
+    }
 
-            let matrix = match self {
-                Self::Diagonal2F => mi(diagonal_2f()),
-                Self::Diagonal3 => mi(diagonal_3()),
-                Self::X => mi(figure_x()),
-            };
-            matrix
+    impl Figure {
+        pub fn width(&self) -> usize {
+            self.cells[0].len()
         }
 
-        pub fn x_dim(&self) -> usize {
-            let matrix = match self {
-                Self::Diagonal2F => x_dim(diagonal_2f),
-                Self::Diagonal3 => x_dim(diagonal_3),
-                Self::X => x_dim(figure_x),
-            };
-            matrix
+        pub fn height(&self) -> usize {
+            self.cells.len()
         }
 
-        pub fn y_dim(&self) -> usize {
-            let matrix = match self {
-                Self::Diagonal2F => y_dim(diagonal_2f),
-                Self::Diagonal3 => y_dim(diagonal_3),
-                Self::X => y_dim(figure_x),
-            };
-            matrix
+        // food cells relative to the top left corner
+        pub fn food_offsets(&self) -> Vec<Pos> {
+            self.cells
+                .iter()
+                .enumerate()
+                .flat_map(|(y, row)| {
+                    row.iter()
+                        .enumerate()
+                        .filter(|(_, cell)| cell.is_food())
+                        .map(move |(x, _)| Pos::new(x as i32, y as i32))
+                })
+                .collect()
         }
-    }
-
-    fn diagonal_2f() -> [[FigureCell; 2]; 2] {
-        use FigureCell::*;
-
-        [
-            //
-            [Empty, Food],
-            [Food, Empty],
-        ]
-    }
 
-    fn diagonal_3() -> [[FigureCell; 3]; 3] {
-        use FigureCell::*;
+        // quarter turn clockwise
+        pub fn rotated(&self) -> Self {
+            let cells = (0..self.width())
+                .map(|x| {
+                    (0..self.height())
+                        .rev()
+                        .map(|y| self.cells[y][x].clone())
+                        .collect()
+                })
+                .collect();
+
+            Self {
+                cells,
+                ..self.clone()
+            }
+        }
 
-        [
-            [Food, Empty, Empty],
-            [Empty, Food, Empty],
-            [Empty, Empty, Food],
-        ]
-    }
+        // flipped left to right
+        pub fn mirrored(&self) -> Self {
+            let cells = self
+                .cells
+                .iter()
+                .map(|row| row.iter().rev().cloned().collect())
+                .collect();
+
+            Self {
+                cells,
+                ..self.clone()
+            }
+        }
 
-    fn figure_x() -> [[FigureCell; 3]; 3] {
-        use FigureCell::*;
+        // one of the eight rotations and mirrorings, variant modulo 8
+        pub fn oriented(&self, variant: usize) -> Self {
+            let figure = match variant % 8 >= 4 {
+                true => self.mirrored(),
+                false => self.clone(),
+            };
 
-        [
-            [Food, Empty, Food],
-            [Empty, Food, Empty],
-            [Food, Empty, Food],
-        ]
+            (0..variant % 4).fold(figure, |figure, _| figure.rotated())
+        }
     }
 
-    #[derive(Clone, strum::EnumIs)]
+    #[derive(Clone, Debug, PartialEq, strum::EnumIs)]
     pub enum FigureCell {
         Empty,
         Food,
     }
 
-    fn x_dim<T, const C: usize, const R: usize, F: Fn() -> [[T; C]; R]>(f: F) -> usize {
-        C
-    }
+    // blocks separated by empty lines, each a `name weight` line
+    // followed by rows of `#` food and `.` empty cells, `#` lines before a block are comments
+    pub fn parse(source: &str) -> Result<Vec<Figure>, String> {
+        let mut figures = vec![];
+        let mut lines = source.lines().map(str::trim).peekable();
+
+        loop {
+            while lines
+                .peek()
+                .is_some_and(|line| line.is_empty() || line.starts_with("# "))
+            {
+                lines.next();
+            }
+
+            let Some(header) = lines.next() else {
+                break;
+            };
 
-    fn y_dim<T, const C: usize, const R: usize, F: Fn() -> [[T; C]; R]>(f: F) -> usize {
-        R
+            let (name, weight) = header.split_once(char::is_whitespace).ok_or(format!(
+                "figure header {header:?}: expected name and weight"
+            ))?;
+            let weight = weight
+                .trim()
+                .parse::<u32>()
+                .map_err(|_| format!("figure {name}: invalid weight {weight:?}"))?;
+
+            let mut cells = vec![];
+            while let Some(line) = lines.next_if(|line| !line.is_empty()) {
+                let row = line
+                    .chars()
+                    .map(|c| match c {
+                        '#' => Ok(FigureCell::Food),
+                        '.' => Ok(FigureCell::Empty),
+                        other => Err(format!("figure {name}: unexpected cell {other:?}")),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                cells.push(row);
+            }
+
+            if cells.is_empty() {
+                return Err(format!("figure {name}: no rows"));
+            }
+            if cells.iter().any(|row| row.len() != cells[0].len()) {
+                return Err(format!("figure {name}: rows differ in length"));
+            }
+            if !cells.iter().flatten().any(FigureCell::is_food) {
+                return Err(format!("figure {name}: no food"));
+            }
+
+            figures.push(Figure {
+                name: name.to_string(),
+                weight,
+                cells,
+            });
+        }
+
+        match figures.iter().any(|figure| figure.weight > 0) {
+            true => Ok(figures),
+            false => Err("figures: no figure with weight".into()),
+        }
     }
+}
 
-    fn matrix_to_iter<T, U, I>(array: T) -> Vec<Vec<I>>
-    where
-        T: IntoIterator<Item = U>,
-        U: IntoIterator<Item = I>,
-    {
-        array
-            .into_iter()
-            .map(|inner_array| inner_array.into_iter().collect())
-            .collect()
+pub mod placement {
+    use super::{figures::FigureSet, Boundaries, Food, Foods, Pos};
+    use std::collections::HashSet;
+
+    // puts foods on free cells, whole figures where they fit
+    pub struct FoodPlacement {
+        boundaries: Boundaries,
+        // snakes, portals, pickups
+        taken: HashSet<Pos>,
+        // of the placed foods
+        lifetime: Option<u32>,
+    }
+
+    impl FoodPlacement {
+        pub fn new(
+            boundaries: Boundaries,
+            taken: impl Iterator<Item = Pos>,
+            lifetime: Option<u32>,
+        ) -> Self {
+            Self {
+                boundaries,
+                taken: taken.collect(),
+                lifetime,
+            }
+        }
+
+        fn food(&self, pos: Pos) -> Food {
+            Food {
+                lifetime: self.lifetime,
+                ..Food::from(pos)
+            }
+        }
+
+        // cells inside the walls, not taken and without food
+        pub fn free_cells(&self, foods: &Foods) -> Vec<Pos> {
+            let b = self.boundaries;
+
+            ((b.min.x + 1)..b.max.x)
+                .flat_map(|x| ((b.min.y + 1)..b.max.y).map(move |y| Pos::new(x, y)))
+                .filter(|pos| !self.taken.contains(pos) && !foods.has_pos(*pos))
+                .collect()
+        }
+
+        // food count covering the share of cells inside the walls
+        pub fn target(&self, density: f64) -> usize {
+            let b = self.boundaries;
+            let cells = b.width().saturating_sub(1) * b.height().saturating_sub(1);
+
+            (cells as f64 * density).round() as usize
+        }
+
+        // adds foods up to the target count, figures of the set first, picked by weight,
+        // rotated and mirrored, then single foods scattered over what is left,
+        // `random(n)` picks an index below n
+        pub fn fill(
+            &self,
+            foods: &mut Foods,
+            target: usize,
+            set: FigureSet,
+            random: &mut impl FnMut(usize) -> usize,
+        ) {
+            let mut free = self.free_cells(foods).into_iter().collect::<HashSet<_>>();
+            let figures = set.figures();
+            let total = figures
+                .iter()
+                .map(|figure| figure.weight as usize)
+                .sum::<usize>();
+
+            while foods.count() < target {
+                let missing = target - foods.count();
+
+                // weighted pick, the rest are tried in order when it does not fit
+                let mut roll = random(total);
+                let first = figures
+                    .iter()
+                    .position(|figure| match roll < figure.weight as usize {
+                        true => true,
+                        false => {
+                            roll -= figure.weight as usize;
+                            false
+                        }
+                    })
+                    .unwrap();
+                let variant = random(8);
+
+                let placed = (0..figures.len())
+                    .map(|i| figures[(first + i) % figures.len()].oriented(variant))
+                    .filter(|figure| figure.weight > 0)
+                    .map(|figure| figure.food_offsets())
+                    .filter(|offsets| offsets.len() <= missing)
+                    .find_map(|offsets| {
+                        let origins = self.origins(&offsets, &free);
+                        (!origins.is_empty()).then(|| (origins[random(origins.len())], offsets))
+                    });
+
+                match placed {
+                    Some((origin, offsets)) => {
+                        for offset in offsets {
+                            let pos = origin + offset;
+                            free.remove(&pos);
+                            foods.insert(self.food(pos));
+                        }
+                    }
+                    None => {
+                        let mut cells = free.into_iter().collect::<Vec<_>>();
+                        // stable order, so only `random` decides
+                        cells.sort_by_key(|pos| (pos.x, pos.y));
+
+                        for _ in 0..missing.min(cells.len()) {
+                            let pos = cells.swap_remove(random(cells.len()));
+                            foods.insert(self.food(pos));
+                        }
+                        return;
+                    }
+                }
+            }
+        }
+
+        // top left corners where all foods of the figure are free, so inside the walls
+        fn origins(&self, offsets: &[Pos], free: &HashSet<Pos>) -> Vec<Pos> {
+            let b = self.boundaries;
+            let Some(first) = offsets.first() else {
+                return vec![];
+            };
+
+            // the first food lands on a free cell
+            let mut origins = free
+                .iter()
+                .map(|pos| *pos - *first)
+                .filter(|origin| {
+                    (b.min.x + 1..b.max.x).contains(&origin.x)
+                        && (b.min.y + 1..b.max.y).contains(&origin.y)
+                })
+                .filter(|origin| {
+                    offsets
+                        .iter()
+                        .all(|offset| free.contains(&(*origin + *offset)))
+                })
+                .collect::<Vec<_>>();
+            // stable order, so only `random` decides
+            origins.sort_by_key(|pos| (pos.x, pos.y));
+            origins
+        }
     }
 }
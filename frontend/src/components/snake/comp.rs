@@ -2,24 +2,31 @@
 use crate::components::imports::*;
 use futures::SinkExt;
 use gloo_events::{EventListener, EventListenerOptions};
+use gloo_render::{request_animation_frame, AnimationFrame};
 use gloo_timers::callback::Interval;
-use std::collections::HashSet;
+use std::collections::{HashSet, VecDeque};
 use wasm_bindgen::{JsCast, JsValue};
 use web_sys::{CanvasRenderingContext2d, Document, HtmlCanvasElement, Window};
 use yew::{classes, html::Scope};
 
 use interfacing::snake::{
-    lobby_state::{LobbyPrep, LobbyRunning},
-    JoinLobbyDecline, LobbyName, LobbyState, PinnedMessage, UserName, WsClientMsg, WsMsg,
+    lobby_state::{
+        BoostSettings, CaptureZone, GameEvents, GameOver, HillSettings, LobbyCountdown, LobbyPrep,
+        LobbyRunning, LobbyRunningPlayer, LobbyRunningTeam, LobbySeries, LobbySettings,
+        SeriesSettings, ShrinkSettings, TeamSettings, TeammateCollision, TimedRanking,
+        TimedSettings, UpcomingBoundaries,
+    },
+    JoinLobbyDecline, LobbyName, LobbyState, PinnedMessage, PlayerId, UserName, WsClientMsg, WsMsg,
     WsServerMsg,
 };
 
+use super::prediction::{self, DrawnSnake, Prediction};
 use super::styles;
 
 type ClientMsg = WsMsg<interfacing::snake::WsClientMsg>;
 type ServerMsg = WsMsg<interfacing::snake::WsServerMsg>;
 
-use domain::Domain;
+use domain::{figures::FigureSet, Domain};
 use interfacing::snake_domain as domain;
 
 const PAUSED: bool = false;
@@ -36,7 +43,25 @@ const MAP_BOUNDARIES_Y: i32 = 10;
 
 const PANEL_PX_WIDTH: u32 = 350;
 
+const KILL_FEED_CAPACITY: usize = 6;
+const TOAST_DURATION: i32 = 3000; // in milliseconds
+
 const SNAKE_BODY_WIDTH: f64 = 0.9;
+
+const DANGER_ZONE_COLOR: &str = "rgba(230, 25, 75, 0.25)";
+const CAPTURE_ZONE_COLOR: &str = "rgba(128, 128, 128, 0.25)";
+// appended to holder color
+const CAPTURE_ZONE_ALPHA: &str = "40";
+// by pair, repeats when there are more pairs
+const PORTAL_COLORS: [&str; 2] = ["#42d4f4", "#f032e6"];
+const PORTAL_DIAMETER: f64 = 0.9;
+const PICKUP_DIAMETER: f64 = 0.7;
+// auras around the head, one ring per active power-up
+const AURA_WIDTH: f64 = 0.12;
+const AURA_GAP: f64 = 0.25;
+// glow around boosting snakes, appended to player color
+const BOOST_ALPHA: &str = "60";
+const BOOST_GLOW_WIDTH: f64 = 1.5;
 const FOOD_DIAMETER: f64 = 0.6;
 
 #[derive(Debug, Default)]
@@ -49,6 +74,10 @@ pub struct WsState {
     synced_user_name: bool,
     joined_lobby_name: Option<LobbyName>,
     joined_lobby_state: Option<interfacing::snake::LobbyState>,
+    // why the connection was closed, shown until reload
+    closed: Option<String>,
+    // seconds the server gave before closing for maintenance
+    maintenance: Option<u32>,
 }
 
 pub struct Snake {
@@ -58,15 +87,48 @@ pub struct Snake {
     canvas_requires_fit: bool,
 
     camera: Camera,
+    // interpolated own mouth the camera follows, in multiplayer
+    focus: Option<prediction::Point>,
 
     refs: Refs,
     listeners: Listeners,
+    // redraws the canvas every frame in multiplayer
+    animation_frame: Option<AnimationFrame>,
 
     ws_sink: tokio::sync::mpsc::UnboundedSender<ClientMsg>,
     ws_state: WsState,
     acknowledgeable_messages: AcknowledgeableMessages,
+    game_feed: GameFeed,
 
     theme_ctx: ThemeCtxSub,
+
+    // picked before a single player game
+    sp_food: SPFoodSettings,
+}
+
+// multiplayer game events, as shown to the player
+#[derive(Default)]
+struct GameFeed {
+    // latest last
+    kill_feed: VecDeque<String>,
+    toasts: Vec<(u32, String)>,
+    toast_counter: u32,
+}
+
+impl GameFeed {
+    fn push_kill(&mut self, line: String) {
+        if self.kill_feed.len() == KILL_FEED_CAPACITY {
+            self.kill_feed.pop_front();
+        }
+        self.kill_feed.push_back(line);
+    }
+
+    // returns id to expire the toast with
+    fn push_toast(&mut self, text: String) -> u32 {
+        self.toast_counter += 1;
+        self.toasts.push((self.toast_counter, text));
+        self.toast_counter
+    }
 }
 
 #[derive(Default, derived_deref::Deref, derived_deref::DerefMut)]
@@ -78,6 +140,7 @@ pub enum SnakeMsg {
     Advance,
     Restart,
     DirectionChange(domain::Direction),
+    Boost(bool),
     WindowLoaded,
     WindowResized,
     FitCanvasImmediately,
@@ -88,11 +151,16 @@ pub enum SnakeMsg {
     RedirectToLobby { lobby_name: LobbyName },
     StateChange(State),
     Begin,
+    SPFoodChange(SPFoodSettings),
     PauseUnpause,
     ToMenu,
     // LeaveLobby,
     WsSend(ClientMsg),
     WsRecv(ServerMsg),
+    ToastExpired(u32),
+    AnimationFrame,
+    // close code and reason
+    WsClosed(u16, String),
 }
 
 #[derive(Properties, PartialEq)]
@@ -124,7 +192,7 @@ impl Component for Snake {
                     // TODO impl reconnect
                     Err(gloo_net::websocket::WebSocketError::ConnectionClose(e)) => {
                         console::log!(format!("{} {} {}", e.code, e.reason, e.was_clean));
-                        SnakeMsg::Nothing
+                        SnakeMsg::WsClosed(e.code, e.reason)
                     }
                     Err(gloo_net::websocket::WebSocketError::ConnectionError) => {
                         console::log!("! read channel ConnectionError");
@@ -167,15 +235,20 @@ impl Component for Snake {
             canvas_requires_fit: false,
 
             camera: CAMERA,
+            focus: None,
 
             refs: Default::default(),
             listeners: Listeners::init(ctx.link().clone()),
+            animation_frame: None,
 
             ws_sink,
             ws_state: Default::default(),
             acknowledgeable_messages: Default::default(),
+            game_feed: Default::default(),
 
             theme_ctx: ThemeCtxSub::subscribe(ctx, Self::Message::ThemeContextUpdate),
+
+            sp_food: Default::default(),
         }
     }
 
@@ -290,8 +363,32 @@ impl Component for Snake {
                         }
                     };
 
-                    let restart_btn = if multiplayer {
-                        html! {}
+                    let restart_btn = if let State::BegunMultiplayer {
+                        respawn_in,
+                        countdown,
+                        ..
+                    } = s
+                    {
+                        // take back the start vote
+                        let cancel_onclick = ctx.link().callback(move |e| {
+                            Self::Message::WsSend(
+                                "vote-start"
+                                    .pinned_msg(interfacing::snake::WsClientMsg::VoteStart(false)),
+                            )
+                        });
+
+                        match respawn_in {
+                            _ if countdown.is_some() => {
+                                html! {<div class={ btn_style.clone() } onclick={cancel_onclick}>{ "Cancel start" }</div>}
+                            }
+                            None => html! {},
+                            Some(0) => {
+                                html! {<div ref={self.refs.btn_refs.restart_btn_ref.clone()} class={ btn_style.clone() } onclick={restart_btn_onclick}>{ "Respawn (R)" }</div>}
+                            }
+                            Some(ticks) => {
+                                html! {<div ref={self.refs.btn_refs.restart_btn_ref.clone()} class={ btn_style.clone() }>{ format!("Respawn in {ticks}") }</div>}
+                            }
+                        }
                     } else {
                         html! {<div ref={self.refs.btn_refs.restart_btn_ref.clone()} class={ btn_style.clone() } onclick={restart_btn_onclick}>{ "Restart (R)" }</div>}
                     };
@@ -302,6 +399,21 @@ impl Component for Snake {
                         html! {<div ref={self.refs.btn_refs.pause_btn_ref.clone()} class={ btn_style.clone() } onclick={pause_btn_onclick}>{ "Pause (P)" }</div>}
                     };
 
+                    let leaderboard = match s {
+                        State::BegunMultiplayer {
+                            scoreboard,
+                            teams,
+                            zones,
+                            ..
+                        } => html! {
+                            <>
+                                { leaderboard(scoreboard, teams, !zones.is_empty(), text_color) }
+                                { kill_feed(&self.game_feed.kill_feed, text_color) }
+                            </>
+                        },
+                        _ => html! {},
+                    };
+
                     html! {
                         <>
                             { menu_btn }
@@ -322,6 +434,7 @@ impl Component for Snake {
                             <div ref={self.refs.btn_refs.camera_btn_ref.clone()} class={ btn_style.clone() } onclick={camera_btn_onclick}>{ "Camera (C)" }</div>
                             {restart_btn}
                             {pause_btn}
+                            {leaderboard}
                         </>
                     }
                 }
@@ -354,9 +467,83 @@ impl Component for Snake {
 
         let main_area = 'main_area: {
             match &self.state {
-                State::BegunSingleplayer { .. } | State::BegunMultiplayer { .. } => {
+                State::BegunSingleplayer { .. } => {
                     html! { <canvas ref={self.refs.canvas_ref.clone() }></canvas> }
                 }
+                State::BegunMultiplayer {
+                    scoreboard,
+                    respawn_in,
+                    game_over,
+                    upcoming_boundaries,
+                    ticks_left,
+                    series,
+                    countdown,
+                    ..
+                } => {
+                    let toasts_style = css! {"
+                        position: fixed;
+                        top: 20px;
+                        left: calc((100% - ${panel_width}px) / 2);
+                        transform: translateX(-50%);
+                        pointer-events: none;
+                        font-family: 'Iosevka Web';
+                        font-size: 25px;
+                        color: ${text_color};
+                        text-align: center;
+                    ",
+                        panel_width = PANEL_PX_WIDTH,
+                        text_color = text_color
+                    };
+
+                    let toasts = self
+                        .game_feed
+                        .toasts
+                        .iter()
+                        .map(|(_, text)| html! { <p>{ text }</p> })
+                        .collect::<Html>();
+
+                    let respawn = match respawn_in {
+                        _ if game_over.is_some() => html! {},
+                        None => html! {},
+                        Some(0) => html! { <p>{ "Press R to respawn" }</p> },
+                        Some(ticks) => html! { <p>{ format!("Respawn in {ticks}") }</p> },
+                    };
+
+                    html! {
+                        <>
+                            <canvas ref={self.refs.canvas_ref.clone() }></canvas>
+                            <div class={toasts_style}>
+                                if let Some(seconds) = countdown {
+                                    <h1>{ format!("Starting in {seconds}") }</h1>
+                                }
+                                if let Some(GameOver { winner, standings }) = game_over {
+                                    if let Some(series) = series.as_ref().filter(|series| series.ended) {
+                                        <p>{ "Series over! " }{ game_over_text(series.winner, scoreboard) }</p>
+                                        { series_standings(series, scoreboard) }
+                                    } else if let Some(series) = series {
+                                        <p>{ format!("Round {} over! ", series.round) }{ game_over_text(*winner, scoreboard) }</p>
+                                        { series_standings(series, scoreboard) }
+                                        if let Some(ticks) = series.next_round_in {
+                                            <p>{ format!("Next round in {ticks}") }</p>
+                                        }
+                                    } else {
+                                        <p>{ "Game over! " }{ game_over_text(*winner, scoreboard) }</p>
+                                        { final_standings(standings, scoreboard) }
+                                    }
+                                } else if let Some(series) = series {
+                                    <p>{ format!("Round {} of {}", series.round, series.rounds) }</p>
+                                }
+                                { toasts }{ respawn }
+                                if let Some(upcoming) = upcoming_boundaries {
+                                    <p>{ format!("Arena shrinks in {}", upcoming.in_ticks) }</p>
+                                }
+                                if let (Some(ticks), None) = (ticks_left, game_over) {
+                                    <p>{ format!("Time left {ticks}") }</p>
+                                }
+                            </div>
+                        </>
+                    }
+                }
                 State::NotBegun {
                     inner: NotBegunState::MPLobbyList { lobbies },
                 } => match lobbies {
@@ -524,7 +711,10 @@ impl Component for Snake {
                                 self.ws_state.joined_lobby_name.as_ref().expect("to exist");
 
                             let block = match ls {
-                                LobbyState::Prep(LobbyPrep { participants }) => {
+                                LobbyState::Prep(LobbyPrep {
+                                    participants,
+                                    settings,
+                                }) => {
                                     let onclick = ctx.link().callback(move |e| {
                                         Self::Message::WsSend("vote-start".pinned_msg(
                                             interfacing::snake::WsClientMsg::VoteStart(true),
@@ -534,9 +724,18 @@ impl Component for Snake {
                                     let part = participants
                                         .into_iter()
                                         .map(|p| {
+                                            let team = match (p.team, &p.color) {
+                                                (Some(team), Some(color)) => html! {
+                                                    <span style={format!("color: {color};")}>
+                                                        { format!(" [Team {team}]") }
+                                                    </span>
+                                                },
+                                                _ => html! {},
+                                            };
+
                                             html! {
                                                 <>
-                                                <h2>{&p.user_name} {" voted: "} {p.vote_start} </h2>
+                                                <h2>{&p.user_name} {team} {" voted: "} {p.vote_start} </h2>
                                                 </>
                                             }
                                         })
@@ -551,25 +750,309 @@ impl Component for Snake {
                                         styles::average_btn_style(),
                                     ];
 
+                                    // sends settings to replace current ones
+                                    let setting_btn = |text: String, settings: LobbySettings| {
+                                        let onclick = ctx.link().callback(move |e| {
+                                            Self::Message::WsSend("lobby-settings".pinned_msg(
+                                                WsClientMsg::SetLobbySettings(settings.clone()),
+                                            ))
+                                        });
+
+                                        html! { <div class={btn_style.clone()} {onclick}>{ text }</div> }
+                                    };
+
+                                    let on_off = |on| if on { "on" } else { "off" };
+
+                                    let respawn_btn = {
+                                        let text = match settings.respawn_cooldown {
+                                            Some(ticks) => format!("Respawn: after {ticks} ticks"),
+                                            None => "Respawn: off".to_owned(),
+                                        };
+                                        let mut next = settings.clone();
+                                        next.respawn_cooldown = match settings.respawn_cooldown {
+                                            Some(_) => None,
+                                            None => Some(LobbySettings::DEFAULT_RESPAWN_COOLDOWN),
+                                        };
+                                        setting_btn(text, next)
+                                    };
+
+                                    let mid_game_join_btn = {
+                                        let text = format!(
+                                            "Mid-game join: {}",
+                                            on_off(settings.mid_game_join)
+                                        );
+                                        let mut next = settings.clone();
+                                        next.mid_game_join = !settings.mid_game_join;
+                                        setting_btn(text, next)
+                                    };
+
+                                    // off, then every possible count
+                                    let teams_btn = {
+                                        let text = match &settings.teams {
+                                            Some(teams) => format!("Teams: {}", teams.count),
+                                            None => "Teams: off".to_owned(),
+                                        };
+                                        let mut next = settings.clone();
+                                        next.teams = match &settings.teams {
+                                            None => Some(TeamSettings {
+                                                count: TeamSettings::MIN_COUNT,
+                                                teammate_collision: TeammateCollision::PassThrough,
+                                            }),
+                                            Some(teams)
+                                                if teams.count < TeamSettings::MAX_COUNT =>
+                                            {
+                                                Some(TeamSettings {
+                                                    count: teams.count + 1,
+                                                    ..teams.clone()
+                                                })
+                                            }
+                                            Some(_) => None,
+                                        };
+                                        setting_btn(text, next)
+                                    };
+
+                                    let shrinking_btn = {
+                                        let text = format!(
+                                            "Shrinking arena: {}",
+                                            on_off(settings.shrinking.is_some())
+                                        );
+                                        let mut next = settings.clone();
+                                        next.shrinking = match settings.shrinking {
+                                            Some(_) => None,
+                                            None => Some(ShrinkSettings::default()),
+                                        };
+                                        setting_btn(text, next)
+                                    };
+
+                                    let hill_btn = {
+                                        let text = format!(
+                                            "King of the hill: {}",
+                                            on_off(settings.hill.is_some())
+                                        );
+                                        let mut next = settings.clone();
+                                        next.hill = match settings.hill {
+                                            Some(_) => None,
+                                            None => Some(HillSettings::default()),
+                                        };
+                                        setting_btn(text, next)
+                                    };
+
+                                    // off, then every ranking
+                                    let timed_btn = {
+                                        let text = match &settings.timed {
+                                            Some(timed) => format!(
+                                                "Timed: {} ticks, {}",
+                                                timed.ticks,
+                                                match timed.ranking {
+                                                    TimedRanking::Length => "longest wins",
+                                                    TimedRanking::Score => "best score wins",
+                                                }
+                                            ),
+                                            None => "Timed: off".to_owned(),
+                                        };
+                                        let mut next = settings.clone();
+                                        next.timed = match &settings.timed {
+                                            None => Some(TimedSettings::default()),
+                                            Some(timed)
+                                                if timed.ranking == TimedRanking::Length =>
+                                            {
+                                                Some(TimedSettings {
+                                                    ranking: TimedRanking::Score,
+                                                    ..timed.clone()
+                                                })
+                                            }
+                                            Some(_) => None,
+                                        };
+                                        setting_btn(text, next)
+                                    };
+
+                                    let food_lifetime_btn = {
+                                        let (text, lifetime) =
+                                            next_food_lifetime(settings.food_lifetime);
+                                        let mut next = settings.clone();
+                                        next.food_lifetime = lifetime;
+                                        setting_btn(format!("Food lifetime: {text}"), next)
+                                    };
+
+                                    let food_flee_btn = {
+                                        let (text, every) =
+                                            next_food_flee_every(settings.food_flee_every);
+                                        let mut next = settings.clone();
+                                        next.food_flee_every = every;
+                                        setting_btn(format!("Fleeing food: {text}"), next)
+                                    };
+
+                                    let portals_btn = {
+                                        let text = format!("Portals: {}", on_off(settings.portals));
+                                        let mut next = settings.clone();
+                                        next.portals = !settings.portals;
+                                        setting_btn(text, next)
+                                    };
+
+                                    let power_ups_btn = {
+                                        let text =
+                                            format!("Power-ups: {}", on_off(settings.power_ups));
+                                        let mut next = settings.clone();
+                                        next.power_ups = !settings.power_ups;
+                                        setting_btn(text, next)
+                                    };
+
+                                    let series_btn = {
+                                        // off, then more rounds each click
+                                        let (text, rounds) = match &settings.series {
+                                            None => ("off".to_string(), Some(3)),
+                                            Some(series) => (
+                                                format!("best of {}", series.rounds),
+                                                match series.rounds {
+                                                    3 => Some(5),
+                                                    5 => Some(7),
+                                                    _ => None,
+                                                },
+                                            ),
+                                        };
+                                        let mut next = settings.clone();
+                                        next.series = rounds.map(|rounds| SeriesSettings {
+                                            rounds,
+                                            ..settings.series.clone().unwrap_or_default()
+                                        });
+                                        setting_btn(format!("Series: {text}"), next)
+                                    };
+
+                                    let boost_btn = {
+                                        // off, then shedding slower each click
+                                        let (text, boost) = match &settings.boost {
+                                            None => ("off".to_string(), Some(1)),
+                                            Some(boost) => (
+                                                format!("shed every {} ticks", boost.shed_every),
+                                                match boost.shed_every {
+                                                    1 => Some(2),
+                                                    2 => Some(4),
+                                                    _ => None,
+                                                },
+                                            ),
+                                        };
+                                        let mut next = settings.clone();
+                                        next.boost =
+                                            boost.map(|shed_every| BoostSettings { shed_every });
+                                        setting_btn(format!("Boost: {text}"), next)
+                                    };
+
+                                    let figures_btn = {
+                                        // every set in turn
+                                        let (text, figures) = match settings.figures {
+                                            FigureSet::Classic => ("classic", FigureSet::Shapes),
+                                            FigureSet::Shapes => ("shapes", FigureSet::Singles),
+                                            FigureSet::Singles => ("singles", FigureSet::Classic),
+                                        };
+                                        let mut next = settings.clone();
+                                        next.figures = figures;
+                                        setting_btn(format!("Food figures: {text}"), next)
+                                    };
+
+                                    let team_btns = match &settings.teams {
+                                        None => html! {},
+                                        Some(teams) => {
+                                            let collision_btn = {
+                                                use TeammateCollision::*;
+
+                                                let (text, collision) =
+                                                    match teams.teammate_collision {
+                                                        PassThrough => ("pass through", Block),
+                                                        Block => ("block", PassThrough),
+                                                    };
+                                                let mut next = settings.clone();
+                                                next.teams = Some(TeamSettings {
+                                                    teammate_collision: collision,
+                                                    ..teams.clone()
+                                                });
+                                                setting_btn(format!("Teammates: {text}"), next)
+                                            };
+
+                                            let pick_btns = (1..=teams.count)
+                                                .map(|team| {
+                                                    let onclick = ctx.link().callback(move |e| {
+                                                        Self::Message::WsSend(
+                                                            "pick-team".pinned_msg(
+                                                                WsClientMsg::PickTeam(team),
+                                                            ),
+                                                        )
+                                                    });
+
+                                                    html! {
+                                                        <div class={btn_style.clone()} {onclick}>
+                                                            { format!("Join team {team}") }
+                                                        </div>
+                                                    }
+                                                })
+                                                .collect::<Html>();
+
+                                            html! {
+                                                <>
+                                                { collision_btn }
+                                                <p></p>
+                                                <div class={css!("display: flex; gap: 10px;")}>
+                                                    { pick_btns }
+                                                </div>
+                                                <p></p>
+                                                </>
+                                            }
+                                        }
+                                    };
+
                                     html! {
                                         <>
-                                        <div class={btn_style} {onclick}> { "Vote start" } </div>
+                                        <div class={btn_style.clone()} {onclick}> { "Vote start" } </div>
+                                        <p></p>
+                                        { respawn_btn }
+                                        <p></p>
+                                        { mid_game_join_btn }
+                                        <p></p>
+                                        { teams_btn }
+                                        <p></p>
+                                        { shrinking_btn }
+                                        <p></p>
+                                        { hill_btn }
+                                        <p></p>
+                                        { timed_btn }
+                                        <p></p>
+                                        { portals_btn }
+                                        <p></p>
+                                        { boost_btn }
+                                        <p></p>
+                                        { power_ups_btn }
+                                        <p></p>
+                                        { series_btn }
+                                        <p></p>
+                                        { figures_btn }
+                                        <p></p>
+                                        { food_lifetime_btn }
+                                        <p></p>
+                                        { food_flee_btn }
                                         <p></p>
+                                        { team_btns }
                                         {part}
                                         </>
                                     }
                                 }
 
-                                LobbyState::Running(LobbyRunning {
-                                    counter,
-                                    player_counter,
-                                    domain,
-                                }) => {
+                                LobbyState::Countdown(countdown) => {
                                     ctx.link().send_message(SnakeMsg::StateChange(
-                                        State::BegunMultiplayer {
-                                            domain: domain.clone(),
-                                            px_scale: calc_px_scale(&domain.boundaries),
-                                        },
+                                        State::counting_down(countdown),
+                                    ));
+
+                                    html! {
+                                        <h1>{"Starting in "} { countdown.seconds_left }</h1>
+                                    }
+                                }
+
+                                LobbyState::Running(running) => {
+                                    let LobbyRunning {
+                                        counter,
+                                        player_counter,
+                                        ..
+                                    } = running.as_ref();
+                                    ctx.link().send_message(SnakeMsg::StateChange(
+                                        State::begun_multiplayer(running),
                                     ));
 
                                     html! {
@@ -788,10 +1271,33 @@ impl Component for Snake {
                     ];
 
                     let start_btn_onclick = ctx.link().callback(move |e| Self::Message::Begin);
+
+                    let food_btns = {
+                        let food = self.sp_food;
+                        let food_btn = |text: String, next: SPFoodSettings| {
+                            let onclick = ctx
+                                .link()
+                                .callback(move |e| Self::Message::SPFoodChange(next));
+                            html! { <div {onclick} class={ btn_style.clone() }>{ text }</div> }
+                        };
+
+                        let (lifetime_text, lifetime) = next_food_lifetime(food.lifetime);
+                        let (flee_text, flee_every) = next_food_flee_every(food.flee_every);
+                        html! {
+                            <>
+                                { food_btn(format!("Food lifetime: {lifetime_text}"), SPFoodSettings { lifetime, ..food }) }
+                                { food_btn(format!("Fleeing food: {flee_text}"), SPFoodSettings { flee_every, ..food }) }
+                            </>
+                        }
+                    };
+
                     let items = match inner {
                         NotBegunState::Initial => {
                             html! {
-                                <div onclick={start_btn_onclick} class={ btn_style.clone() }>{ "Start" }</div>
+                                <>
+                                    <div onclick={start_btn_onclick} class={ btn_style.clone() }>{ "Start" }</div>
+                                    { food_btns }
+                                </>
                             }
                         }
                         NotBegunState::Ended => {
@@ -799,6 +1305,7 @@ impl Component for Snake {
                                 <>
                                     <p class={css!{"font-size: 35px;"}}>{"Game over!"}</p>
                                     <div onclick={start_btn_onclick} class={ btn_style.clone() }>{ "Try again" }</div>
+                                    { food_btns }
                                 </>
                             }
                         }
@@ -854,11 +1361,39 @@ impl Component for Snake {
             }
         };
 
+        let notice = match (&self.ws_state.closed, self.ws_state.maintenance) {
+            (Some(closed), _) => Some(closed.clone()),
+            (None, Some(secs)) => Some(format!(
+                "Server is going down for maintenance, games end within {secs}s"
+            )),
+            (None, None) => None,
+        };
+        let notice = notice.map(|text| {
+            let notice_style = css! {"
+                position: fixed;
+                bottom: 20px;
+                left: 50%;
+                transform: translateX(-50%);
+                padding: 10px 20px;
+                font-family: 'Iosevka Web';
+                font-size: 20px;
+                color: ${text_color};
+                background-color: ${bg_color};
+                border: 2px solid ${text_color};
+            ",
+                text_color = text_color,
+                bg_color = bg_color,
+            };
+
+            html! { <div class={notice_style}>{ text }</div> }
+        });
+
         html! {
             <>
                 <Global css={global_style}/>
                 <PageTitle title={"Snake"}/>
                 { body }
+                { notice }
             </>
         }
     }
@@ -889,66 +1424,12 @@ impl Component for Snake {
             // }
         }
 
-        let theme = self.theme_ctx.as_ref();
-        let bg_color = &theme.bg_color;
-        let box_border_color = &theme.box_border_color;
-
         if self.canvas_requires_fit {
             ctx.link().send_message(Self::Message::FitCanvasImmediately);
         }
 
-        match &self.state {
-            s @ (State::BegunSingleplayer { .. } | State::BegunMultiplayer { .. }) => {
-                let r = self.refs.canvas_renderer();
-                r.set_stroke_style(box_border_color);
-                r.set_line_join("round");
-                r.set_fill_style(bg_color);
-
-                // assert!(self.refs.is_canvas_fit(self.state));
-                let cd = self.refs.canvas_dimensions();
-                r.fill_rect(domain::Boundaries {
-                    min: domain::Pos::new(0, 0),
-                    max: domain::Pos::new(cd.width as i32, cd.height as i32),
-                });
-
-                match s {
-                    State::NotBegun { .. } => unreachable!(),
-                    State::BegunSingleplayer {
-                        snake,
-                        foods,
-                        boundaries,
-                        px_scale,
-                        ..
-                    } => {
-                        self.draw_snake(&r, snake, boundaries, false, *px_scale);
-                        self.draw_foods(&r, foods, Some(snake), boundaries, *px_scale);
-                        self.draw_boundaries(&r, boundaries, Some(snake), *px_scale);
-                    }
-                    State::BegunMultiplayer {
-                        domain:
-                            Domain {
-                                snake,
-                                other_snakes,
-                                foods,
-                                boundaries,
-                            },
-                        px_scale,
-                    } => {
-                        if let Some(snake) = snake {
-                            self.draw_snake(&r, snake, boundaries, true, *px_scale);
-                        }
-                        for snake in other_snakes {
-                            self.draw_snake(&r, snake, boundaries, false, *px_scale);
-                        }
-                        self.draw_foods(&r, foods, snake.as_ref(), boundaries, *px_scale);
-                        self.draw_boundaries(&r, boundaries, snake.as_ref(), *px_scale);
-                    }
-                }
-            }
-            State::NotBegun { .. } => {}
-        }
-        // console::log!("random int (0..1)", rand_from_iterator(0..1));
-        // console::log!("random int (0..2)", rand_from_iterator(0..2));
+        self.draw_canvas();
+        self.request_animation_frame(ctx);
     }
 
     fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
@@ -963,6 +1444,30 @@ impl Component for Snake {
             // }
             Self::Message::WsRecv(msg) => self.handle_received_message(ctx, msg),
 
+            Self::Message::AnimationFrame => {
+                self.animation_frame = None;
+                self.draw_canvas();
+                self.request_animation_frame(ctx);
+                false
+            }
+
+            Self::Message::ToastExpired(id) => {
+                self.game_feed.toasts.retain(|(i, _)| *i != id);
+                true
+            }
+
+            Self::Message::WsClosed(code, reason) => {
+                let text = match interfacing::snake::CloseReason::from_code(code) {
+                    Some(known) if known.description() == reason || reason.is_empty() => {
+                        known.description().to_owned()
+                    }
+                    Some(known) => format!("{}: {reason}", known.description()),
+                    None => format!("Connection closed ({code})"),
+                };
+                self.ws_state.closed = Some(format!("{text}, reload to reconnect"));
+                true
+            }
+
             Self::Message::WsSend(msg) => {
                 if let WsMsg(Some(id), msg) = &msg {
                     self.acknowledgeable_messages
@@ -993,24 +1498,23 @@ impl Component for Snake {
                 false
             }
 
-            Self::Message::FitCanvasImmediately => match &mut self.state {
-                State::BegunSingleplayer {
-                    boundaries,
-                    px_scale,
-                    ..
-                }
-                | State::BegunMultiplayer {
-                    domain: Domain { boundaries, .. },
-                    px_scale,
-                    ..
-                } => {
-                    self.canvas_requires_fit = false;
-                    self.refs.fit_canvas();
-                    *px_scale = calc_px_scale(boundaries);
-                    true
-                }
-                State::NotBegun { .. } => false,
-            },
+            Self::Message::FitCanvasImmediately => {
+                let (boundaries, px_scale) = match &mut self.state {
+                    State::BegunSingleplayer {
+                        boundaries,
+                        px_scale,
+                        ..
+                    } => (*boundaries, px_scale),
+                    State::BegunMultiplayer {
+                        domain, px_scale, ..
+                    } => (domain.boundaries, px_scale),
+                    State::NotBegun { .. } => return false,
+                };
+                self.canvas_requires_fit = false;
+                self.refs.fit_canvas();
+                *px_scale = calc_px_scale(&boundaries);
+                true
+            }
 
             Self::Message::Advance => {
                 match &mut self.state {
@@ -1018,6 +1522,7 @@ impl Component for Snake {
                         snake,
                         foods,
                         boundaries,
+                        food,
                         ..
                     } => {
                         let game_over = || {
@@ -1027,20 +1532,30 @@ impl Component for Snake {
                                 }));
                         };
 
-                        match snake.advance(foods, &[], &boundaries) {
+                        match snake.advance(foods, &[], boundaries, &[]) {
                             domain::AdvanceResult::Success => {
+                                foods.tick();
+                                if let Some(every) = food.flee_every {
+                                    let body = snake.iter_vertices().collect::<HashSet<_>>();
+                                    foods.flee(every, &[snake.mouth()], boundaries, |pos| {
+                                        body.contains(&pos)
+                                    });
+                                }
+
                                 // when no food, replenish
                                 if foods.empty() {
                                     *foods = DomainDefaults::foods(
                                         rand_from_iterator(10..15),
                                         *boundaries,
                                         snake.iter_vertices(),
+                                        food.lifetime,
                                     );
                                 }
                             }
                             domain::AdvanceResult::BitYaSelf
                             | domain::AdvanceResult::OutOfBounds => game_over(),
-                            domain::AdvanceResult::BitSomeone => unreachable!(),
+                            domain::AdvanceResult::BitSomeone(_)
+                            | domain::AdvanceResult::Shielded => unreachable!(),
                         }
 
                         true
@@ -1065,10 +1580,11 @@ impl Component for Snake {
                         snake,
                         boundaries,
                         foods,
+                        food,
                         advance_interval,
                         ..
                     } => {
-                        let defaults = SPDomainDefaults::defaults();
+                        let defaults = SPDomainDefaults::defaults(*food);
                         *snake = defaults.snake;
                         *foods = defaults.foods;
                         *boundaries = defaults.boundaries;
@@ -1080,8 +1596,13 @@ impl Component for Snake {
 
                         true
                     }
-                    State::BegunMultiplayer { .. } => {
-                        console::log!("Restart unsupported in MP");
+                    State::BegunMultiplayer { respawn_in, .. } => {
+                        if *respawn_in == Some(0) {
+                            ctx.link().send_message(SnakeMsg::WsSend(
+                                "respawn".pinned_msg(WsClientMsg::Respawn),
+                            ));
+                            Refs::fire_btn_active(self.refs.restart_btn_el());
+                        }
 
                         false
                     }
@@ -1096,10 +1617,16 @@ impl Component for Snake {
                             console::log!("cannot move into the opposite direction")
                         }
                     }
-                    State::BegunMultiplayer { .. } => {
+                    State::BegunMultiplayer {
+                        domain, prediction, ..
+                    } => {
+                        // shown right away, the server sends the same turn back
+                        if prediction.input(direction, domain).is_err() {
+                            console::log!("cannot move into the opposite direction")
+                        }
                         ctx.link().send_message(SnakeMsg::WsSend(WsMsg(
                             None,
-                            WsClientMsg::SetDirection(direction),
+                            WsClientMsg::SetDirection(direction, prediction.tick()),
                         )));
                     }
                     State::NotBegun { .. } => {}
@@ -1111,6 +1638,18 @@ impl Component for Snake {
                 false
             }
 
+            // only multiplayer, server ignores it when disabled
+            Self::Message::Boost(boosting) => {
+                if let State::BegunMultiplayer { .. } = self.state {
+                    ctx.link().send_message(SnakeMsg::WsSend(WsMsg(
+                        None,
+                        WsClientMsg::SetBoost(boosting),
+                    )));
+                }
+
+                false
+            }
+
             Self::Message::CameraChange(camera) => self.change_camera(camera).is_ok(),
 
             Self::Message::CameraToggle => {
@@ -1129,15 +1668,28 @@ impl Component for Snake {
                 true
             }
 
-            Self::Message::StateChange(new_state @ State::BegunMultiplayer { .. }) => {
+            Self::Message::StateChange(mut new_state @ State::BegunMultiplayer { .. }) => {
+                // carry the prediction over to the authoritative state
+                if let (
+                    State::BegunMultiplayer {
+                        domain: old_domain,
+                        prediction: old,
+                        ..
+                    },
+                    State::BegunMultiplayer {
+                        domain, prediction, ..
+                    },
+                ) = (&self.state, &mut new_state)
+                {
+                    prediction.reconcile(old, old_domain, domain);
+                }
+
                 // TODO rework camera management
-                if let State::BegunMultiplayer {
+                if let State::BegunMultiplayer { domain, .. } = &new_state {
                     // no snake to control, no mouth to center to
-                    domain: Domain { snake: None, .. },
-                    ..
-                } = &new_state
-                {
-                    self.change_camera(Camera::BoundariesCentered).unwrap_or(());
+                    if domain.snake.is_none() {
+                        self.change_camera(Camera::BoundariesCentered).unwrap_or(());
+                    }
                 }
 
                 match self.state {
@@ -1210,10 +1762,11 @@ impl Component for Snake {
                         snake,
                         foods,
                         boundaries,
+                        food,
                         advance_interval,
                         ..
                     } => {
-                        let defaults = SPDomainDefaults::defaults();
+                        let defaults = SPDomainDefaults::defaults(*food);
                         *snake = defaults.snake;
                         *foods = defaults.foods;
                         *boundaries = defaults.boundaries;
@@ -1230,12 +1783,18 @@ impl Component for Snake {
                 true
             }
 
+            Self::Message::SPFoodChange(food) => {
+                self.sp_food = food;
+                true
+            }
+
             Self::Message::Begin => {
+                let food = self.sp_food;
                 let SPDomainDefaults {
                     snake,
                     foods,
                     boundaries,
-                } = SPDomainDefaults::defaults();
+                } = SPDomainDefaults::defaults(food);
                 let mut advance_interval = SnakeAdvanceInterval::create(ctx.link().clone());
                 advance_interval.start();
 
@@ -1244,6 +1803,7 @@ impl Component for Snake {
                         snake,
                         foods,
                         boundaries,
+                        food,
                         px_scale: calc_px_scale(&boundaries),
                         advance_interval,
                     }));
@@ -1315,13 +1875,12 @@ impl Snake {
         // TODO move camera to Begun states
         match &self.state {
             State::NotBegun { .. } => vec![],
-            State::BegunSingleplayer { snake, .. }
-            | State::BegunMultiplayer {
-                domain: Domain {
-                    snake: Some(snake), ..
-                },
-                ..
-            } => vec![Camera::MouthCentered, Camera::BoundariesCentered],
+            State::BegunSingleplayer { .. } => {
+                vec![Camera::MouthCentered, Camera::BoundariesCentered]
+            }
+            State::BegunMultiplayer { domain, .. } if domain.snake.is_some() => {
+                vec![Camera::MouthCentered, Camera::BoundariesCentered]
+            }
             _ => vec![Camera::BoundariesCentered],
         }
     }
@@ -1333,20 +1892,35 @@ impl Snake {
         boundaries: &domain::Boundaries,
         px_scale: f64,
     ) -> TransformedPos {
-        let pos = TransformedPos::from(pos) * px_scale;
+        let point = (f64::from(pos.x), f64::from(pos.y));
+        self.transform_point(point, snake, boundaries, px_scale)
+    }
+
+    pub fn transform_point(
+        &self,
+        (x, y): prediction::Point,
+        snake: Option<&domain::Snake>,
+        boundaries: &domain::Boundaries,
+        px_scale: f64,
+    ) -> TransformedPos {
+        let pos = TransformedPos::new(x, y) * px_scale;
 
         match self.camera {
             Camera::MouthCentered => {
                 // center camera to the mouth
                 //
-                // position of the mouth after the same transformations as of 'pos'
-
-                let adjusted_mouth = TransformedPos::from(
-                    snake
-                        .as_ref()
-                        .expect("snake to exist for this camera to work")
-                        .mouth(),
-                ) * px_scale;
+                // position of the mouth after the same transformations as of 'pos',
+                // in multiplayer it is between cells while moving
+
+                let adjusted_mouth = match self.focus {
+                    Some((x, y)) => TransformedPos::new(x, y),
+                    None => TransformedPos::from(
+                        snake
+                            .as_ref()
+                            .expect("snake to exist for this camera to work")
+                            .mouth(),
+                    ),
+                } * px_scale;
 
                 // target position - center of the canvas
                 // assert!(self.refs.is_canvas_fit(self.state));
@@ -1379,35 +1953,66 @@ impl Snake {
     fn draw_snake(
         &self,
         r: &CanvasRenderer,
-        snake: &domain::Snake,
+        // body can be between cells while moving
+        drawn: DrawnSnake,
         boundaries: &domain::Boundaries,
         // distinguish controlled snake from others, by drawing another cirle on head
         style: bool,
+        // name is drawn above the head, theme color is used if unset
+        player: Option<&LobbyRunningPlayer>,
         px_scale: f64,
     ) {
         let theme = self.theme_ctx.as_ref();
         let bg_color = &theme.bg_color;
         let box_border_color = &theme.box_border_color;
+        let text_color = &theme.text_color;
+        let color = player.map_or(box_border_color.as_str(), |p| p.color.as_str());
 
+        let snake = drawn.snake;
         let snake_body_width = SNAKE_BODY_WIDTH * px_scale;
 
-        let transform_pos = |pos| self.transform_pos(pos, Some(snake), boundaries, px_scale);
+        let transform_point =
+            |point| self.transform_point(point, Some(snake), boundaries, px_scale);
+        let mouth = transform_point(drawn.mouth());
+        let tail = transform_point(drawn.tail());
 
+        // glow below the body
+        if let Some(player) = player.filter(|player| player.boosting) {
+            r.set_stroke_style(&format!("{}{BOOST_ALPHA}", player.color));
+            r.set_line_width(snake_body_width * BOOST_GLOW_WIDTH);
+            r.begin_path();
+            for part in drawn.parts.iter() {
+                let mut part = part.iter().cloned().map(transform_point);
+                r.move_to(part.next().unwrap());
+                for pos in part {
+                    r.line_to(pos);
+                }
+            }
+            r.stroke();
+            r.close_path();
+        }
+
+        r.set_stroke_style(color);
         r.set_line_width(snake_body_width);
-        let pos = transform_pos(snake.iter_vertices().next().unwrap());
         r.begin_path();
-        r.move_to(pos);
-        for pos in snake.iter_vertices().skip(1).map(transform_pos) {
-            r.line_to(pos);
+        // body is split by portals
+        for part in drawn.parts.iter() {
+            let mut part = part.iter().cloned().map(transform_point);
+            r.move_to(part.next().unwrap());
+            for pos in part {
+                r.line_to(pos);
+            }
         }
         r.stroke();
         r.close_path();
+        // restore for the following drawings
+        r.set_stroke_style(box_border_color);
 
         // round head
-        let pos = transform_pos(snake.mouth());
+        let pos = mouth;
         r.begin_path();
         r.cirle(pos, snake_body_width / 2.);
-        r.set_fill_style(box_border_color);
+        r.set_fill_style(color);
         r.fill();
         r.close_path();
 
@@ -1418,30 +2023,49 @@ impl Snake {
         r.close_path();
 
         if style {
-            let pos = transform_pos(snake.mouth());
+            let pos = mouth;
             r.begin_path();
             r.cirle(pos, (snake_body_width / 2.) * 0.3);
-            r.set_fill_style(box_border_color);
+            r.set_fill_style(color);
             r.fill();
             r.close_path();
         }
 
         // round tail
-        let pos = transform_pos(snake.tail_end());
+        let pos = tail;
         r.begin_path();
         r.cirle(pos, snake_body_width / 2.);
-        r.set_fill_style(box_border_color);
+        r.set_fill_style(color);
         r.fill();
         r.close_path();
 
-        // if let Some(label) = label {
-        //     let pos = transform_pos(snake.mouth());
-        //     r.begin_path();
-        //     r.set_text_align("center");
-        //     r.set_font("30px serif");
-        //     r.fill_text(label, pos);
-        //     r.close_path();
-        // }
+        let pos = mouth;
+        r.set_line_width(AURA_WIDTH * px_scale);
+        for (i, power_up) in snake.effects.active().into_iter().enumerate() {
+            r.set_stroke_style(power_up_color(power_up));
+            r.begin_path();
+            r.cirle(
+                pos,
+                snake_body_width / 2. + (i + 1) as f64 * AURA_GAP * px_scale,
+            );
+            r.stroke();
+            r.close_path();
+        }
+        r.set_stroke_style(box_border_color);
+
+        if let Some(LobbyRunningPlayer { user_name, .. }) = player {
+            let pos = mouth;
+            let pos = TransformedPos::new(pos.x, pos.y - snake_body_width);
+            r.begin_path();
+            r.set_text_align("center");
+            r.set_font(&format!(
+                "{}px 'Iosevka Web'",
+                (px_scale * 0.5).max(10.) as u32
+            ));
+            r.set_fill_style(text_color);
+            r.fill_text(user_name, pos);
+            r.close_path();
+        }
     }
 
     fn draw_foods(
@@ -1461,7 +2085,10 @@ impl Snake {
             r.begin_path();
             r.cirle(pos, FOOD_DIAMETER * px_scale / 2.);
             r.set_fill_style(box_border_color);
+            // fades out before expiring
+            r.set_global_alpha(food.freshness());
             r.fill();
+            r.set_global_alpha(1.);
             r.close_path();
         }
     }
@@ -1493,6 +2120,263 @@ impl Snake {
         r.close_path();
         r.stroke();
     }
+
+    // area between current and upcoming boundaries
+    fn draw_danger_zone(
+        &self,
+        r: &CanvasRenderer,
+        boundaries: &domain::Boundaries,
+        upcoming: &domain::Boundaries,
+        snake: Option<&domain::Snake>,
+        px_scale: f64,
+    ) {
+        let transform_pos = |pos| self.transform_pos(pos, snake, boundaries, px_scale);
+
+        r.set_fill_style(DANGER_ZONE_COLOR);
+        r.begin_path();
+
+        // opposite windings, so that only the ring between is filled
+        let outer = [
+            boundaries.left_top(),
+            boundaries.right_top(),
+            boundaries.right_bottom(),
+            boundaries.left_bottom(),
+        ];
+        let inner = [
+            upcoming.left_top(),
+            upcoming.left_bottom(),
+            upcoming.right_bottom(),
+            upcoming.right_top(),
+        ];
+
+        for corners in [outer, inner] {
+            r.move_to(transform_pos(corners[0]));
+            for pos in &corners[1..] {
+                r.line_to(transform_pos(*pos));
+            }
+            r.close_path();
+        }
+
+        r.fill();
+    }
+
+    fn draw_pickups(
+        &self,
+        r: &CanvasRenderer,
+        pickups: &[domain::Pickup],
+        boundaries: &domain::Boundaries,
+        snake: Option<&domain::Snake>,
+        px_scale: f64,
+    ) {
+        for pickup in pickups {
+            r.begin_path();
+            r.cirle(
+                self.transform_pos(pickup.pos, snake, boundaries, px_scale),
+                PICKUP_DIAMETER * px_scale / 2.,
+            );
+            r.set_fill_style(power_up_color(pickup.power_up));
+            r.fill();
+            r.close_path();
+        }
+    }
+
+    // rings, linked ones share color
+    fn draw_portals(
+        &self,
+        r: &CanvasRenderer,
+        portals: &[domain::Portal],
+        boundaries: &domain::Boundaries,
+        snake: Option<&domain::Snake>,
+        px_scale: f64,
+    ) {
+        let transform_pos = |pos| self.transform_pos(pos, snake, boundaries, px_scale);
+
+        r.set_line_width(0.15 * px_scale);
+        for (i, portal) in portals.iter().enumerate() {
+            r.set_stroke_style(PORTAL_COLORS[i % PORTAL_COLORS.len()]);
+            for pos in [portal.a, portal.b] {
+                r.begin_path();
+                r.cirle(transform_pos(pos), PORTAL_DIAMETER * px_scale / 2.);
+                r.stroke();
+                r.close_path();
+            }
+        }
+        // restore for the following drawings
+        r.set_stroke_style(&self.theme_ctx.as_ref().box_border_color);
+    }
+
+    // tinted by holder color
+    fn draw_zones(
+        &self,
+        r: &CanvasRenderer,
+        zones: &[CaptureZone],
+        scoreboard: &[LobbyRunningPlayer],
+        boundaries: &domain::Boundaries,
+        snake: Option<&domain::Snake>,
+        px_scale: f64,
+    ) {
+        // positions are cell centers, zones include edge cells
+        let half_cell = |dx, dy| TransformedPos::new(dx, dy) * (0.5 * px_scale);
+        let transform_pos = |pos| self.transform_pos(pos, snake, boundaries, px_scale);
+
+        for zone in zones {
+            let holder = zone
+                .holder
+                .and_then(|id| scoreboard.iter().find(|player| player.id == id));
+            match holder {
+                Some(player) => r.set_fill_style(&format!("{}{CAPTURE_ZONE_ALPHA}", player.color)),
+                None => r.set_fill_style(CAPTURE_ZONE_COLOR),
+            }
+
+            r.begin_path();
+            r.move_to(transform_pos(zone.min) + half_cell(-1., -1.));
+            r.line_to(transform_pos(domain::Pos::new(zone.max.x, zone.min.y)) + half_cell(1., -1.));
+            r.line_to(transform_pos(zone.max) + half_cell(1., 1.));
+            r.line_to(transform_pos(domain::Pos::new(zone.min.x, zone.max.y)) + half_cell(-1., 1.));
+            r.close_path();
+            r.fill();
+        }
+    }
+}
+
+fn power_up_color(power_up: domain::PowerUp) -> &'static str {
+    match power_up {
+        domain::PowerUp::Ghost => "#a9a9a9",
+        domain::PowerUp::Shield => "#4363d8",
+        domain::PowerUp::Magnet => "#e6194b",
+    }
+}
+
+fn game_over_text(winner: Option<domain::Winner>, scoreboard: &[LobbyRunningPlayer]) -> String {
+    match winner {
+        Some(winner) => format!("{} wins!", winner_name(winner, scoreboard)),
+        None => "Nobody wins".to_owned(),
+    }
+}
+
+fn winner_name(winner: domain::Winner, scoreboard: &[LobbyRunningPlayer]) -> String {
+    match winner {
+        domain::Winner::Team(team) => format!("Team {team}"),
+        domain::Winner::Player(id) => match scoreboard.iter().find(|p| p.id == id) {
+            Some(player) => player.user_name.clone(),
+            None => format!("Player {id}"),
+        },
+    }
+}
+
+// round wins, most first
+fn series_standings(series: &LobbySeries, scoreboard: &[LobbyRunningPlayer]) -> Html {
+    series
+        .standings
+        .iter()
+        .map(|standing| {
+            let name = winner_name(standing.winner, scoreboard);
+            html! { <p>{ format!("{name}: {}", standing.wins) }</p> }
+        })
+        .collect()
+}
+
+fn final_standings(standings: &[PlayerId], scoreboard: &[LobbyRunningPlayer]) -> Html {
+    standings
+        .iter()
+        .enumerate()
+        .map(|(i, id)| {
+            let name = match scoreboard.iter().find(|p| p.id == *id) {
+                Some(player) => player.user_name.clone(),
+                None => format!("Player {id}"),
+            };
+            html! { <p>{ format!("{}. {name}", i + 1) }</p> }
+        })
+        .collect()
+}
+
+fn kill_feed(lines: &VecDeque<String>, text_color: &str) -> Html {
+    let style = css! {"
+        margin-top: 10px;
+        color: ${text_color};
+        font-family: 'Iosevka Web';
+        font-size: 14px;
+        p { margin: 2px 0; }
+    ",
+        text_color = text_color
+    };
+
+    html! {
+        <div class={style}>
+            { lines.iter().map(|line| html! { <p>{ line }</p> }).collect::<Html>() }
+        </div>
+    }
+}
+
+fn leaderboard(
+    scoreboard: &[LobbyRunningPlayer],
+    teams: &[LobbyRunningTeam],
+    // zone points column in king of the hill mode
+    hill: bool,
+    text_color: &str,
+) -> Html {
+    let style = css! {"
+        margin-top: 10px;
+        color: ${text_color};
+        font-family: 'Iosevka Web';
+        border-spacing: 8px 2px;
+        .dead { text-decoration: line-through; opacity: 0.6; }
+    ",
+        text_color = text_color
+    };
+
+    let rows = scoreboard
+        .iter()
+        .map(|player| {
+            let color = format!("color: {};", player.color);
+            let class = if player.alive { "" } else { "dead" };
+            let score = &player.score;
+
+            html! {
+                <tr {class}>
+                    <td style={color}>{ "■" }</td>
+                    <td>{ &player.user_name }</td>
+                    <td>{ score.score }</td>
+                    <td>{ score.length }</td>
+                    <td>{ score.kills }</td>
+                    if hill {
+                        <td>{ score.zone_points }</td>
+                    }
+                </tr>
+            }
+        })
+        .collect::<Html>();
+
+    let team_rows = teams
+        .iter()
+        .map(|team| {
+            let color = format!("color: {};", team.color);
+            let class = if team.alive { "" } else { "dead" };
+
+            html! {
+                <tr {class}>
+                    <td style={color}>{ "■" }</td>
+                    <td>{ format!("Team {}", team.id) }</td>
+                    <td>{ team.score }</td>
+                </tr>
+            }
+        })
+        .collect::<Html>();
+
+    html! {
+        <table class={style}>
+            if !teams.is_empty() {
+                <tr><th></th><th>{ "Team" }</th><th>{ "Score" }</th></tr>
+                { team_rows }
+            }
+            <tr><th></th><th>{ "Player" }</th><th>{ "Score" }</th><th>{ "Len" }</th><th>{ "Kills" }</th>
+                if hill {
+                    <th>{ "Zone" }</th>
+                }
+            </tr>
+            { rows }
+        </table>
+    }
 }
 
 #[derive(Clone, PartialEq)]
@@ -1544,15 +2428,33 @@ pub enum State {
         snake: domain::Snake,
         foods: domain::Foods,
         boundaries: domain::Boundaries,
+        food: SPFoodSettings,
         // greater value - closer camera
         px_scale: f64,
 
         advance_interval: SnakeAdvanceInterval,
     },
     BegunMultiplayer {
-        domain: Domain,
+        // boxed, keeps the state small
+        domain: Box<Domain>,
         // greater value - closer camera
         px_scale: f64,
+        // best score first
+        scoreboard: Vec<LobbyRunningPlayer>,
+        // ticks until respawn is available, when dead and respawns are allowed
+        respawn_in: Option<u32>,
+        // in team mode, best score first
+        teams: Vec<LobbyRunningTeam>,
+        game_over: Option<GameOver>,
+        upcoming_boundaries: Option<UpcomingBoundaries>,
+        // in king of the hill mode
+        zones: Vec<CaptureZone>,
+        ticks_left: Option<u32>,
+        series: Option<LobbySeries>,
+        // seconds until the game starts, snakes are only spawned
+        countdown: Option<u32>,
+        // movement between ticks
+        prediction: Prediction,
     },
     NotBegun {
         inner: NotBegunState,
@@ -1560,6 +2462,33 @@ pub enum State {
 }
 
 impl State {
+    pub fn begun_multiplayer(running: &LobbyRunning) -> Self {
+        let now = js_sys::Date::now();
+
+        State::BegunMultiplayer {
+            domain: Box::new(running.domain.clone()),
+            px_scale: calc_px_scale(&running.domain.boundaries),
+            scoreboard: running.scoreboard.clone(),
+            respawn_in: running.respawn_in,
+            teams: running.teams.clone(),
+            game_over: running.game_over.clone(),
+            upcoming_boundaries: running.upcoming_boundaries.clone(),
+            zones: running.zones.clone(),
+            ticks_left: running.ticks_left,
+            series: running.series.clone(),
+            countdown: None,
+            prediction: Prediction::new(running.counter, now, &running.domain),
+        }
+    }
+
+    pub fn counting_down(countdown: &LobbyCountdown) -> Self {
+        let mut state = Self::begun_multiplayer(&countdown.running);
+        if let State::BegunMultiplayer { countdown: c, .. } = &mut state {
+            c.replace(countdown.seconds_left);
+        }
+        state
+    }
+
     pub fn to_be_loaded_lobby(lobby_name: LobbyName) -> Self {
         State::NotBegun {
             inner: NotBegunState::MPLobby {
@@ -1702,6 +2631,7 @@ impl Refs {
 
 pub struct Listeners {
     kb_listener: EventListener,
+    kb_up_listener: EventListener,
     window_load_listener: EventListener,
     window_resize_listener: EventListener,
     location_listener: LocationHandle,
@@ -1737,6 +2667,7 @@ impl Listeners {
 
                     enum KeyBoardEvent {
                         DirectionChange(domain::Direction),
+                        Boost,
                         Restart,
                         None,
                         CameraToggle,
@@ -1749,6 +2680,8 @@ impl Listeners {
                         "ArrowDown" => DirectionChange(domain::Direction::Bottom),
                         "ArrowLeft" => DirectionChange(domain::Direction::Left),
                         "ArrowRight" => DirectionChange(domain::Direction::Right),
+                        // held key repeats keydown
+                        " " if !event.repeat() => Boost,
                         "r" | "R" => Restart,
                         "c" | "C" => CameraToggle,
                         "p" | "P" => PauseUnpause,
@@ -1757,6 +2690,7 @@ impl Listeners {
 
                     let message = match kb_event {
                         DirectionChange(direction) => SnakeMsg::DirectionChange(direction),
+                        Boost => SnakeMsg::Boost(true),
                         Restart => SnakeMsg::Restart,
                         None => SnakeMsg::Nothing,
                         CameraToggle => SnakeMsg::CameraToggle,
@@ -1767,6 +2701,18 @@ impl Listeners {
             )
         };
 
+        // boost lasts while the key is held
+        let kb_up_listener = {
+            let link = link.clone();
+            EventListener::new(&get_document(), "keyup", move |event| {
+                let event = event.dyn_ref::<web_sys::KeyboardEvent>().unwrap();
+
+                if event.key() == " " {
+                    link.send_message(SnakeMsg::Boost(false));
+                }
+            })
+        };
+
         let location_listener = link
             .add_location_listener(link.callback(|e: Location| {
                 let path = e.path();
@@ -1800,6 +2746,7 @@ impl Listeners {
 
         Self {
             kb_listener,
+            kb_up_listener,
             window_load_listener,
             window_resize_listener,
             location_listener,
@@ -1831,6 +2778,8 @@ impl DomainDefaults {
         domain::Snake {
             sections,
             direction,
+            id: None,
+            effects: Default::default(),
         }
     }
 
@@ -1838,38 +2787,16 @@ impl DomainDefaults {
         food_count: i32,
         boundaries: domain::Boundaries,
         taken_positions: impl Iterator<Item = domain::Pos>,
+        lifetime: Option<u32>,
     ) -> domain::Foods {
-        let b = boundaries;
-        let b = domain::Boundaries {
-            min: domain::Pos::new(b.min.x + 1, b.min.y + 1),
-            max: domain::Pos::new(b.max.x - 1, b.max.y - 1),
-        };
-
-        let mut positions = HashSet::new();
-        for x in (b.min.x)..(b.max.x) {
-            for y in (b.min.y)..(b.max.y) {
-                positions.insert(domain::Pos::new(x, y));
-            }
-        }
-
-        let taken_positions = taken_positions.collect::<HashSet<_>>();
-        let mut vacant_food_positions = positions
-            .difference(&taken_positions)
-            .collect::<HashSet<_>>();
-
-        let mut values = vec![];
-        for _ in 0..food_count {
-            if vacant_food_positions.len() > 0 {
-                let food_pos = rand_from_iterator(vacant_food_positions.iter());
-                values.push(**food_pos);
-                let _removed = vacant_food_positions.remove(*food_pos);
-            } else {
-                break;
-            }
-        }
+        let placement =
+            domain::placement::FoodPlacement::new(boundaries, taken_positions, lifetime);
 
         let mut foods = domain::Foods::default();
-        foods.extend(values.into_iter().map(domain::Food::from));
+        let set = FigureSet::default();
+        placement.fill(&mut foods, food_count.max(0) as usize, set, &mut |n| {
+            rand_from_iterator(0..n)
+        });
         foods
     }
 
@@ -1890,10 +2817,50 @@ fn default_domain() -> Domain {
             rand_from_iterator(((food_average * 0.9) as i32)..((food_average * 1.1) as i32)),
             boundaries,
             snake.iter_vertices(),
+            None,
         ),
         other_snakes: Default::default(),
         boundaries,
         snake: Some(snake),
+        portals: vec![],
+        pickups: vec![],
+    }
+}
+
+// single player foods expire and run from the head when picked,
+// off by default to keep the classic game
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct SPFoodSettings {
+    lifetime: Option<u32>,
+    flee_every: Option<u32>,
+}
+
+// off, then shorter lifetimes each click
+fn next_food_lifetime(lifetime: Option<u32>) -> (String, Option<u32>) {
+    match lifetime {
+        None => ("off".to_string(), Some(100)),
+        Some(ticks) => (
+            format!("{ticks} ticks"),
+            match ticks {
+                100 => Some(50),
+                _ => None,
+            },
+        ),
+    }
+}
+
+// off, then running more often each click
+fn next_food_flee_every(every: Option<u32>) -> (String, Option<u32>) {
+    match every {
+        None => ("off".to_string(), Some(3)),
+        Some(ticks) => (
+            format!("every {ticks} ticks"),
+            match ticks {
+                3 => Some(2),
+                2 => Some(1),
+                _ => None,
+            },
+        ),
     }
 }
 
@@ -1904,7 +2871,7 @@ struct SPDomainDefaults {
 }
 
 impl SPDomainDefaults {
-    fn defaults() -> Self {
+    fn defaults(food: SPFoodSettings) -> Self {
         let snake = DomainDefaults::snake();
         let boundaries = DomainDefaults::boundaries(&snake);
 
@@ -1915,6 +2882,7 @@ impl SPDomainDefaults {
                 rand_from_iterator(((food_average * 0.9) as i32)..((food_average * 1.1) as i32)),
                 boundaries,
                 snake.iter_vertices(),
+                food.lifetime,
             )
         };
 
@@ -2130,6 +3098,10 @@ impl CanvasRenderer {
         self.as_ref().set_fill_style(&JsValue::from_str(value));
     }
 
+    fn set_global_alpha(&self, value: f64) {
+        self.as_ref().set_global_alpha(value);
+    }
+
     fn set_stroke_style(&self, value: &str) {
         self.as_ref().set_stroke_style(&JsValue::from_str(value));
     }
@@ -2217,6 +3189,129 @@ pub async fn sleep(delay: i32) {
 const UPDATE: bool = true;
 
 impl Snake {
+    // multiplayer frames follow one another until the game is left
+    fn request_animation_frame(&mut self, ctx: &Context<Self>) {
+        match self.state {
+            State::BegunMultiplayer { .. } if self.animation_frame.is_none() => {
+                let link = ctx.link().clone();
+                self.animation_frame = Some(request_animation_frame(move |_| {
+                    link.send_message(SnakeMsg::AnimationFrame)
+                }));
+            }
+            State::BegunMultiplayer { .. } => {}
+            _ => self.animation_frame = None,
+        }
+    }
+
+    fn draw_canvas(&mut self) {
+        let now = js_sys::Date::now();
+
+        // camera follows the mouth between cells
+        self.focus = match &self.state {
+            State::BegunMultiplayer {
+                domain, prediction, ..
+            } => domain
+                .snake
+                .as_ref()
+                .map(|snake| prediction.own(snake, now).mouth()),
+            _ => None,
+        };
+
+        let theme = self.theme_ctx.as_ref();
+        let bg_color = &theme.bg_color;
+        let box_border_color = &theme.box_border_color;
+
+        match &self.state {
+            s @ (State::BegunSingleplayer { .. } | State::BegunMultiplayer { .. }) => {
+                let r = self.refs.canvas_renderer();
+                r.set_stroke_style(box_border_color);
+                r.set_line_join("round");
+                r.set_fill_style(bg_color);
+
+                // assert!(self.refs.is_canvas_fit(self.state));
+                let cd = self.refs.canvas_dimensions();
+                r.fill_rect(domain::Boundaries {
+                    min: domain::Pos::new(0, 0),
+                    max: domain::Pos::new(cd.width as i32, cd.height as i32),
+                });
+
+                match s {
+                    State::NotBegun { .. } => unreachable!(),
+                    State::BegunSingleplayer {
+                        snake,
+                        foods,
+                        boundaries,
+                        px_scale,
+                        ..
+                    } => {
+                        let drawn = DrawnSnake::still(snake);
+                        self.draw_snake(&r, drawn, boundaries, false, None, *px_scale);
+                        self.draw_foods(&r, foods, Some(snake), boundaries, *px_scale);
+                        self.draw_boundaries(&r, boundaries, Some(snake), *px_scale);
+                    }
+                    State::BegunMultiplayer {
+                        domain,
+                        px_scale,
+                        scoreboard,
+                        upcoming_boundaries,
+                        zones,
+                        prediction,
+                        ..
+                    } => {
+                        let Domain {
+                            snake,
+                            other_snakes,
+                            foods,
+                            boundaries,
+                            portals,
+                            pickups,
+                        } = domain.as_ref();
+
+                        let player = |snake: &domain::Snake| {
+                            snake
+                                .id
+                                .and_then(|id| scoreboard.iter().find(|player| player.id == id))
+                        };
+
+                        let draw_snake = |drawn: DrawnSnake, style| {
+                            let player = player(drawn.snake);
+                            self.draw_snake(&r, drawn, boundaries, style, player, *px_scale);
+                        };
+
+                        self.draw_zones(
+                            &r,
+                            zones,
+                            scoreboard,
+                            boundaries,
+                            snake.as_ref(),
+                            *px_scale,
+                        );
+                        self.draw_portals(&r, portals, boundaries, snake.as_ref(), *px_scale);
+                        self.draw_pickups(&r, pickups, boundaries, snake.as_ref(), *px_scale);
+                        if let Some(snake) = snake {
+                            draw_snake(prediction.own(snake, now), true);
+                        }
+                        for snake in other_snakes {
+                            draw_snake(prediction.other(snake, now), false);
+                        }
+                        self.draw_foods(&r, foods, snake.as_ref(), boundaries, *px_scale);
+                        self.draw_boundaries(&r, boundaries, snake.as_ref(), *px_scale);
+                        if let Some(upcoming) = upcoming_boundaries {
+                            self.draw_danger_zone(
+                                &r,
+                                boundaries,
+                                &upcoming.boundaries,
+                                snake.as_ref(),
+                                *px_scale,
+                            );
+                        }
+                    }
+                }
+            }
+            State::NotBegun { .. } => {}
+        }
+    }
+
     fn handle_state_change(
         &mut self,
         ctx: &Context<Self>,
@@ -2225,15 +3320,24 @@ impl Snake {
         console::log!(format!("state change: {s:?}"));
 
         match &s {
-            LobbyState::Prep(s) => {}
-            LobbyState::Running(interfacing::snake::lobby_state::LobbyRunning {
-                domain, ..
-            }) => {
+            // countdown was cancelled
+            LobbyState::Prep(_) => {
+                if let State::BegunMultiplayer { .. } = self.state {
+                    ctx.link()
+                        .send_message(SnakeMsg::StateChange(State::NotBegun {
+                            inner: NotBegunState::MPLobby {
+                                state: MPLobbyState::Joined,
+                            },
+                        }));
+                }
+            }
+            LobbyState::Countdown(countdown) => {
                 ctx.link()
-                    .send_message(SnakeMsg::StateChange(State::BegunMultiplayer {
-                        domain: domain.clone(),
-                        px_scale: calc_px_scale(&domain.boundaries),
-                    }));
+                    .send_message(SnakeMsg::StateChange(State::counting_down(countdown)));
+            }
+            LobbyState::Running(running) => {
+                ctx.link()
+                    .send_message(SnakeMsg::StateChange(State::begun_multiplayer(running)));
             }
             LobbyState::Terminated => {
                 console::log!("should not receive");
@@ -2245,6 +3349,80 @@ impl Snake {
         UPDATE
     }
 
+    // events arrive before the state they lead to,
+    // so victims are still present in the current one
+    fn handle_game_events(&mut self, ctx: &Context<Self>, events: GameEvents) -> bool {
+        use domain::{DeathCause, GameEvent};
+
+        let (own_id, scoreboard) = match &self.state {
+            State::BegunMultiplayer {
+                domain, scoreboard, ..
+            } => (
+                domain.snake.as_ref().and_then(|s| s.id),
+                scoreboard.as_slice(),
+            ),
+            _ => (None, [].as_slice()),
+        };
+
+        let name = |id: domain::SnakeId| {
+            scoreboard
+                .iter()
+                .find(|p| p.id == id)
+                .map(|p| p.user_name.clone())
+                .unwrap_or_else(|| format!("Player {id}"))
+        };
+
+        let mut toasts = vec![];
+
+        for event in events.events {
+            match event {
+                GameEvent::GameStarted => {
+                    self.game_feed.kill_feed.clear();
+                    toasts.push("Game started!".to_owned());
+                }
+                GameEvent::SnakeDied {
+                    victim,
+                    cause,
+                    killer,
+                } => {
+                    let line = match (cause, killer) {
+                        (_, Some(killer)) => format!("{} ⚔ {}", name(killer), name(victim)),
+                        (DeathCause::BitYaSelf, None) => format!("{} bit themselves", name(victim)),
+                        (DeathCause::OutOfBounds, None) => format!("{} hit the wall", name(victim)),
+                        (DeathCause::Left, None) => format!("{} left", name(victim)),
+                        (DeathCause::BitSomeone, None) => format!("{} died", name(victim)),
+                    };
+                    self.game_feed.push_kill(line);
+
+                    if own_id == Some(victim) {
+                        toasts.push(match killer {
+                            Some(killer) => format!("Killed by {}", name(killer)),
+                            None => "You died".to_owned(),
+                        });
+                    } else if own_id.is_some() && own_id == killer {
+                        toasts.push(format!("You killed {}", name(victim)));
+                    }
+                }
+                GameEvent::GameEnded { winner } => {
+                    self.game_feed.push_kill(game_over_text(winner, scoreboard));
+                }
+                GameEvent::SnakeSpawned { .. }
+                | GameEvent::FoodEaten { .. }
+                | GameEvent::PowerUpPicked { .. } => {}
+            }
+        }
+
+        for text in toasts {
+            let id = self.game_feed.push_toast(text);
+            ctx.link().send_future(async move {
+                sleep(TOAST_DURATION).await;
+                SnakeMsg::ToastExpired(id)
+            });
+        }
+
+        UPDATE
+    }
+
     fn handle_received_message(&mut self, ctx: &Context<Self>, msg: WsMsg<WsServerMsg>) -> bool {
         console::log!(format!("recv: {msg:?}"));
 
@@ -2334,7 +3512,10 @@ impl Snake {
                             .send_message(SnakeMsg::StateChange(State::NotBegun { inner: s }));
                     }
 
-                    (WsClientMsg::VoteStart(_), WsServerMsg::LobbyState(s)) => {
+                    (WsClientMsg::VoteStart(_), WsServerMsg::LobbyState(s))
+                    | (WsClientMsg::SetLobbySettings(_), WsServerMsg::LobbyState(s))
+                    | (WsClientMsg::Respawn, WsServerMsg::LobbyState(s))
+                    | (WsClientMsg::PickTeam(_), WsServerMsg::LobbyState(s)) => {
                         return self.handle_state_change(ctx, s);
                     }
 
@@ -2368,6 +3549,15 @@ impl Snake {
                     return self.handle_state_change(ctx, s);
                 }
 
+                WsServerMsg::GameEvents(events) => {
+                    return self.handle_game_events(ctx, events);
+                }
+
+                WsServerMsg::Maintenance(secs) => {
+                    self.ws_state.maintenance = Some(secs);
+                    return UPDATE;
+                }
+
                 recv => console::log!(format!("invalid recv: {recv:?}")),
             },
         }
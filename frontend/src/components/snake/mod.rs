@@ -1,2 +1,3 @@
 pub mod comp;
+mod prediction;
 mod styles;
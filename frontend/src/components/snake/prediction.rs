@@ -0,0 +1,280 @@
+// Smooth multiplayer movement between server ticks
+//
+// Own snake is predicted one tick ahead with the shared domain code,
+// other snakes are drawn moving from the previous server state to the latest one.
+
+use interfacing::snake_domain::{self as domain, AdvanceResult, Direction, Domain};
+
+// until measured from arriving states, in milliseconds
+const DEFAULT_TICK_MS: f64 = 500.;
+// weight of the latest measured tick in the estimate
+const TICK_SMOOTHING: f64 = 0.2;
+
+// grid position, fractional while moving between cells
+pub type Point = (f64, f64);
+
+// snake with the body as drawn
+pub struct DrawnSnake<'a> {
+    pub snake: &'a domain::Snake,
+    // split on portal jumps, from the tail to the mouth
+    pub parts: Vec<Vec<Point>>,
+}
+
+impl<'a> DrawnSnake<'a> {
+    // standing on its cells
+    pub fn still(snake: &'a domain::Snake) -> Self {
+        Self {
+            snake,
+            parts: parts(snake),
+        }
+    }
+
+    pub fn mouth(&self) -> Point {
+        *self.parts.last().unwrap().last().unwrap()
+    }
+
+    pub fn tail(&self) -> Point {
+        self.parts[0][0]
+    }
+}
+
+pub struct Prediction {
+    // of the server state it belongs to
+    counter: u32,
+    // when that state arrived, in milliseconds
+    received_at: f64,
+    // estimated time between server ticks
+    tick_ms: f64,
+    // server domain of the tick before, when the states are consecutive
+    previous: Option<Box<Domain>>,
+    // own snake after the upcoming tick, none when it is about to die
+    next: Option<domain::Snake>,
+    // last direction sent to the server with the counter it was sent at
+    input: Option<(u32, Direction)>,
+}
+
+impl Prediction {
+    pub fn new(counter: u32, received_at: f64, domain: &Domain) -> Self {
+        Self {
+            counter,
+            received_at,
+            tick_ms: DEFAULT_TICK_MS,
+            previous: None,
+            next: predict(domain),
+            input: None,
+        }
+    }
+
+    // continue from the prediction of the state replaced by the new one,
+    // an input sent during the previous tick might have reached the server late,
+    // so it is applied again, a no-op when the server already has it
+    pub fn reconcile(&mut self, old: &Prediction, old_domain: &Domain, domain: &mut Domain) {
+        self.tick_ms = old.tick_ms;
+
+        if self.counter == old.counter + 1 {
+            let measured = self.received_at - old.received_at;
+            self.tick_ms = old.tick_ms * (1. - TICK_SMOOTHING) + measured * TICK_SMOOTHING;
+            self.previous = Some(Box::new(old_domain.clone()));
+        } else if self.counter == old.counter {
+            // same tick sent again, keep moving as before
+            self.received_at = old.received_at;
+            self.previous = old.previous.clone();
+        }
+
+        if let Some((sent_at, direction)) = old.input {
+            if sent_at + 1 >= self.counter {
+                if let Some(snake) = domain.snake.as_mut() {
+                    snake.set_direction(direction).unwrap_or(());
+                }
+                self.input = old.input;
+            }
+        }
+
+        self.next = predict(domain);
+    }
+
+    // turn ahead of the server, the same way it is going to
+    pub fn input(&mut self, direction: Direction, domain: &mut Domain) -> Result<(), ()> {
+        let snake = domain.snake.as_mut().ok_or(())?;
+        snake.set_direction(direction)?;
+
+        self.input = Some((self.counter, direction));
+        self.next = predict(domain);
+        Ok(())
+    }
+
+    // the predicted one, turns are sent for it
+    pub fn tick(&self) -> u32 {
+        self.counter + 1
+    }
+
+    // share of the tick passed since the state arrived, from 0 to 1
+    pub fn progress(&self, now: f64) -> f64 {
+        ((now - self.received_at) / self.tick_ms).clamp(0., 1.)
+    }
+
+    // own snake on the way to the predicted position
+    pub fn own<'a>(&self, snake: &'a domain::Snake, now: f64) -> DrawnSnake<'a> {
+        match &self.next {
+            Some(next) => DrawnSnake {
+                snake,
+                parts: interpolated_parts(snake, next, self.progress(now)),
+            },
+            None => DrawnSnake::still(snake),
+        }
+    }
+
+    // another snake on the way from its previous position
+    pub fn other<'a>(&self, snake: &'a domain::Snake, now: f64) -> DrawnSnake<'a> {
+        let previous = self.previous.as_ref().and_then(|previous| {
+            previous
+                .snake
+                .iter()
+                .chain(previous.other_snakes.iter())
+                .find(|other| other.id.is_some() && other.id == snake.id)
+        });
+
+        match previous {
+            Some(previous) => DrawnSnake {
+                snake,
+                parts: interpolated_parts(previous, snake, self.progress(now)),
+            },
+            None => DrawnSnake::still(snake),
+        }
+    }
+}
+
+// own snake after one more tick, foods and others as they are now
+fn predict(domain: &Domain) -> Option<domain::Snake> {
+    let mut snake = domain.snake.clone()?;
+    let mut foods = domain.foods.clone();
+
+    match snake.advance(
+        &mut foods,
+        &domain.other_snakes,
+        &domain.boundaries,
+        &domain.portals,
+    ) {
+        AdvanceResult::Success => Some(snake),
+        _ => None,
+    }
+}
+
+fn parts(snake: &domain::Snake) -> Vec<Vec<Point>> {
+    snake
+        .sections
+        .parts()
+        .into_iter()
+        .map(|part| part.into_iter().map(point).collect())
+        .collect()
+}
+
+fn point(pos: domain::Pos) -> Point {
+    (f64::from(pos.x), f64::from(pos.y))
+}
+
+fn lerp(from: domain::Pos, to: domain::Pos, progress: f64) -> Point {
+    let (from, to) = (point(from), point(to));
+    (
+        from.0 + (to.0 - from.0) * progress,
+        from.1 + (to.1 - from.1) * progress,
+    )
+}
+
+// body of the later snake with the head and the tail slid back towards
+// the earlier one, equals the earlier at 0 and the later at 1,
+// jumps over portals or boosted double steps are not slid
+pub fn interpolated_parts(
+    from: &domain::Snake,
+    to: &domain::Snake,
+    progress: f64,
+) -> Vec<Vec<Point>> {
+    let mut parts = parts(to);
+
+    if from.mouth().distance(to.mouth()) == 1 {
+        let head = parts.last_mut().unwrap().last_mut().unwrap();
+        *head = lerp(from.mouth(), to.mouth(), progress);
+    }
+
+    if from.tail_end().distance(to.tail_end()) == 1 {
+        let tail = lerp(from.tail_end(), to.tail_end(), progress);
+        parts[0].insert(0, tail);
+    }
+
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use domain::{Pos, Sections};
+
+    fn snake(tail: Pos, directions: &[Direction]) -> domain::Snake {
+        domain::Snake {
+            sections: Sections::from_directions(tail, directions.iter().cloned()),
+            direction: *directions.last().unwrap(),
+            id: Some(1),
+            effects: Default::default(),
+        }
+    }
+
+    fn domain(snake: domain::Snake) -> Domain {
+        Domain {
+            snake: Some(snake),
+            other_snakes: vec![],
+            foods: Default::default(),
+            boundaries: Pos::new(0, 0).boundaries_in_radius(10, 10),
+            portals: vec![],
+            pickups: vec![],
+        }
+    }
+
+    #[test]
+    fn test_interpolated_parts() {
+        let from = snake(Pos::new(0, 0), &[Direction::Right, Direction::Right]);
+        let to = snake(Pos::new(1, 0), &[Direction::Right, Direction::Bottom]);
+
+        let start = interpolated_parts(&from, &to, 0.);
+        assert_eq!(start, vec![vec![(0., 0.), (1., 0.), (2., 0.), (2., 0.)]]);
+
+        let middle = interpolated_parts(&from, &to, 0.5);
+        assert_eq!(middle, vec![vec![(0.5, 0.), (1., 0.), (2., 0.), (2., 0.5)]]);
+
+        let end = interpolated_parts(&from, &to, 1.);
+        assert_eq!(end, vec![vec![(1., 0.), (1., 0.), (2., 0.), (2., 1.)]]);
+    }
+
+    #[test]
+    fn test_reconcile_late_input() {
+        let right = snake(Pos::new(0, 0), &[Direction::Right]);
+        let mut old_domain = domain(right.clone());
+        let mut old = Prediction::new(4, 0., &old_domain);
+
+        assert!(old.input(Direction::Left, &mut old_domain).is_err());
+        assert!(old.input(Direction::Up, &mut old_domain).is_ok());
+        assert_eq!(old.next.as_ref().unwrap().mouth(), Pos::new(1, -1));
+
+        // server moved on before the input arrived
+        let mut moved = right.clone();
+        moved.advance(&mut Default::default(), &[], &old_domain.boundaries, &[]);
+        let mut new_domain = domain(moved);
+        let mut new = Prediction::new(5, 400., &new_domain);
+        new.reconcile(&old, &old_domain, &mut new_domain);
+
+        assert_eq!(new_domain.snake.as_ref().unwrap().direction, Direction::Up);
+        assert_eq!(new.next.as_ref().unwrap().mouth(), Pos::new(2, -1));
+        assert!(new.previous.is_some());
+        assert_eq!(new.progress(400. + new.tick_ms / 2.), 0.5);
+
+        // too old to be late, dropped
+        let mut later = Prediction::new(6, 800., &new_domain);
+        later.reconcile(&new, &new_domain, &mut new_domain.clone());
+        assert_eq!(later.input, None);
+
+        // same tick again
+        let mut again = Prediction::new(6, 900., &new_domain);
+        again.reconcile(&later, &new_domain, &mut new_domain.clone());
+        assert_eq!(again.received_at, 800.);
+        assert!(again.previous.is_some());
+    }
+}
@@ -0,0 +1,545 @@
+// Client state, commands and handling of server messages
+//
+
+use domain::figures::FigureSet;
+use interfacing::snake::{
+    lobby_state::{
+        BoostSettings, HillSettings, SeriesSettings, TeamSettings, TeammateCollision, TimedRanking,
+        TimedSettings,
+    },
+    JoinLobbyDecline, LobbyList, LobbyName, LobbyState, MsgId, TeamId, UserName, WsClientMsg,
+    WsMsg, WsServerMsg,
+};
+use interfacing::snake_domain as domain;
+use std::collections::{HashMap, VecDeque};
+use tokio::sync::mpsc;
+
+use crate::{ClientMsg, ServerMsg};
+
+const LOG_CAPACITY: usize = 8;
+
+pub const HELP: &str = "commands: name <user name> | whoami | list | create <lobby> \
+    | join <lobby> | vote | unvote | set respawn <ticks|off> \
+    | set join <on|off> | set teams <count|off> | set teammates <pass|block> \
+    | set shrinking <on|off> | set hill <points|off> | set timed <ticks|off> \
+    | set ranking <length|score> | set portals <on|off> | set boost <ticks|off> \
+    | set powerups <on|off> | set series <rounds|off> \
+    | set figures <classic|shapes|singles> | set lifetime <ticks|off> \
+    | set flee <ticks|off> | set tick <ms|default> \
+    | team <id> | leave | dir <up|down|left|right> | boost <on|off> \
+    | respawn | sleep <ms> | quit";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Send(WsClientMsg),
+    // changes a single lobby setting, keeping the others
+    Set(Setting),
+    // sent for the upcoming tick of the joined game
+    Turn(domain::Direction),
+    Sleep(u64),
+    Help,
+    Quit,
+}
+
+impl std::str::FromStr for Command {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let line = line.trim();
+        let (cmd, arg) = match line.split_once(char::is_whitespace) {
+            Some((cmd, arg)) => (cmd, arg.trim()),
+            None => (line, ""),
+        };
+
+        let required = |what: &str| {
+            if arg.is_empty() {
+                Err(format!("{cmd}: {what} required"))
+            } else {
+                Ok(arg.to_owned())
+            }
+        };
+
+        use WsClientMsg::*;
+
+        let command = match cmd {
+            "name" => Self::Send(SetUserName(required("user name")?)),
+            "whoami" => Self::Send(UserName),
+            "list" => Self::Send(LobbyList),
+            "create" => Self::Send(CreateLobby(required("lobby name")?)),
+            "join" => Self::Send(JoinLobby(required("lobby name")?)),
+            "vote" => Self::Send(VoteStart(true)),
+            "unvote" => Self::Send(VoteStart(false)),
+            "set" => Self::Set(parse_setting(arg)?),
+            "leave" => Self::Send(LeaveLobby),
+            "respawn" => Self::Send(Respawn),
+            "team" => Self::Send(PickTeam(
+                required("team")?
+                    .parse()
+                    .map_err(|_| format!("team: invalid team {arg:?}"))?,
+            )),
+            "dir" => Self::Turn(parse_direction(arg)?),
+            "boost" => match arg {
+                "on" => Self::Send(SetBoost(true)),
+                "off" => Self::Send(SetBoost(false)),
+                other => return Err(format!("boost: expected on or off, got {other:?}")),
+            },
+            "sleep" => Self::Sleep(
+                required("milliseconds")?
+                    .parse()
+                    .map_err(|_| format!("sleep: invalid milliseconds {arg:?}"))?,
+            ),
+            "help" => Self::Help,
+            "quit" | "exit" => Self::Quit,
+            other => return Err(format!("unknown command {other:?}, try help")),
+        };
+
+        Ok(command)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Setting {
+    Respawn(Option<u32>),
+    MidGameJoin(bool),
+    Teams(Option<TeamId>),
+    TeammateCollision(TeammateCollision),
+    Shrinking(bool),
+    // target zone points
+    Hill(Option<u32>),
+    // match length in ticks
+    Timed(Option<u32>),
+    Ranking(TimedRanking),
+    Portals(bool),
+    // boosted ticks per shed tail cell
+    Boost(Option<u32>),
+    PowerUps(bool),
+    // best-of rounds
+    Series(Option<u32>),
+    Figures(FigureSet),
+    // food ticks before expiring
+    FoodLifetime(Option<u32>),
+    // ticks between food escapes
+    FoodFlee(Option<u32>),
+    // milliseconds between ticks
+    Tick(Option<u32>),
+}
+
+fn parse_setting(value: &str) -> Result<Setting, String> {
+    let (name, value) = value
+        .split_once(char::is_whitespace)
+        .map(|(name, value)| (name, value.trim()))
+        .ok_or("set: setting and value required")?;
+
+    match (name, value) {
+        ("respawn", "off") => Ok(Setting::Respawn(None)),
+        ("respawn", ticks) => ticks
+            .parse()
+            .map(|ticks| Setting::Respawn(Some(ticks)))
+            .map_err(|_| format!("set: invalid ticks {ticks:?}")),
+        ("join", "on") => Ok(Setting::MidGameJoin(true)),
+        ("join", "off") => Ok(Setting::MidGameJoin(false)),
+        ("join", other) => Err(format!("set: expected on or off, got {other:?}")),
+        ("teams", "off") => Ok(Setting::Teams(None)),
+        ("teams", count) => count
+            .parse()
+            .map(|count| Setting::Teams(Some(count)))
+            .map_err(|_| format!("set: invalid team count {count:?}")),
+        ("teammates", "pass") => Ok(Setting::TeammateCollision(TeammateCollision::PassThrough)),
+        ("teammates", "block") => Ok(Setting::TeammateCollision(TeammateCollision::Block)),
+        ("teammates", other) => Err(format!("set: expected pass or block, got {other:?}")),
+        ("shrinking", "on") => Ok(Setting::Shrinking(true)),
+        ("shrinking", "off") => Ok(Setting::Shrinking(false)),
+        ("shrinking", other) => Err(format!("set: expected on or off, got {other:?}")),
+        ("hill", "off") => Ok(Setting::Hill(None)),
+        ("hill", points) => points
+            .parse()
+            .map(|points| Setting::Hill(Some(points)))
+            .map_err(|_| format!("set: invalid points {points:?}")),
+        ("timed", "off") => Ok(Setting::Timed(None)),
+        ("timed", ticks) => ticks
+            .parse()
+            .map(|ticks| Setting::Timed(Some(ticks)))
+            .map_err(|_| format!("set: invalid ticks {ticks:?}")),
+        ("ranking", "length") => Ok(Setting::Ranking(TimedRanking::Length)),
+        ("ranking", "score") => Ok(Setting::Ranking(TimedRanking::Score)),
+        ("ranking", other) => Err(format!("set: expected length or score, got {other:?}")),
+        ("portals", "on") => Ok(Setting::Portals(true)),
+        ("portals", "off") => Ok(Setting::Portals(false)),
+        ("portals", other) => Err(format!("set: expected on or off, got {other:?}")),
+        ("series", "off") => Ok(Setting::Series(None)),
+        ("series", rounds) => rounds
+            .parse()
+            .map(|rounds| Setting::Series(Some(rounds)))
+            .map_err(|_| format!("set: invalid rounds {rounds:?}")),
+        ("powerups", "on") => Ok(Setting::PowerUps(true)),
+        ("powerups", "off") => Ok(Setting::PowerUps(false)),
+        ("powerups", other) => Err(format!("set: expected on or off, got {other:?}")),
+        ("figures", "classic") => Ok(Setting::Figures(FigureSet::Classic)),
+        ("figures", "shapes") => Ok(Setting::Figures(FigureSet::Shapes)),
+        ("figures", "singles") => Ok(Setting::Figures(FigureSet::Singles)),
+        ("figures", other) => Err(format!(
+            "set: expected classic, shapes or singles, got {other:?}"
+        )),
+        ("lifetime", "off") => Ok(Setting::FoodLifetime(None)),
+        ("lifetime", ticks) => ticks
+            .parse()
+            .map(|ticks| Setting::FoodLifetime(Some(ticks)))
+            .map_err(|_| format!("set: invalid ticks {ticks:?}")),
+        ("flee", "off") => Ok(Setting::FoodFlee(None)),
+        ("flee", ticks) => ticks
+            .parse()
+            .map(|ticks| Setting::FoodFlee(Some(ticks)))
+            .map_err(|_| format!("set: invalid ticks {ticks:?}")),
+        ("tick", "default") => Ok(Setting::Tick(None)),
+        ("tick", ms) => ms
+            .parse()
+            .map(|ms| Setting::Tick(Some(ms)))
+            .map_err(|_| format!("set: invalid milliseconds {ms:?}")),
+        ("boost", "off") => Ok(Setting::Boost(None)),
+        ("boost", ticks) => ticks
+            .parse()
+            .map(|shed_every| Setting::Boost(Some(shed_every)))
+            .map_err(|_| format!("set: invalid ticks {ticks:?}")),
+        (other, _) => Err(format!("set: unknown setting {other:?}")),
+    }
+}
+
+pub fn power_up_name(power_up: domain::PowerUp) -> &'static str {
+    match power_up {
+        domain::PowerUp::Ghost => "ghost",
+        domain::PowerUp::Shield => "shield",
+        domain::PowerUp::Magnet => "magnet",
+    }
+}
+
+fn parse_direction(value: &str) -> Result<domain::Direction, String> {
+    match value {
+        "up" => Ok(domain::Direction::Up),
+        "down" => Ok(domain::Direction::Bottom),
+        "left" => Ok(domain::Direction::Left),
+        "right" => Ok(domain::Direction::Right),
+        other => Err(format!("dir: invalid direction {other:?}")),
+    }
+}
+
+pub struct App {
+    pub user_name: Option<UserName>,
+    pub lobby_list: Option<LobbyList>,
+    pub joined_lobby_name: Option<LobbyName>,
+    pub joined_lobby_state: Option<LobbyState>,
+    pub log: VecDeque<String>,
+
+    sink: mpsc::UnboundedSender<ClientMsg>,
+    acknowledgeable_messages: HashMap<MsgId, WsClientMsg>,
+    msg_counter: u64,
+}
+
+impl App {
+    pub fn new(sink: mpsc::UnboundedSender<ClientMsg>) -> Self {
+        Self {
+            user_name: None,
+            lobby_list: None,
+            joined_lobby_name: None,
+            joined_lobby_state: None,
+            log: Default::default(),
+
+            sink,
+            acknowledgeable_messages: Default::default(),
+            msg_counter: 0,
+        }
+    }
+
+    // snakes can be steered, also during the start countdown
+    pub fn running(&self) -> bool {
+        matches!(
+            self.joined_lobby_state,
+            Some(LobbyState::Countdown(_) | LobbyState::Running(_))
+        )
+    }
+
+    /// Send turn for the tick after the last received one
+    pub fn turn(&mut self, direction: domain::Direction) -> Result<(), String> {
+        let counter = match &self.joined_lobby_state {
+            Some(LobbyState::Countdown(c)) => c.running.counter,
+            Some(LobbyState::Running(s)) => s.counter,
+            _ => return Err("dir: no game is running".into()),
+        };
+
+        self.send(WsClientMsg::SetDirection(direction, counter + 1))
+    }
+
+    /// Send lobby settings with one setting changed
+    pub fn set(&mut self, setting: Setting) -> Result<(), String> {
+        let Some(LobbyState::Prep(prep)) = &self.joined_lobby_state else {
+            return Err("set: settings can be changed only before the game".into());
+        };
+
+        let mut settings = prep.settings.clone();
+        match setting {
+            Setting::Respawn(cooldown) => settings.respawn_cooldown = cooldown,
+            Setting::MidGameJoin(allow) => settings.mid_game_join = allow,
+            Setting::Teams(None) => settings.teams = None,
+            Setting::Teams(Some(count)) => {
+                settings.teams = Some(TeamSettings {
+                    count,
+                    teammate_collision: settings
+                        .teams
+                        .map(|teams| teams.teammate_collision)
+                        .unwrap_or(TeammateCollision::PassThrough),
+                })
+            }
+            Setting::TeammateCollision(teammate_collision) => match &mut settings.teams {
+                Some(teams) => teams.teammate_collision = teammate_collision,
+                None => return Err("set: enable teams first".into()),
+            },
+            Setting::Shrinking(on) => settings.shrinking = on.then(Default::default),
+            Setting::Hill(target_score) => {
+                settings.hill = target_score.map(|target_score| HillSettings {
+                    target_score,
+                    ..Default::default()
+                })
+            }
+            Setting::Timed(None) => settings.timed = None,
+            Setting::Timed(Some(ticks)) => {
+                settings.timed = Some(TimedSettings {
+                    ticks,
+                    ..settings.timed.unwrap_or_default()
+                })
+            }
+            Setting::Ranking(ranking) => match &mut settings.timed {
+                Some(timed) => timed.ranking = ranking,
+                None => return Err("set: enable timed mode first".into()),
+            },
+            Setting::Portals(on) => settings.portals = on,
+            Setting::PowerUps(on) => settings.power_ups = on,
+            Setting::Series(None) => settings.series = None,
+            Setting::Series(Some(rounds)) => {
+                settings.series = Some(SeriesSettings {
+                    rounds,
+                    ..settings.series.unwrap_or_default()
+                })
+            }
+            Setting::Figures(figures) => settings.figures = figures,
+            Setting::FoodLifetime(ticks) => settings.food_lifetime = ticks,
+            Setting::FoodFlee(ticks) => settings.food_flee_every = ticks,
+            Setting::Tick(ms) => settings.tick_ms = ms,
+            Setting::Boost(shed_every) => {
+                settings.boost = shed_every.map(|shed_every| BoostSettings { shed_every })
+            }
+        }
+
+        self.send(WsClientMsg::SetLobbySettings(settings))
+    }
+
+    pub fn log(&mut self, line: impl Into<String>) {
+        if self.log.len() == LOG_CAPACITY {
+            self.log.pop_front();
+        }
+        self.log.push_back(line.into());
+    }
+
+    /// Send message, attaching an id to messages which expect a response
+    pub fn send(&mut self, msg: WsClientMsg) -> Result<(), String> {
+        let msg = match msg {
+            // server does not respond to these
+            msg @ (WsClientMsg::SetDirection(..) | WsClientMsg::SetBoost(_)) => WsMsg::new(msg),
+            msg => {
+                self.msg_counter += 1;
+                let id = self.msg_counter.to_string();
+                self.acknowledgeable_messages
+                    .insert(id.clone(), msg.clone());
+                WsMsg::new(msg).id(id)
+            }
+        };
+
+        self.sink
+            .send(msg)
+            .map_err(|_| "connection closed".to_owned())
+    }
+
+    pub fn player_name(&self, id: domain::SnakeId) -> String {
+        match &self.joined_lobby_state {
+            Some(LobbyState::Countdown(c)) => c.running.player(id).map(|p| p.user_name.clone()),
+            Some(LobbyState::Running(s)) => s.player(id).map(|p| p.user_name.clone()),
+            _ => None,
+        }
+        .unwrap_or_else(|| format!("player {id}"))
+    }
+
+    pub fn winner_text(&self, winner: Option<domain::Winner>) -> String {
+        match winner {
+            Some(winner) => format!("{} wins", self.winner_name(winner)),
+            None => "nobody wins".into(),
+        }
+    }
+
+    pub fn winner_name(&self, winner: domain::Winner) -> String {
+        match winner {
+            domain::Winner::Team(team) => format!("team {team}"),
+            domain::Winner::Player(id) => self.player_name(id),
+        }
+    }
+
+    // kill feed line, spawns are not worth logging
+    fn describe_event(&self, event: &domain::GameEvent) -> Option<String> {
+        use domain::{DeathCause, GameEvent};
+
+        let line = match event {
+            GameEvent::GameStarted => "game started".into(),
+            GameEvent::SnakeSpawned { .. } | GameEvent::FoodEaten { .. } => return None,
+            GameEvent::SnakeDied {
+                victim,
+                cause,
+                killer,
+            } => {
+                let victim = self.player_name(*victim);
+                match (cause, killer) {
+                    (_, Some(killer)) => format!("{} killed {victim}", self.player_name(*killer)),
+                    (DeathCause::BitYaSelf, None) => format!("{victim} bit themselves"),
+                    (DeathCause::OutOfBounds, None) => format!("{victim} hit the wall"),
+                    (DeathCause::Left, None) => format!("{victim} left"),
+                    (DeathCause::BitSomeone, None) => format!("{victim} died"),
+                }
+            }
+            GameEvent::PowerUpPicked { id, power_up } => format!(
+                "{} picked up {}",
+                self.player_name(*id),
+                power_up_name(*power_up)
+            ),
+            GameEvent::GameEnded { winner } => format!("game over, {}", self.winner_text(*winner)),
+        };
+
+        Some(line)
+    }
+
+    pub fn handle_received_message(&mut self, msg: ServerMsg) {
+        match msg {
+            WsMsg(Some(id), msg) => {
+                let Some(ack_msg) = self.acknowledgeable_messages.remove(&id) else {
+                    self.log(format!("dismiss response: {id} {msg:?}"));
+                    return;
+                };
+
+                match (ack_msg, msg) {
+                    (WsClientMsg::CreateLobby(lobby_name), WsServerMsg::Ack) => {
+                        self.log(format!("created lobby {lobby_name}"));
+                        // like frontend, enter created lobby right away
+                        self.send(WsClientMsg::JoinLobby(lobby_name))
+                            .unwrap_or_else(|e| self.log(e));
+                    }
+
+                    (WsClientMsg::LobbyList, WsServerMsg::LobbyList(lobby_list)) => {
+                        self.lobby_list.replace(lobby_list);
+                    }
+
+                    (WsClientMsg::UserName, WsServerMsg::UserName(user_name)) => {
+                        self.user_name = user_name;
+                    }
+
+                    (WsClientMsg::SetUserName(user_name), WsServerMsg::Ack) => {
+                        self.log(format!("user name set to {user_name}"));
+                        self.user_name = Some(user_name);
+                    }
+
+                    (WsClientMsg::JoinLobby(lobby_name), WsServerMsg::LobbyState(s)) => {
+                        self.log(format!("joined {lobby_name}"));
+                        self.joined_lobby_name = Some(lobby_name);
+                        self.joined_lobby_state = Some(s);
+                    }
+
+                    (WsClientMsg::JoinLobby(lobby_name), WsServerMsg::JoinLobbyDecline(r)) => {
+                        let reason = match r {
+                            JoinLobbyDecline::AlreadyJoined(ln) => format!("already joined {ln}"),
+                            JoinLobbyDecline::NotFound => "not found".into(),
+                            JoinLobbyDecline::UserNameNotSet => "set user name first".into(),
+                            JoinLobbyDecline::AlreadyStarted => "already started".into(),
+                        };
+                        self.log(format!("cannot join {lobby_name}: {reason}"));
+                    }
+
+                    (WsClientMsg::VoteStart(_), WsServerMsg::LobbyState(s))
+                    | (WsClientMsg::SetLobbySettings(_), WsServerMsg::LobbyState(s))
+                    | (WsClientMsg::Respawn, WsServerMsg::LobbyState(s))
+                    | (WsClientMsg::PickTeam(_), WsServerMsg::LobbyState(s)) => {
+                        self.joined_lobby_state = Some(s);
+                    }
+
+                    (WsClientMsg::LeaveLobby, WsServerMsg::Ack) => {
+                        self.joined_lobby_name = None;
+                        self.joined_lobby_state = None;
+                        self.log("left lobby");
+                    }
+
+                    (req, WsServerMsg::Err(m)) => self.log(format!("{req:?} failed: {m}")),
+
+                    (req, res) => self.log(format!("{req:?}: {res:?}")),
+                }
+            }
+
+            WsMsg(None, WsServerMsg::LobbyState(s)) => {
+                if self.joined_lobby_name.is_some() {
+                    self.joined_lobby_state = Some(s);
+                }
+            }
+
+            WsMsg(None, WsServerMsg::GameEvents(events)) => {
+                for event in events.events {
+                    if let Some(line) = self.describe_event(&event) {
+                        self.log(line);
+                    }
+                }
+            }
+
+            WsMsg(None, WsServerMsg::Maintenance(secs)) => {
+                self.log(format!("server is going down for maintenance in {secs}s"))
+            }
+
+            WsMsg(None, msg) => self.log(format!("unexpected message: {msg:?}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_commands() {
+        use WsClientMsg::*;
+
+        assert_eq!(
+            "join my lobby".parse(),
+            Ok(Command::Send(JoinLobby("my lobby".into())))
+        );
+        assert_eq!("vote".parse(), Ok(Command::Send(VoteStart(true))));
+        assert_eq!(
+            " dir down ".parse(),
+            Ok(Command::Turn(domain::Direction::Bottom))
+        );
+        assert_eq!("sleep 300".parse(), Ok(Command::Sleep(300)));
+        assert_eq!("boost on".parse(), Ok(Command::Send(SetBoost(true))));
+        assert_eq!(
+            "set boost 3".parse(),
+            Ok(Command::Set(Setting::Boost(Some(3))))
+        );
+        assert_eq!(
+            "set respawn 10".parse(),
+            Ok(Command::Set(Setting::Respawn(Some(10))))
+        );
+        assert_eq!(
+            "set respawn off".parse(),
+            Ok(Command::Set(Setting::Respawn(None)))
+        );
+        assert!("set respawn".parse::<Command>().is_err());
+        assert_eq!("team 2".parse(), Ok(Command::Send(PickTeam(2))));
+        assert_eq!(
+            "set teammates block".parse(),
+            Ok(Command::Set(Setting::TeammateCollision(
+                TeammateCollision::Block
+            )))
+        );
+        assert!("join".parse::<Command>().is_err());
+        assert!("dir sideways".parse::<Command>().is_err());
+        assert!("fly".parse::<Command>().is_err());
+    }
+}
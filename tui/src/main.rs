@@ -0,0 +1,192 @@
+// Terminal client for multiplayer
+//
+// Usage: tui [url]
+//
+// url defaults to TUI__URL env var, then to local backend
+//
+// When stdin is a terminal, runs interactively.
+// Otherwise reads commands line by line from stdin and prints every
+// received server message as json, which allows scripting clients:
+//
+//     printf 'create test\nvote\nsleep 5000\n' | tui
+//
+
+mod app;
+mod render;
+mod ws;
+
+use app::{App, Command};
+use crossterm::{
+    cursor,
+    event::{Event, EventStream, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute, terminal,
+};
+use futures_util::StreamExt;
+use interfacing::snake::{WsClientMsg, WsMsg};
+use interfacing::snake_domain as domain;
+use std::io::IsTerminal;
+use tokio::io::AsyncBufReadExt;
+
+type ClientMsg = WsMsg<interfacing::snake::WsClientMsg>;
+type ServerMsg = WsMsg<interfacing::snake::WsServerMsg>;
+
+#[tokio::main]
+async fn main() -> Result<(), String> {
+    let url = std::env::args()
+        .nth(1)
+        .or_else(|| std::env::var("TUI__URL").ok())
+        .unwrap_or_else(|| ws::DEFAULT_URL.into());
+
+    let con = ws::connect(&url).await?;
+
+    if std::io::stdin().is_terminal() {
+        interactive(con).await
+    } else {
+        script(con).await
+    }
+}
+
+async fn script(ws::Connection { sink, mut stream }: ws::Connection) -> Result<(), String> {
+    let mut app = App::new(sink);
+    let mut lines = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+    // keep receiving messages after input ends, until quit or disconnect
+    let mut input_closed = false;
+    // input is not read while sleeping, messages are
+    let sleep = tokio::time::sleep(std::time::Duration::ZERO);
+    tokio::pin!(sleep);
+
+    loop {
+        tokio::select! {
+            () = &mut sleep, if !sleep.is_elapsed() => {}
+
+            line = lines.next_line(), if !input_closed && sleep.is_elapsed() => {
+                let Some(line) = line.map_err(|e| e.to_string())? else {
+                    input_closed = true;
+                    continue;
+                };
+
+                if line.trim().is_empty() {
+                    continue;
+                }
+
+                match line.parse::<Command>()? {
+                    Command::Send(msg) => app.send(msg)?,
+                    Command::Set(setting) => app.set(setting)?,
+                    Command::Turn(direction) => app.turn(direction)?,
+                    Command::Sleep(ms) => sleep
+                        .as_mut()
+                        .reset(tokio::time::Instant::now() + std::time::Duration::from_millis(ms)),
+                    Command::Help => eprintln!("{}", app::HELP),
+                    Command::Quit => return Ok(()),
+                }
+            }
+
+            msg = stream.recv() => {
+                let Some(msg) = msg else {
+                    return Err("connection closed".into());
+                };
+                println!("{}", serde_json::to_string(&msg).unwrap());
+                app.handle_received_message(msg);
+            }
+        }
+    }
+}
+
+// restores terminal on drop, including on error
+struct RawTerminal;
+
+impl RawTerminal {
+    fn enter() -> std::io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(
+            std::io::stdout(),
+            terminal::EnterAlternateScreen,
+            cursor::Hide
+        )?;
+        Ok(Self)
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        execute!(
+            std::io::stdout(),
+            cursor::Show,
+            terminal::LeaveAlternateScreen
+        )
+        .unwrap_or(());
+        terminal::disable_raw_mode().unwrap_or(());
+    }
+}
+
+async fn interactive(ws::Connection { sink, mut stream }: ws::Connection) -> Result<(), String> {
+    let _terminal = RawTerminal::enter().map_err(|e| e.to_string())?;
+
+    let mut app = App::new(sink);
+    let mut input = String::new();
+    let mut events = EventStream::new();
+    let mut out = std::io::stdout();
+
+    // sync user name, since server may have generated one
+    app.send(WsClientMsg::UserName)?;
+    app.send(WsClientMsg::LobbyList)?;
+
+    loop {
+        render::draw(&mut out, &app, &input).map_err(|e| e.to_string())?;
+
+        tokio::select! {
+            msg = stream.recv() => {
+                let Some(msg) = msg else {
+                    return Err("connection closed".into());
+                };
+                app.handle_received_message(msg);
+            }
+
+            event = events.next() => {
+                let Some(Ok(Event::Key(KeyEvent { code, modifiers, kind: KeyEventKind::Press, .. }))) = event else {
+                    continue;
+                };
+
+                let direction = match code {
+                    KeyCode::Up => Some(domain::Direction::Up),
+                    KeyCode::Down => Some(domain::Direction::Bottom),
+                    KeyCode::Left => Some(domain::Direction::Left),
+                    KeyCode::Right => Some(domain::Direction::Right),
+                    _ => None,
+                };
+
+                match (code, direction) {
+                    (_, Some(direction)) => {
+                        if app.running() {
+                            app.turn(direction)?;
+                        }
+                    }
+                    (KeyCode::Char('c'), _) if modifiers.contains(KeyModifiers::CONTROL) => {
+                        return Ok(());
+                    }
+                    (KeyCode::Char(c), _) => input.push(c),
+                    (KeyCode::Backspace, _) => {
+                        input.pop();
+                    }
+                    (KeyCode::Enter, _) => {
+                        let line = std::mem::take(&mut input);
+                        if line.trim().is_empty() {
+                            continue;
+                        }
+
+                        match line.parse::<Command>() {
+                            Ok(Command::Send(msg)) => app.send(msg)?,
+                            Ok(Command::Set(setting)) => app.set(setting).unwrap_or_else(|e| app.log(e)),
+                            Ok(Command::Turn(direction)) => app.turn(direction).unwrap_or_else(|e| app.log(e)),
+                            Ok(Command::Sleep(_)) => app.log("sleep is for scripts"),
+                            Ok(Command::Help) => app.log(app::HELP),
+                            Ok(Command::Quit) => return Ok(()),
+                            Err(e) => app.log(e),
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
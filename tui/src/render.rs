@@ -0,0 +1,485 @@
+// Drawing client state to terminal
+//
+
+use crossterm::{
+    cursor, queue,
+    style::Print,
+    terminal::{self, ClearType},
+};
+use interfacing::snake::lobby_state::{
+    GameOver, LobbyPrep, LobbyRunning, TeammateCollision, TimedRanking,
+};
+use interfacing::snake::LobbyState;
+use interfacing::snake_domain::{self as domain, figures::FigureSet, Domain};
+use std::io::Write;
+
+use crate::app::App;
+
+const BOUNDARY: char = '#';
+const FOOD: char = '*';
+// about to expire
+const FADING_FOOD: char = '+';
+const EMPTY: char = ' ';
+const DANGER: char = '.';
+const ZONE: char = ':';
+const PORTAL: char = 'O';
+const GHOST: char = 'G';
+const SHIELD: char = 'S';
+const MAGNET: char = 'M';
+// controlled snake
+const MOUTH: char = '@';
+const BODY: char = 'o';
+// other snakes
+const OTHER_MOUTH: char = 'X';
+const OTHER_BODY: char = 'x';
+
+/// Render domain row by row, one char per position
+///
+/// Boundaries are inclusive, snakes are drawn over foods, portals and pickups
+pub fn render_domain(domain: &Domain) -> Vec<String> {
+    let b = domain.boundaries;
+    let width = (b.max.x - b.min.x + 1) as usize;
+    let height = (b.max.y - b.min.y + 1) as usize;
+
+    let mut grid = vec![vec![EMPTY; width]; height];
+
+    let mut put = |pos: domain::Pos, c: char| {
+        if b.relation(pos).is_outside() {
+            return;
+        }
+        grid[(pos.y - b.min.y) as usize][(pos.x - b.min.x) as usize] = c;
+    };
+
+    for x in b.min.x..=b.max.x {
+        put(domain::Pos::new(x, b.min.y), BOUNDARY);
+        put(domain::Pos::new(x, b.max.y), BOUNDARY);
+    }
+
+    for y in b.min.y..=b.max.y {
+        put(domain::Pos::new(b.min.x, y), BOUNDARY);
+        put(domain::Pos::new(b.max.x, y), BOUNDARY);
+    }
+
+    for food in domain.foods.iter() {
+        put(
+            food.pos(),
+            match food.freshness() < 1. {
+                true => FADING_FOOD,
+                false => FOOD,
+            },
+        );
+    }
+
+    for portal in domain.portals.iter() {
+        put(portal.a, PORTAL);
+        put(portal.b, PORTAL);
+    }
+
+    for pickup in domain.pickups.iter() {
+        let c = match pickup.power_up {
+            domain::PowerUp::Ghost => GHOST,
+            domain::PowerUp::Shield => SHIELD,
+            domain::PowerUp::Magnet => MAGNET,
+        };
+        put(pickup.pos, c);
+    }
+
+    let snakes = domain
+        .other_snakes
+        .iter()
+        .map(|snake| (snake, OTHER_BODY, OTHER_MOUTH))
+        .chain(domain.snake.iter().map(|snake| (snake, BODY, MOUTH)));
+
+    for (snake, body, mouth) in snakes {
+        for pos in snake.iter_vertices() {
+            put(pos, body);
+        }
+        put(snake.mouth(), mouth);
+    }
+
+    grid.into_iter()
+        .map(|row| row.into_iter().collect())
+        .collect()
+}
+
+// replace empty cells matching the predicate,
+// like the ones outside of upcoming boundaries
+fn mark_empty(
+    rows: &mut [String],
+    b: &domain::Boundaries,
+    mark: char,
+    predicate: impl Fn(domain::Pos) -> bool,
+) {
+    for (y, row) in (b.min.y..).zip(rows.iter_mut()) {
+        *row = (b.min.x..)
+            .zip(row.chars())
+            .map(|(x, c)| {
+                if c == EMPTY && predicate(domain::Pos::new(x, y)) {
+                    mark
+                } else {
+                    c
+                }
+            })
+            .collect();
+    }
+}
+
+// double horizontally, so that cells look square
+fn square(row: &str) -> String {
+    row.chars()
+        .flat_map(|c| [c, EMPTY])
+        .collect::<String>()
+        .trim_end()
+        .to_owned()
+}
+
+fn lines(app: &App) -> Vec<String> {
+    let mut lines = vec![format!(
+        "user: {}    lobby: {}",
+        app.user_name.as_deref().unwrap_or("-"),
+        app.joined_lobby_name.as_deref().unwrap_or("-"),
+    )];
+    lines.push(String::new());
+
+    match &app.joined_lobby_state {
+        None => {
+            lines.push("Lobbies:".into());
+            match &app.lobby_list {
+                None => lines.push("  type list to load".into()),
+                Some(lobbies) if lobbies.is_empty() => lines.push("  none".into()),
+                Some(lobbies) => {
+                    lines.extend(lobbies.iter().map(|lobby| format!("  {}", lobby.name)))
+                }
+            }
+        }
+        Some(LobbyState::Prep(LobbyPrep {
+            participants,
+            settings,
+        })) => {
+            lines.push(format!(
+                "respawn: {}",
+                match settings.respawn_cooldown {
+                    Some(ticks) => format!("after {ticks} ticks"),
+                    None => "off".into(),
+                }
+            ));
+            lines.push(format!(
+                "mid-game join: {}",
+                if settings.mid_game_join { "on" } else { "off" }
+            ));
+            lines.push(format!(
+                "teams: {}",
+                match &settings.teams {
+                    Some(teams) => format!(
+                        "{}, teammates {}",
+                        teams.count,
+                        match teams.teammate_collision {
+                            TeammateCollision::PassThrough => "pass through",
+                            TeammateCollision::Block => "block",
+                        }
+                    ),
+                    None => "off".into(),
+                }
+            ));
+            lines.push(format!(
+                "shrinking arena: {}",
+                if settings.shrinking.is_some() {
+                    "on"
+                } else {
+                    "off"
+                }
+            ));
+            lines.push(format!(
+                "king of the hill: {}",
+                match &settings.hill {
+                    Some(hill) => format!(
+                        "{} points or leader after {} ticks",
+                        hill.target_score, hill.time_limit
+                    ),
+                    None => "off".into(),
+                }
+            ));
+            lines.push(format!(
+                "timed: {}",
+                match &settings.timed {
+                    Some(timed) => format!(
+                        "{} ticks, {} wins",
+                        timed.ticks,
+                        match timed.ranking {
+                            TimedRanking::Length => "longest snake",
+                            TimedRanking::Score => "best score",
+                        }
+                    ),
+                    None => "off".into(),
+                }
+            ));
+            lines.push(format!(
+                "portals: {}",
+                if settings.portals { "on" } else { "off" }
+            ));
+            lines.push(format!(
+                "power-ups: {}",
+                if settings.power_ups { "on" } else { "off" }
+            ));
+            lines.push(format!(
+                "series: {}",
+                match &settings.series {
+                    Some(series) => format!("best of {}", series.rounds),
+                    None => "off".into(),
+                }
+            ));
+            lines.push(format!(
+                "boost: {}",
+                match &settings.boost {
+                    Some(boost) => format!("sheds a cell every {} ticks", boost.shed_every),
+                    None => "off".into(),
+                }
+            ));
+            lines.push(format!(
+                "food figures: {}",
+                match settings.figures {
+                    FigureSet::Classic => "classic",
+                    FigureSet::Shapes => "shapes",
+                    FigureSet::Singles => "singles",
+                }
+            ));
+            lines.push(format!(
+                "food: {}, {}",
+                match settings.food_lifetime {
+                    Some(ticks) => format!("expires after {ticks} ticks"),
+                    None => "never expires".into(),
+                },
+                match settings.food_flee_every {
+                    Some(ticks) => format!("runs every {ticks} ticks"),
+                    None => "stays".into(),
+                }
+            ));
+            lines.push(match settings.tick_ms {
+                Some(ms) => format!("tick: {ms} ms"),
+                None => "tick: default".into(),
+            });
+            lines.push(String::new());
+            lines.push("Participants:".into());
+            lines.extend(participants.iter().map(|p| {
+                format!(
+                    "  [{}] {}{}",
+                    if p.vote_start { "x" } else { " " },
+                    p.user_name,
+                    p.team
+                        .map(|team| format!("    team {team}"))
+                        .unwrap_or_default(),
+                )
+            }));
+        }
+        Some(LobbyState::Countdown(countdown)) => {
+            lines.push(format!(
+                "starting in {}, type unvote to cancel",
+                countdown.seconds_left
+            ));
+            lines.extend(
+                render_domain(&countdown.running.domain)
+                    .iter()
+                    .map(|row| square(row)),
+            );
+        }
+        Some(LobbyState::Running(running)) => {
+            let LobbyRunning {
+                counter,
+                player_counter,
+                domain,
+                scoreboard,
+                respawn_in,
+                teams,
+                game_over,
+                upcoming_boundaries,
+                zones,
+                ticks_left,
+                series,
+            } = running.as_ref();
+            lines.push(format!(
+                "tick: {counter}    players: {player_counter}{}",
+                match (&domain.snake, respawn_in) {
+                    _ if game_over.is_some() => String::new(),
+                    (Some(_), _) => String::new(),
+                    (None, None) => "    you died, spectating".into(),
+                    (None, Some(0)) => "    you died, type respawn".into(),
+                    (None, Some(ticks)) => format!("    you died, respawn in {ticks}"),
+                }
+            ));
+            let mut rows = render_domain(domain);
+            if let Some(upcoming) = upcoming_boundaries {
+                lines.push(format!("arena shrinks in {}", upcoming.in_ticks));
+                mark_empty(&mut rows, &domain.boundaries, DANGER, |pos| {
+                    !upcoming.boundaries.relation(pos).is_inside()
+                });
+            }
+            if let Some(ticks) = ticks_left {
+                lines.push(format!("time limit in {ticks}"));
+            }
+            if let Some(snake) = domain.snake.as_ref().filter(|s| !s.effects.is_empty()) {
+                let effects = &snake.effects;
+                let active = effects
+                    .active()
+                    .into_iter()
+                    .map(|power_up| match power_up {
+                        domain::PowerUp::Ghost => format!("ghost {}", effects.ghost),
+                        domain::PowerUp::Shield => "shield".into(),
+                        domain::PowerUp::Magnet => format!("magnet {}", effects.magnet),
+                    })
+                    .collect::<Vec<_>>();
+                lines.push(format!("power-ups: {}", active.join(", ")));
+            }
+            mark_empty(&mut rows, &domain.boundaries, ZONE, |pos| {
+                zones.iter().any(|zone| zone.contains(pos))
+            });
+            lines.extend(rows.iter().map(|row| square(row)));
+            lines.push(String::new());
+            if let Some(GameOver { winner, standings }) = game_over {
+                match series {
+                    Some(series) if series.ended => {
+                        lines.push(format!("Series over, {}", app.winner_text(series.winner)))
+                    }
+                    Some(series) => lines.push(format!(
+                        "Round {} over, {}",
+                        series.round,
+                        app.winner_text(*winner)
+                    )),
+                    None => lines.push(format!("Game over, {}", app.winner_text(*winner))),
+                }
+                match series {
+                    Some(series) => lines.extend(series.standings.iter().map(|standing| {
+                        format!(
+                            "  {}: {} round wins",
+                            app.winner_name(standing.winner),
+                            standing.wins
+                        )
+                    })),
+                    None => lines.extend(
+                        standings
+                            .iter()
+                            .enumerate()
+                            .map(|(i, id)| format!("  {}. {}", i + 1, app.player_name(*id))),
+                    ),
+                }
+                if let Some(ticks) = series.as_ref().and_then(|series| series.next_round_in) {
+                    lines.push(format!("next round in {ticks}"));
+                }
+                lines.push(String::new());
+            } else if let Some(series) = series {
+                lines.push(format!("round {} of {}", series.round, series.rounds));
+            }
+            if !teams.is_empty() {
+                lines.push(format!("  {:<20} {:>6}", "team", "score"));
+                lines.extend(teams.iter().map(|team| {
+                    format!(
+                        "{} {:<20} {:>6}",
+                        if team.alive { ' ' } else { '+' },
+                        format!("team {}", team.id),
+                        team.score,
+                    )
+                }));
+                lines.push(String::new());
+            }
+            let hill = !zones.is_empty();
+            lines.push(format!(
+                "  {:<20} {:>6} {:>6} {:>6} {:>6}{}",
+                "player",
+                "score",
+                "length",
+                "kills",
+                "lag",
+                if hill { "   zone" } else { "" },
+            ));
+            lines.extend(scoreboard.iter().map(|player| {
+                format!(
+                    "{} {:<20} {:>6} {:>6} {:>6} {:>6.1}{}",
+                    // dead or boosting
+                    match (player.alive, player.boosting) {
+                        (false, _) => '+',
+                        (true, true) => '>',
+                        (true, false) => ' ',
+                    },
+                    player.user_name,
+                    player.score.score,
+                    player.score.length,
+                    player.score.kills,
+                    // average ticks turns arrive late
+                    player.input_delay.average(),
+                    if hill {
+                        format!(" {:>6}", player.score.zone_points)
+                    } else {
+                        String::new()
+                    },
+                )
+            }));
+        }
+        Some(LobbyState::Terminated) => lines.push("Lobby terminated".into()),
+    }
+
+    lines.push(String::new());
+    lines.extend(app.log.iter().cloned());
+
+    lines
+}
+
+pub fn draw(out: &mut impl Write, app: &App, input: &str) -> std::io::Result<()> {
+    queue!(out, cursor::MoveTo(0, 0), terminal::Clear(ClearType::All))?;
+
+    let lines = lines(app);
+    for (i, line) in lines.iter().enumerate() {
+        queue!(out, cursor::MoveTo(0, i as u16), Print(line))?;
+    }
+
+    let hint = if app.running() {
+        "arrows to steer, "
+    } else {
+        ""
+    };
+    queue!(
+        out,
+        cursor::MoveTo(0, lines.len() as u16 + 1),
+        Print(format!(
+            "({hint}help for commands, ctrl-c to quit) > {input}"
+        )),
+    )?;
+
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render() {
+        let snake = domain::Snake {
+            sections: domain::Sections::from_directions(
+                domain::Pos::new(1, 3),
+                [domain::Direction::Up, domain::Direction::Right],
+            ),
+            direction: domain::Direction::Right,
+            id: None,
+            effects: Default::default(),
+        };
+
+        let mut foods = domain::Foods::default();
+        foods.insert(domain::Food::new(3, 1));
+
+        let domain = Domain {
+            snake: Some(snake),
+            other_snakes: vec![],
+            foods,
+            boundaries: domain::Boundaries {
+                min: domain::Pos::new(0, 0),
+                max: domain::Pos::new(4, 4),
+            },
+            portals: vec![],
+            pickups: vec![],
+        };
+
+        assert_eq!(
+            render_domain(&domain),
+            vec!["#####", "#  *#", "#o@ #", "#o  #", "#####"]
+        );
+    }
+}
@@ -0,0 +1,58 @@
+// Websocket connection to backend
+//
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::{ClientMsg, ServerMsg};
+
+pub const DEFAULT_URL: &str = "ws://127.0.0.1:8000/api/snake/ws";
+
+pub struct Connection {
+    pub sink: mpsc::UnboundedSender<ClientMsg>,
+    pub stream: mpsc::UnboundedReceiver<ServerMsg>,
+}
+
+/// Connect to backend and spawn read/write tasks
+///
+/// Closed stream means the connection was closed
+pub async fn connect(url: &str) -> Result<Connection, String> {
+    let (ws, _) = tokio_tungstenite::connect_async(url)
+        .await
+        .map_err(|e| format!("cannot connect to {url}: {e}"))?;
+
+    let (mut w_ws, mut r_ws) = ws.split();
+
+    let (sink, mut r) = mpsc::unbounded_channel::<ClientMsg>();
+    let (s, stream) = mpsc::unbounded_channel::<ServerMsg>();
+
+    tokio::spawn(async move {
+        while let Some(msg) = r.recv().await {
+            let msg = Message::Text(serde_json::to_string(&msg).unwrap());
+            if w_ws.send(msg).await.is_err() {
+                return;
+            }
+        }
+    });
+
+    tokio::spawn(async move {
+        while let Some(Ok(msg)) = r_ws.next().await {
+            match msg {
+                Message::Text(text) => match serde_json::from_str::<ServerMsg>(&text) {
+                    Ok(msg) => {
+                        if s.send(msg).is_err() {
+                            return;
+                        }
+                    }
+                    // messages which do not parse are skipped
+                    Err(_) => continue,
+                },
+                Message::Close(_) => return,
+                _ => continue,
+            }
+        }
+    });
+
+    Ok(Connection { sink, stream })
+}